@@ -0,0 +1,774 @@
+use std::collections::HashMap;
+use std::io::{self, BufRead, BufReader, Read, Write};
+use std::net::{Shutdown, TcpListener, TcpStream};
+use std::sync::mpsc::{self, Receiver, Sender};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use crate::book::random_u64;
+use crate::broadcast::{self, Spectate};
+use crate::position::{opponent, Position};
+use crate::variant::{outcome, parse_player_move, Outcome, Variant};
+use crate::websocket::{accept_key, read_frame, write_frame, OPCODE_CLOSE, OPCODE_PING, OPCODE_PONG, OPCODE_TEXT};
+use crate::Color;
+
+// A JSON API over HTTP for dashboards and bots, with a WebSocket stream of each game's events. It
+// listens on the local machine only unless told otherwise, and needs nothing but the standard
+// library, so it can be tried with curl and driven from tests. Each connection is served in its
+// own thread and answers a single request.
+//
+// The routes are:
+//
+// - GET /games: the games, as a list of states
+// - POST /games: start a game, of the variant given as {"variant": "atomic"} or of standard chess.
+//   The answer is the game's state with "tokens": {"white": "...", "black": "..."} added, and each
+//   player acts for their side by giving theirs as {"token": "..."}.
+// - GET /games/<id>: the state of a game
+// - GET /games/<id>/moves: the legal moves, in long algebraic notation
+// - POST /games/<id>/move: play {"move": "e4", "token": "..."}, written as at the terminal, when it
+//   is the token's side to move
+// - POST /games/<id>/resign: {"token": "..."} resigns the game for the token's side
+// - POST /games/<id>/draw: {"token": "..."} offers a draw, or accepts the opponent's offer. An
+//   offer lapses when the opponent moves instead.
+// - GET /games/<id>/events, upgraded to a WebSocket: the game's state, then its events as they
+//   happen: {"type": "move", ...}, {"type": "draw_offer", ...} and {"type": "end", ...}
+// - GET /games/<id>/watch, upgraded to a WebSocket: the same events for spectators, held back by
+//   the spectator delay. It starts with {"type": "watching", ...}, giving the variant, the starting
+//   position and the delay in seconds, followed by every event of the game so far.
+//
+// A game is kept for an hour after it ends, and its WebSockets are closed once the result is out.
+//
+// A state looks like {"id": 1, "variant": "chess", "fen": "...", "turn": "white", "moves":
+// ["e2e4"], "status": "playing", "result": null, "reason": null, "draw_offer": null}, where the
+// result is "white", "black" or "draw" once the game is over. Errors come with a 4xx status and
+// {"error": "..."}.
+
+// The address the API listens on when none is given
+pub const DEFAULT_API_ADDRESS: &str = "127.0.0.1:8080";
+
+// The largest request body accepted
+const MAX_BODY_LENGTH: usize = 1 << 16;
+
+// How long a write to a client may take before their connection is given up as stuck
+const WRITE_TIMEOUT: Duration = Duration::from_secs(10);
+
+// How long a game is kept once it is over
+const KEEP_FINISHED: Duration = Duration::from_secs(60 * 60);
+
+// Where the frames for a WebSocket are queued, to be written in order by the thread that owns it
+type Follower = Sender<(u8, Vec<u8>)>;
+
+// A game driven through the API, with the WebSockets following it by number, and its broadcast to
+// spectators until it ends
+struct ApiGame {
+    variant: Variant,
+    position: Position,
+    moves: Vec<String>,
+    result: Option<Outcome>,
+    ended: Option<Instant>,
+    draw_offer: Option<Color>,
+    tokens: [(Color, String); 2],
+    listeners: Vec<(u32, Follower)>,
+    spectate: Option<Sender<Spectate<Follower>>>,
+}
+
+// The games by number, and the number of the next WebSocket to follow one
+struct Games {
+    games: HashMap<u32, ApiGame>,
+    next_id: u32,
+    next_follower: u32,
+    spectator_delay: Duration,
+}
+
+type SharedGames = Arc<Mutex<Games>>;
+
+// An HTTP request, with the header names in lowercase
+struct Request {
+    method: String,
+    path: String,
+    headers: Vec<(String, String)>,
+    body: String,
+}
+
+impl Request {
+    fn header(&self, name: &str) -> Option<&str> {
+        self.headers.iter().find(|(key, _)| key == name).map(|(_, value)| value.as_str())
+    }
+}
+
+// A failed request: the HTTP status and what went wrong
+type ApiError = (u16, String);
+
+// Write a string as a JSON string, with quotes
+fn json_string(text: &str) -> String {
+    let mut json = String::from("\"");
+    for c in text.chars() {
+        match c {
+            '"' => json.push_str("\\\""),
+            '\\' => json.push_str("\\\\"),
+            '\n' => json.push_str("\\n"),
+            '\r' => json.push_str("\\r"),
+            '\t' => json.push_str("\\t"),
+            c if (c as u32) < 0x20 => json.push_str(&format!("\\u{:04x}", c as u32)),
+            c => json.push(c),
+        }
+    }
+    json.push('"');
+    json
+}
+
+// Write an optional string as a JSON string or null
+fn json_option(text: Option<&str>) -> String {
+    text.map_or("null".to_string(), json_string)
+}
+
+// Read a JSON string, after its opening quote
+fn parse_json_string(chars: &mut std::iter::Peekable<std::str::Chars>) -> Result<String, String> {
+    let mut text = String::new();
+    loop {
+        match chars.next().ok_or("Unterminated string")? {
+            '"' => return Ok(text),
+            '\\' => match chars.next().ok_or("Unterminated string")? {
+                'n' => text.push('\n'),
+                'r' => text.push('\r'),
+                't' => text.push('\t'),
+                'b' => text.push('\u{8}'),
+                'f' => text.push('\u{c}'),
+                'u' => {
+                    let hex: String = chars.by_ref().take(4).collect();
+                    let code = u32::from_str_radix(&hex, 16).map_err(|_| format!("Invalid escape '\\u{}'", hex))?;
+                    text.push(char::from_u32(code).ok_or(format!("Invalid escape '\\u{}'", hex))?);
+                },
+                c => text.push(c),
+            },
+            c => text.push(c),
+        }
+    }
+}
+
+// Read the fields of a flat JSON object such as {"move": "e4"}. Values other than strings are kept
+// as their text, so {"id": 5} gives "5", and an empty body gives no fields.
+fn parse_json_object(text: &str) -> Result<Vec<(String, String)>, String> {
+    let mut fields = Vec::new();
+    let mut chars = text.trim().chars().peekable();
+    if chars.peek().is_none() {
+        return Ok(fields);
+    }
+    let skip_whitespace = |chars: &mut std::iter::Peekable<std::str::Chars>| {
+        while chars.next_if(|c| c.is_whitespace()).is_some() {}
+    };
+
+    if chars.next() != Some('{') {
+        return Err("Expected a JSON object".to_string());
+    }
+    skip_whitespace(&mut chars);
+    if chars.next_if_eq(&'}').is_some() {
+        return Ok(fields);
+    }
+    loop {
+        skip_whitespace(&mut chars);
+        if chars.next() != Some('"') {
+            return Err("Expected a field name".to_string());
+        }
+        let key = parse_json_string(&mut chars)?;
+        skip_whitespace(&mut chars);
+        if chars.next() != Some(':') {
+            return Err(format!("Expected ':' after \"{}\"", key));
+        }
+        skip_whitespace(&mut chars);
+        let value = match chars.peek() {
+            Some('"') => {
+                chars.next();
+                parse_json_string(&mut chars)?
+            },
+            Some('{' | '[') => return Err(format!("The value of \"{}\" cannot be an object or a list", key)),
+            _ => {
+                let value: String = std::iter::from_fn(|| chars.next_if(|&c| c != ',' && c != '}')).collect();
+                value.trim().to_string()
+            },
+        };
+        fields.push((key, value));
+        skip_whitespace(&mut chars);
+        match chars.next() {
+            Some(',') => {},
+            Some('}') => return Ok(fields),
+            _ => return Err("Expected ',' or '}'".to_string()),
+        }
+    }
+}
+
+// Get a field of a request's JSON body
+fn field<'a>(fields: &'a [(String, String)], name: &str) -> Option<&'a str> {
+    fields.iter().find(|(key, _)| key == name).map(|(_, value)| value.as_str())
+}
+
+// Name a color as the API writes it
+fn color_name(color: Color) -> &'static str {
+    match color {
+        Color::White => "white",
+        Color::Black => "black",
+    }
+}
+
+// Make up the token a player acts for their side of a game with
+fn new_token() -> String {
+    format!("{:016x}{:016x}", random_u64(), random_u64())
+}
+
+// Find the color a request acts for from the token it gives
+fn player_color(game: &ApiGame, fields: &[(String, String)]) -> Result<Color, ApiError> {
+    let token = field(fields, "token").ok_or((400, "Give your token, e.g. {\"token\": \"...\"}".to_string()))?;
+    match game.tokens.iter().find(|(_, game_token)| game_token == token) {
+        Some(&(color, _)) => Ok(color),
+        None => Err((403, "That token is not for this game".to_string())),
+    }
+}
+
+// Describe a game as JSON
+fn state_json(id: u32, game: &ApiGame) -> String {
+    let (result, reason) = match game.result {
+        Some(Outcome::Win(color, ref reason)) => (Some(color_name(color)), Some(reason.as_str())),
+        Some(Outcome::Draw(ref reason)) => (Some("draw"), Some(reason.as_str())),
+        None => (None, None),
+    };
+    let moves: Vec<String> = game.moves.iter().map(|mv| json_string(mv)).collect();
+    format!(
+        "{{\"id\": {}, \"variant\": {}, \"fen\": {}, \"turn\": {}, \"moves\": [{}], \"status\": {}, \"result\": {}, \"reason\": {}, \"draw_offer\": {}}}",
+        id,
+        json_string(game.variant.name()),
+        json_string(&game.position.to_fen()),
+        json_string(color_name(game.position.side_to_move)),
+        moves.join(", "),
+        json_string(if game.result.is_some() { "over" } else { "playing" }),
+        json_option(result),
+        json_option(reason),
+        json_option(game.draw_offer.map(color_name)),
+    )
+}
+
+// Describe how a game ended as an event
+fn end_json(result: &Outcome) -> String {
+    let (result, reason) = match result {
+        Outcome::Win(color, reason) => (color_name(*color), reason),
+        Outcome::Draw(reason) => ("draw", reason),
+    };
+    format!("{{\"type\": \"end\", \"result\": {}, \"reason\": {}}}", json_string(result), json_string(reason))
+}
+
+// Send an event to every WebSocket following the game, forgetting those that have gone away, and
+// to the spectators once the delay is over
+fn broadcast(game: &mut ApiGame, event: &str) {
+    game.listeners.retain_mut(|(_, follower)| write_text(follower, event).is_ok());
+    if let Some(ref spectate) = game.spectate {
+        let _ = spectate.send(Spectate::Record(event.to_string()));
+    }
+}
+
+// Queue an event for a WebSocket, which fails once it has closed
+fn write_text(follower: &mut Follower, event: &str) -> io::Result<()> {
+    follower.send((OPCODE_TEXT, event.as_bytes().to_vec())).map_err(|_| io::Error::new(io::ErrorKind::BrokenPipe, "The WebSocket is closed"))
+}
+
+// Write the frames queued for a WebSocket as they come, until everyone queueing them is done with
+// it. A connection that fails or stops reading is shut down, so its reading side notices too.
+fn write_frames(mut stream: TcpStream, frames: Receiver<(u8, Vec<u8>)>) {
+    for (opcode, payload) in frames {
+        if write_frame(&mut stream, opcode, &payload).is_err() {
+            let _ = stream.shutdown(Shutdown::Both);
+            return;
+        }
+        // Nothing may follow the closing frame
+        if opcode == OPCODE_CLOSE {
+            return;
+        }
+    }
+}
+
+// End a game, telling its followers and closing their WebSockets. Its spectators are told once the
+// delay is over, after which their broadcast stops.
+fn finish(game: &mut ApiGame, result: Outcome) {
+    broadcast(game, &end_json(&result));
+    for (_, follower) in game.listeners.drain(..) {
+        let _ = follower.send((OPCODE_CLOSE, Vec::new()));
+    }
+    game.spectate = None;
+    game.result = Some(result);
+    game.ended = Some(Instant::now());
+    game.draw_offer = None;
+}
+
+// Find a game by the number in its path
+fn find_game<'a>(games: &'a mut Games, id: &str) -> Result<(u32, &'a mut ApiGame), ApiError> {
+    let id = id.parse::<u32>().map_err(|_| (404, format!("There is no game {}", id)))?;
+    match games.games.get_mut(&id) {
+        Some(game) => Ok((id, game)),
+        None => Err((404, format!("There is no game {}", id))),
+    }
+}
+
+// Find a game that is still being played
+fn find_live_game<'a>(games: &'a mut Games, id: &str) -> Result<(u32, &'a mut ApiGame), ApiError> {
+    let (id, game) = find_game(games, id)?;
+    if game.result.is_some() {
+        return Err((409, format!("Game {} is over", id)));
+    }
+    Ok((id, game))
+}
+
+// Start a game of the variant the request names
+fn create_game(games: &mut Games, fields: &[(String, String)]) -> Result<String, ApiError> {
+    let variant = match field(fields, "variant") {
+        None => Variant::Standard,
+        Some(name) => Variant::from_name(name)
+            .filter(|&variant| variant != Variant::Bughouse)
+            .ok_or((400, format!("Unknown variant '{}'", name)))?,
+    };
+    // Games that ended long enough ago make way for the new one
+    games.games.retain(|_, game| game.ended.is_none_or(|ended| ended.elapsed() < KEEP_FINISHED));
+    let id = games.next_id;
+    games.next_id += 1;
+    let game = ApiGame {
+        variant,
+        position: Position::with_variant(variant),
+        moves: Vec::new(),
+        result: None,
+        ended: None,
+        draw_offer: None,
+        tokens: [(Color::White, new_token()), (Color::Black, new_token())],
+        listeners: Vec::new(),
+        spectate: Some(broadcast::spawn(games.spectator_delay, write_text)),
+    };
+    // Only the answer to whoever started the game has the tokens, to hand on to the players
+    let state = state_json(id, &game);
+    let state = format!(
+        "{}, \"tokens\": {{\"white\": {}, \"black\": {}}}}}",
+        &state[..state.len() - 1], json_string(&game.tokens[0].1), json_string(&game.tokens[1].1),
+    );
+    games.games.insert(id, game);
+    Ok(state)
+}
+
+// Play a move for the side the request's token is for, when it is their turn
+fn play_move(games: &mut Games, id: &str, fields: &[(String, String)]) -> Result<String, ApiError> {
+    let (id, game) = find_live_game(games, id)?;
+    let color = game.position.side_to_move;
+    if player_color(game, fields)? != color {
+        return Err((409, format!("It is {}'s turn", color_name(color))));
+    }
+    let text = field(fields, "move").ok_or((400, "Give the move, e.g. {\"move\": \"e4\"}".to_string()))?;
+    let mv = parse_player_move(&game.position, text).ok_or((400, format!("Illegal move '{}'", text)))?;
+
+    let played = game.position.move_to_string(&mv);
+    game.position.make_move(&mv);
+    game.moves.push(played.clone());
+    // Moving instead of answering turns the opponent's draw offer down
+    if game.draw_offer == Some(opponent(color)) {
+        game.draw_offer = None;
+    }
+    let event = format!(
+        "{{\"type\": \"move\", \"color\": {}, \"move\": {}, \"fen\": {}}}",
+        json_string(color_name(color)), json_string(&played), json_string(&game.position.to_fen()),
+    );
+    broadcast(game, &event);
+    if let Some(result) = outcome(&game.position) {
+        finish(game, result);
+    }
+    Ok(state_json(id, game))
+}
+
+// Resign the game for the side the request's token is for
+fn resign(games: &mut Games, id: &str, fields: &[(String, String)]) -> Result<String, ApiError> {
+    let (id, game) = find_live_game(games, id)?;
+    let color = player_color(game, fields)?;
+    finish(game, Outcome::Win(opponent(color), format!("{:?} resigned", color)));
+    Ok(state_json(id, game))
+}
+
+// Offer a draw, or agree to one when the opponent has offered it
+fn offer_draw(games: &mut Games, id: &str, fields: &[(String, String)]) -> Result<String, ApiError> {
+    let (id, game) = find_live_game(games, id)?;
+    let color = player_color(game, fields)?;
+    if game.draw_offer == Some(opponent(color)) {
+        finish(game, Outcome::Draw("agreed".to_string()));
+    } else if game.draw_offer.is_none() {
+        game.draw_offer = Some(color);
+        broadcast(game, &format!("{{\"type\": \"draw_offer\", \"color\": {}}}", json_string(color_name(color))));
+    }
+    Ok(state_json(id, game))
+}
+
+// Answer a request, with the status and the JSON body
+fn route(request: &Request, games: &SharedGames) -> Result<(u16, String), ApiError> {
+    let segments: Vec<&str> = request.path.split('?').next().unwrap_or("").split('/').filter(|segment| !segment.is_empty()).collect();
+    let fields = match request.method.as_str() {
+        "POST" => parse_json_object(&request.body).map_err(|error| (400, error))?,
+        _ => Vec::new(),
+    };
+    let mut games = games.lock().unwrap();
+    match (request.method.as_str(), &segments[..]) {
+        ("GET", ["games"]) => {
+            let mut ids: Vec<&u32> = games.games.keys().collect();
+            ids.sort();
+            let states: Vec<String> = ids.into_iter().map(|id| state_json(*id, &games.games[id])).collect();
+            Ok((200, format!("[{}]", states.join(", "))))
+        },
+        ("POST", ["games"]) => create_game(&mut games, &fields).map(|state| (201, state)),
+        ("GET", ["games", id]) => find_game(&mut games, id).map(|(id, game)| (200, state_json(id, game))),
+        ("GET", ["games", id, "moves"]) => {
+            let (_, game) = find_game(&mut games, id)?;
+            let moves: Vec<String> = match game.result {
+                Some(_) => Vec::new(),
+                None => game.position.legal_moves().iter().map(|mv| json_string(&game.position.move_to_string(mv))).collect(),
+            };
+            Ok((200, format!("{{\"moves\": [{}]}}", moves.join(", "))))
+        },
+        ("POST", ["games", id, "move"]) => play_move(&mut games, id, &fields).map(|state| (200, state)),
+        ("POST", ["games", id, "resign"]) => resign(&mut games, id, &fields).map(|state| (200, state)),
+        ("POST", ["games", id, "draw"]) => offer_draw(&mut games, id, &fields).map(|state| (200, state)),
+        (_, ["games"]) | (_, ["games", _]) | (_, ["games", _, "moves" | "move" | "resign" | "draw" | "events" | "watch"]) => {
+            Err((405, format!("{} is not allowed on {}", request.method, request.path)))
+        },
+        _ => Err((404, format!("Nothing at {}", request.path))),
+    }
+}
+
+// Read an HTTP request, the headers and a body as long as its Content-Length
+fn read_request(reader: &mut BufReader<TcpStream>) -> io::Result<Request> {
+    let invalid = |message: &str| io::Error::new(io::ErrorKind::InvalidData, message.to_string());
+    let mut line = String::new();
+    reader.read_line(&mut line)?;
+    let mut words = line.split_whitespace();
+    let (method, path) = match (words.next(), words.next()) {
+        (Some(method), Some(path)) => (method.to_string(), path.to_string()),
+        _ => return Err(invalid("Invalid request line")),
+    };
+
+    let mut headers = Vec::new();
+    loop {
+        let mut line = String::new();
+        if reader.read_line(&mut line)? == 0 || line.trim().is_empty() {
+            break;
+        }
+        if let Some((name, value)) = line.split_once(':') {
+            headers.push((name.trim().to_lowercase(), value.trim().to_string()));
+        }
+    }
+
+    let length = match headers.iter().find(|(name, _)| name == "content-length") {
+        Some((_, value)) => value.parse::<usize>().map_err(|_| invalid("Invalid Content-Length"))?,
+        None => 0,
+    };
+    if length > MAX_BODY_LENGTH {
+        return Err(invalid("Request body too long"));
+    }
+    let mut body = vec![0; length];
+    reader.read_exact(&mut body)?;
+    Ok(Request { method, path, headers, body: String::from_utf8_lossy(&body).into_owned() })
+}
+
+// Write an HTTP response with a JSON body and close the connection
+fn respond(stream: &mut TcpStream, status: u16, body: &str) {
+    let reason = match status {
+        200 => "OK",
+        201 => "Created",
+        400 => "Bad Request",
+        403 => "Forbidden",
+        404 => "Not Found",
+        405 => "Method Not Allowed",
+        409 => "Conflict",
+        _ => "Error",
+    };
+    let response = format!(
+        "HTTP/1.1 {} {}\r\nContent-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+        status, reason, body.len(), body,
+    );
+    let _ = stream.write_all(response.as_bytes());
+}
+
+// Accept a WebSocket for a game's events and keep it until it closes. A player following the game
+// is sent its state and then every event at once, a spectator what the delay allows of its history.
+// Everything sent on the WebSocket goes through one queue, so frames never interleave and nothing
+// is written to it while the games are locked.
+fn follow_game(request: &Request, mut stream: TcpStream, reader: BufReader<TcpStream>, games: &SharedGames, spectating: bool) {
+    let id = request.path.trim_matches('/').split('/').nth(1).unwrap_or("").to_string();
+    let key = match request.header("sec-websocket-key") {
+        Some(key) => key.to_string(),
+        None => return respond(&mut stream, 400, &format!("{{\"error\": {}}}", json_string("Missing Sec-WebSocket-Key"))),
+    };
+
+    let (mut follower, frames) = mpsc::channel();
+    let number = {
+        let mut locked = games.lock().unwrap();
+        let number = locked.next_follower;
+        locked.next_follower += 1;
+        let delay = locked.spectator_delay.as_secs();
+        let followed = find_game(&mut locked, &id).and_then(|(id, game)| match game.spectate {
+            Some(ref spectate) if spectating => {
+                let watching = format!(
+                    "{{\"type\": \"watching\", \"id\": {}, \"variant\": {}, \"fen\": {}, \"delay\": {}}}",
+                    id, json_string(game.variant.name()), json_string(&Position::with_variant(game.variant).to_fen()), delay,
+                );
+                let _ = write_text(&mut follower, &watching);
+                let _ = spectate.send(Spectate::Watch(number, follower.clone()));
+                Ok(())
+            },
+            None if spectating => Err((409, format!("Game {} is over", id))),
+            _ => {
+                let _ = write_text(&mut follower, &format!("{{\"type\": \"state\", \"game\": {}}}", state_json(id, game)));
+                // A game that is over has nothing more to tell
+                match game.result {
+                    Some(_) => {
+                        let _ = follower.send((OPCODE_CLOSE, Vec::new()));
+                    },
+                    None => game.listeners.push((number, follower.clone())),
+                }
+                Ok(())
+            },
+        });
+        followed.map(|_| number)
+    };
+    let number = match number {
+        Ok(number) => number,
+        Err((status, error)) => return respond(&mut stream, status, &format!("{{\"error\": {}}}", json_string(&error))),
+    };
+
+    // The frames queued meanwhile wait for the handshake
+    let handshake = format!(
+        "HTTP/1.1 101 Switching Protocols\r\nUpgrade: websocket\r\nConnection: Upgrade\r\nSec-WebSocket-Accept: {}\r\n\r\n",
+        accept_key(&key),
+    );
+    if stream.write_all(handshake.as_bytes()).is_err() {
+        return;
+    }
+    thread::spawn(move || write_frames(stream, frames));
+
+    // Answer pings and the closing handshake; the events themselves are queued by whoever changes the game
+    let mut reader = reader;
+    while let Ok((opcode, payload)) = read_frame(&mut reader) {
+        match opcode {
+            OPCODE_PING => {
+                let _ = follower.send((OPCODE_PONG, payload));
+            },
+            OPCODE_CLOSE => {
+                let _ = follower.send((OPCODE_CLOSE, payload));
+                break;
+            },
+            _ => {},
+        }
+    }
+    let mut locked = games.lock().unwrap();
+    if let Ok((_, game)) = find_game(&mut locked, &id) {
+        match game.spectate {
+            Some(ref spectate) if spectating => {
+                let _ = spectate.send(Spectate::Unwatch(number));
+            },
+            _ => game.listeners.retain(|(listener, _)| *listener != number),
+        }
+    }
+}
+
+// Serve one request, or follow a game over a WebSocket
+fn handle_connection(stream: TcpStream, games: SharedGames) {
+    let mut writer = match stream.try_clone() {
+        Ok(writer) => writer,
+        Err(_) => return,
+    };
+    let mut reader = BufReader::new(stream);
+    let request = match read_request(&mut reader) {
+        Ok(request) => request,
+        Err(error) => return respond(&mut writer, 400, &format!("{{\"error\": {}}}", json_string(&error.to_string()))),
+    };
+
+    let upgrade = request.header("upgrade").is_some_and(|value| value.eq_ignore_ascii_case("websocket"));
+    let path = request.path.trim_end_matches('/');
+    if upgrade && request.method == "GET" && (path.ends_with("/events") || path.ends_with("/watch")) {
+        let spectating = path.ends_with("/watch");
+        return follow_game(&request, writer, reader, &games, spectating);
+    }
+    match route(&request, &games) {
+        Ok((status, body)) => respond(&mut writer, status, &body),
+        Err((status, error)) => respond(&mut writer, status, &format!("{{\"error\": {}}}", json_string(&error))),
+    }
+}
+
+// Serve the API on the given address until the program is stopped, showing games to spectators
+// after the given delay
+pub fn run(address: &str, spectator_delay: Duration) -> io::Result<()> {
+    let listener = TcpListener::bind(address)?;
+    println!("API listening on http://{}", listener.local_addr()?);
+    serve(listener, spectator_delay);
+    Ok(())
+}
+
+// Serve the API on a listener that is already bound
+fn serve(listener: TcpListener, spectator_delay: Duration) {
+    let games = Arc::new(Mutex::new(Games { games: HashMap::new(), next_id: 1, next_follower: 1, spectator_delay }));
+    for stream in listener.incoming().flatten() {
+        if stream.set_write_timeout(Some(WRITE_TIMEOUT)).is_err() {
+            continue;
+        }
+        let games = Arc::clone(&games);
+        thread::spawn(move || handle_connection(stream, games));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Start the API on a free port of the local machine and get its address
+    fn start_api() -> String {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = listener.local_addr().unwrap().to_string();
+        thread::spawn(move || serve(listener, Duration::ZERO));
+        address
+    }
+
+    // Connect to the API, giving up on answers that take too long
+    fn connect(address: &str) -> TcpStream {
+        let stream = TcpStream::connect(address).unwrap();
+        stream.set_read_timeout(Some(Duration::from_secs(5))).unwrap();
+        stream
+    }
+
+    // Make a request and get the status and body of the answer
+    fn request(address: &str, method: &str, path: &str, body: &str) -> (u16, String) {
+        let mut stream = connect(address);
+        write!(stream, "{} {} HTTP/1.1\r\nHost: localhost\r\nContent-Length: {}\r\n\r\n{}", method, path, body.len(), body).unwrap();
+        let mut response = String::new();
+        stream.read_to_string(&mut response).unwrap();
+        let (head, body) = response.split_once("\r\n\r\n").unwrap();
+        let status = head.split_whitespace().nth(1).unwrap().parse().unwrap();
+        (status, body.to_string())
+    }
+
+    // Start a game and get its number and the bodies White and Black act with
+    fn create(address: &str) -> (u32, String, String) {
+        let (status, body) = request(address, "POST", "/games", "");
+        assert_eq!(status, 201);
+        let id = body.strip_prefix("{\"id\": ").unwrap();
+        let id = id[..id.find(',').unwrap()].parse().unwrap();
+        let tokens = &body[body.find("\"tokens\": ").unwrap()..];
+        let token = |color: &str| {
+            let start = tokens.find(&format!("\"{}\": \"", color)).unwrap() + color.len() + 5;
+            format!("\"token\": \"{}\"", &tokens[start..start + 32])
+        };
+        (id, token("white"), token("black"))
+    }
+
+    #[test]
+    fn games_are_created_and_played() {
+        let address = start_api();
+        let (id, white, black) = create(&address);
+        let (status, body) = request(&address, "GET", &format!("/games/{}", id), "");
+        assert_eq!(status, 200);
+        assert!(body.contains("\"status\": \"playing\""));
+        // Only whoever started the game gets the tokens
+        assert!(!body.contains("token"));
+
+        let (status, body) = request(&address, "POST", &format!("/games/{}/move", id), &format!("{{\"move\": \"e4\", {}}}", white));
+        assert_eq!(status, 200);
+        assert!(body.contains("\"moves\": [\"e2e4\"]"));
+        assert!(body.contains("\"turn\": \"black\""));
+
+        let (status, body) = request(&address, "POST", &format!("/games/{}/move", id), &format!("{{\"move\": \"e4\", {}}}", black));
+        assert_eq!(status, 400);
+        assert_eq!(body, "{\"error\": \"Illegal move 'e4'\"}");
+        let (status, _) = request(&address, "POST", &format!("/games/{}/move", id), &format!("{{\"move\": \"e5\", {}}}", white));
+        assert_eq!(status, 409);
+        let (status, _) = request(&address, "POST", "/games/999/move", &format!("{{\"move\": \"e5\", {}}}", black));
+        assert_eq!(status, 404);
+    }
+
+    #[test]
+    fn players_act_with_their_tokens() {
+        let address = start_api();
+        let (id, white, _) = create(&address);
+        let (other, _, black) = create(&address);
+
+        let (status, _) = request(&address, "POST", &format!("/games/{}/move", id), "{\"move\": \"e4\"}");
+        assert_eq!(status, 400);
+        let (status, body) = request(&address, "POST", &format!("/games/{}/move", id), "{\"move\": \"e4\", \"token\": \"0123\"}");
+        assert_eq!((status, body.as_str()), (403, "{\"error\": \"That token is not for this game\"}"));
+        // A token only works for the game it was given for
+        let (status, _) = request(&address, "POST", &format!("/games/{}/resign", id), &format!("{{{}}}", black));
+        assert_eq!(status, 403);
+        let (status, _) = request(&address, "POST", &format!("/games/{}/draw", other), &format!("{{{}}}", white));
+        assert_eq!(status, 403);
+        let (_, body) = request(&address, "GET", &format!("/games/{}", id), "");
+        assert!(body.contains("\"moves\": [], \"status\": \"playing\""));
+    }
+
+    #[test]
+    fn games_end_by_resigning() {
+        let address = start_api();
+        let (id, white, _) = create(&address);
+        let (status, body) = request(&address, "POST", &format!("/games/{}/resign", id), &format!("{{{}}}", white));
+        assert_eq!(status, 200);
+        assert!(body.contains("\"result\": \"black\", \"reason\": \"White resigned\""));
+
+        // Nothing more can happen in a game that is over
+        let (status, _) = request(&address, "POST", &format!("/games/{}/move", id), &format!("{{\"move\": \"e4\", {}}}", white));
+        assert_eq!(status, 409);
+    }
+
+    #[test]
+    fn draws_are_offered_and_agreed() {
+        let address = start_api();
+        let (id, white, black) = create(&address);
+        let (status, body) = request(&address, "POST", &format!("/games/{}/draw", id), &format!("{{{}}}", white));
+        assert_eq!(status, 200);
+        assert!(body.contains("\"draw_offer\": \"white\""));
+
+        // Moving turns the offer down, so Black has to make one of their own
+        request(&address, "POST", &format!("/games/{}/move", id), &format!("{{\"move\": \"e4\", {}}}", white));
+        let (_, body) = request(&address, "POST", &format!("/games/{}/move", id), &format!("{{\"move\": \"e5\", {}}}", black));
+        assert!(body.contains("\"draw_offer\": null"));
+        request(&address, "POST", &format!("/games/{}/draw", id), &format!("{{{}}}", black));
+        let (_, body) = request(&address, "POST", &format!("/games/{}/draw", id), &format!("{{{}}}", white));
+        assert!(body.contains("\"result\": \"draw\", \"reason\": \"agreed\""));
+    }
+
+    #[test]
+    fn websockets_follow_a_game() {
+        let address = start_api();
+        let (id, white, black) = create(&address);
+        let mut stream = connect(&address);
+        // The sample handshake of RFC 6455
+        write!(
+            stream,
+            "GET /games/{}/events HTTP/1.1\r\nHost: localhost\r\nUpgrade: websocket\r\nConnection: Upgrade\r\nSec-WebSocket-Key: dGhlIHNhbXBsZSBub25jZQ==\r\nSec-WebSocket-Version: 13\r\n\r\n",
+            id,
+        )
+        .unwrap();
+        let mut reader = BufReader::new(stream.try_clone().unwrap());
+        let mut headers = Vec::new();
+        loop {
+            let mut line = String::new();
+            reader.read_line(&mut line).unwrap();
+            if line.trim().is_empty() {
+                break;
+            }
+            headers.push(line.trim().to_string());
+        }
+        assert_eq!(headers[0], "HTTP/1.1 101 Switching Protocols");
+        assert!(headers.contains(&"Sec-WebSocket-Accept: s3pPLMBiTxaQ9kYGzzhZRbK+xOo=".to_string()));
+
+        let (opcode, payload) = read_frame(&mut reader).unwrap();
+        assert_eq!(opcode, OPCODE_TEXT);
+        assert!(String::from_utf8(payload).unwrap().starts_with("{\"type\": \"state\""));
+
+        request(&address, "POST", &format!("/games/{}/move", id), &format!("{{\"move\": \"Nf3\", {}}}", white));
+        let (_, payload) = read_frame(&mut reader).unwrap();
+        assert!(String::from_utf8(payload).unwrap().contains("\"move\": \"g1f3\""));
+
+        // A ping is answered, and resigning sends the result and closes the WebSocket
+        write_frame(&mut stream, OPCODE_PING, b"hello").unwrap();
+        assert_eq!(read_frame(&mut reader).unwrap(), (OPCODE_PONG, b"hello".to_vec()));
+        request(&address, "POST", &format!("/games/{}/resign", id), &format!("{{{}}}", black));
+        let (_, payload) = read_frame(&mut reader).unwrap();
+        assert!(String::from_utf8(payload).unwrap().starts_with("{\"type\": \"end\", \"result\": \"white\""));
+        assert_eq!(read_frame(&mut reader).unwrap().0, OPCODE_CLOSE);
+    }
+}
@@ -0,0 +1,260 @@
+use std::fmt;
+
+// Fairy pieces described in Betza notation, so new pieces can be defined at runtime instead of
+// being added to PieceType. A description is a list of atoms, each a capital letter standing for
+// a leap, optionally preceded by lowercase modifiers and followed by a range:
+//
+// - W (1,0) wazir, F (1,1) ferz, D (2,0) dabbaba, N (2,1) knight, A (2,2) alfil, H (3,0) threeleaper,
+//   C (3,1) camel, Z (3,2) zebra and G (3,3) tripper. K, R, B and Q stand for the king, rook,
+//   bishop and queen.
+// - An atom written twice, as in "NN", or followed by 0 rides any distance along its line, and one
+//   followed by a number rides at most that many steps, as in "W2".
+// - m and c restrict the atom to moving or to capturing, and f, b, l, r, v (vertical) and s
+//   (sideways) restrict its directions as seen by the player. A forward or backward letter followed
+//   by a left or right one, as in "fl", means both at once, otherwise the directions add up.
+//
+// So "NR" is the chancellor, "WB" a bishop that can also step like a wazir, and "fmWfcF" a pawn
+// without its double step.
+
+// One way a piece can move: a leap of so many squares forward and to the right of the player,
+// repeated up to range times along the same line (0 for any number)
+#[derive(Debug, Clone, PartialEq)]
+pub struct Step {
+    pub forward: i32,
+    pub right: i32,
+    pub range: u32,
+    pub moves: bool,
+    pub captures: bool,
+}
+
+// A piece defined by Betza notation, shown on the board and in FEN by its symbol
+#[derive(Clone, PartialEq)]
+pub struct FairyPiece {
+    pub name: String,
+    pub symbol: char,
+    pub notation: String,
+    pub steps: Vec<Step>,
+    // Rough material value in centipawns, from how many squares the piece reaches on an empty board
+    pub value: i32,
+}
+
+// The pieces are printed by name, since their steps would make every message unreadable
+impl fmt::Debug for FairyPiece {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.name)
+    }
+}
+
+// The letters the built-in pieces already use in FEN
+const BUILT_IN_SYMBOLS: &str = "pnbrqkac";
+
+impl FairyPiece {
+    // Define a piece from its name, symbol and Betza notation
+    pub fn new(name: &str, symbol: char, notation: &str) -> Result<FairyPiece, String> {
+        if !symbol.is_ascii_alphabetic() || BUILT_IN_SYMBOLS.contains(symbol.to_ascii_lowercase()) {
+            return Err(format!("'{}' cannot be used as the symbol of a piece", symbol));
+        }
+        let steps = parse_betza(notation)?;
+        let value = estimate_value(&steps);
+        Ok(FairyPiece { name: name.to_string(), symbol: symbol.to_ascii_lowercase(), notation: notation.to_string(), steps, value })
+    }
+}
+
+// Get the leap of a one-letter atom, and whether it is a shorthand for a rider
+fn atom_leaps(atom: char) -> Option<Vec<((i32, i32), bool)>> {
+    let leap = |forward, right| ((forward, right), false);
+    let ride = |forward, right| ((forward, right), true);
+    Some(match atom {
+        'W' => vec![leap(1, 0)],
+        'F' => vec![leap(1, 1)],
+        'D' => vec![leap(2, 0)],
+        'N' => vec![leap(2, 1)],
+        'A' => vec![leap(2, 2)],
+        'H' => vec![leap(3, 0)],
+        'C' => vec![leap(3, 1)],
+        'Z' => vec![leap(3, 2)],
+        'G' => vec![leap(3, 3)],
+        'K' => vec![leap(1, 0), leap(1, 1)],
+        'R' => vec![ride(1, 0)],
+        'B' => vec![ride(1, 1)],
+        'Q' => vec![ride(1, 0), ride(1, 1)],
+        _ => return None,
+    })
+}
+
+// Get every direction a leap can be made in, without repeating any
+fn symmetric_leaps((a, b): (i32, i32)) -> Vec<(i32, i32)> {
+    let mut leaps = Vec::new();
+    for (forward, right) in [(a, b), (b, a)] {
+        for (forward, right) in [(forward, right), (-forward, right), (forward, -right), (-forward, -right)] {
+            if !leaps.contains(&(forward, right)) {
+                leaps.push((forward, right));
+            }
+        }
+    }
+    leaps
+}
+
+// Check if a leap goes in one of the directions given by the modifiers, or in any if there are none
+fn in_directions(directions: &[String], (forward, right): (i32, i32)) -> bool {
+    directions.is_empty() || directions.iter().any(|direction| direction.chars().all(|c| match c {
+        'f' => forward > 0,
+        'b' => forward < 0,
+        'l' => right < 0,
+        'r' => right > 0,
+        'v' => forward.abs() > right.abs(),
+        's' => right.abs() > forward.abs(),
+        _ => false,
+    }))
+}
+
+// Parse Betza notation into the steps of the piece
+pub fn parse_betza(notation: &str) -> Result<Vec<Step>, String> {
+    let chars: Vec<char> = notation.chars().collect();
+    let mut steps: Vec<Step> = Vec::new();
+    let mut i = 0;
+    while i < chars.len() {
+        // Modifiers come before the atom
+        let (mut moves, mut captures) = (false, false);
+        let mut directions: Vec<String> = Vec::new();
+        while i < chars.len() && chars[i].is_ascii_lowercase() {
+            match chars[i] {
+                'm' => moves = true,
+                'c' => captures = true,
+                'f' | 'b' if matches!(chars.get(i + 1), Some('l' | 'r')) => {
+                    directions.push(chars[i..i + 2].iter().collect());
+                    i += 1;
+                },
+                'f' | 'b' | 'l' | 'r' | 'v' | 's' => directions.push(chars[i].to_string()),
+                c => return Err(format!("Unknown modifier '{}' in Betza notation '{}'", c, notation)),
+            }
+            i += 1;
+        }
+        if !moves && !captures {
+            (moves, captures) = (true, true);
+        }
+
+        let atom = *chars.get(i).ok_or(format!("Betza notation '{}' ends without an atom", notation))?;
+        let leaps = atom_leaps(atom).ok_or(format!("Unknown atom '{}' in Betza notation '{}'", atom, notation))?;
+        i += 1;
+
+        // A doubled atom or a number after it makes a rider
+        let mut range = None;
+        if chars.get(i) == Some(&atom) {
+            range = Some(0);
+            i += 1;
+        } else {
+            let digits: String = chars[i..].iter().take_while(|c| c.is_ascii_digit()).collect();
+            if !digits.is_empty() {
+                range = Some(digits.parse::<u32>().map_err(|_| format!("Invalid range in Betza notation '{}'", notation))?);
+                i += digits.len();
+            }
+        }
+
+        for (leap, rides) in leaps {
+            let range = range.unwrap_or(if rides { 0 } else { 1 });
+            for (forward, right) in symmetric_leaps(leap).into_iter().filter(|&leap| in_directions(&directions, leap)) {
+                // The same leap given twice, as in "mWcW", merges into one step
+                match steps.iter_mut().find(|step| (step.forward, step.right, step.range) == (forward, right, range)) {
+                    Some(step) => {
+                        step.moves |= moves;
+                        step.captures |= captures;
+                    },
+                    None => steps.push(Step { forward, right, range, moves, captures }),
+                }
+            }
+        }
+    }
+    if steps.is_empty() {
+        return Err(format!("Betza notation '{}' gives the piece no moves", notation));
+    }
+    Ok(steps)
+}
+
+// Estimate the value of a piece from the average number of squares it reaches from each square
+// of an empty 8x8 board, fitted so the knight, rook and queen come out close to their usual values
+fn estimate_value(steps: &[Step]) -> i32 {
+    let mut reached = 0;
+    for x in 0..8 {
+        for y in 0..8 {
+            for step in steps {
+                let mut distance = 1;
+                while step.range == 0 || distance <= step.range as i32 {
+                    let (to_x, to_y) = (x - step.forward * distance, y + step.right * distance);
+                    if !(0..8).contains(&to_x) || !(0..8).contains(&to_y) {
+                        break;
+                    }
+                    reached += 1;
+                    distance += 1;
+                }
+            }
+        }
+    }
+    150 + reached * 30 / 64
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::position::Position;
+    use std::sync::Arc;
+
+    // The leaps of the steps, sorted
+    fn leaps(steps: &[Step]) -> Vec<(i32, i32, u32)> {
+        let mut leaps: Vec<(i32, i32, u32)> = steps.iter().map(|step| (step.forward, step.right, step.range)).collect();
+        leaps.sort();
+        leaps
+    }
+
+    #[test]
+    fn atoms_and_ranges() {
+        assert_eq!(parse_betza("N").unwrap().len(), 8);
+        assert!(parse_betza("Q").unwrap().iter().all(|step| step.range == 0 && step.moves && step.captures));
+        assert_eq!(leaps(&parse_betza("NN").unwrap()), leaps(&parse_betza("N0").unwrap()));
+        assert!(parse_betza("NN").unwrap().iter().all(|step| step.range == 0));
+        assert_eq!(leaps(&parse_betza("W2").unwrap()), [(-1, 0, 2), (0, -1, 2), (0, 1, 2), (1, 0, 2)]);
+        // The king's atoms are the wazir's and the ferz's together
+        assert_eq!(leaps(&parse_betza("K").unwrap()), leaps(&parse_betza("WF").unwrap()));
+    }
+
+    #[test]
+    fn modifiers() {
+        // A pawn without its double step
+        let pawn = parse_betza("fmWfcF").unwrap();
+        assert_eq!(pawn, [
+            Step { forward: 1, right: 0, range: 1, moves: true, captures: false },
+            Step { forward: 1, right: 1, range: 1, moves: false, captures: true },
+            Step { forward: 1, right: -1, range: 1, moves: false, captures: true },
+        ]);
+        assert_eq!(leaps(&parse_betza("flN").unwrap()), [(1, -2, 1), (2, -1, 1)]);
+        assert_eq!(leaps(&parse_betza("vN").unwrap()), [(-2, -1, 1), (-2, 1, 1), (2, -1, 1), (2, 1, 1)]);
+        assert_eq!(leaps(&parse_betza("fN").unwrap()).len(), 4);
+        // Moving and capturing the same way merge into one step
+        let merged = parse_betza("mWcW").unwrap();
+        assert_eq!(merged.len(), 4);
+        assert!(merged.iter().all(|step| step.moves && step.captures));
+    }
+
+    #[test]
+    fn invalid_notation() {
+        assert_eq!(parse_betza("xN"), Err("Unknown modifier 'x' in Betza notation 'xN'".to_string()));
+        assert_eq!(parse_betza("fm"), Err("Betza notation 'fm' ends without an atom".to_string()));
+        assert_eq!(parse_betza("Y"), Err("Unknown atom 'Y' in Betza notation 'Y'".to_string()));
+        assert_eq!(parse_betza(""), Err("Betza notation '' gives the piece no moves".to_string()));
+        assert!(FairyPiece::new("Knight", 'n', "N").is_err());
+        assert!(FairyPiece::new("Star", '*', "N").is_err());
+    }
+
+    #[test]
+    fn fairy_pieces_move_by_their_steps() {
+        let knight = FairyPiece::new("Knight", 'y', "N").unwrap();
+        let rook = FairyPiece::new("Rook", 'y', "R").unwrap();
+        assert!(knight.value < rook.value);
+
+        // A hawk in the middle of the board moves like a bishop and a knight, beside the king's 5 moves
+        let hawk = Arc::new(FairyPiece::new("Hawk", 'y', "BN").unwrap());
+        let position = Position::from_fen_with_pieces("4k3/8/8/8/3Y4/8/8/4K3 w - - 0 1", None, &[hawk]).unwrap();
+        assert_eq!(position.legal_moves().len(), 13 + 8 + 5);
+        assert_eq!(position.to_fen(), "4k3/8/8/8/3Y4/8/8/4K3 w - - 0 1");
+    }
+}
@@ -0,0 +1,176 @@
+use std::io;
+use std::net::TcpStream;
+use std::sync::mpsc::{self, Receiver, RecvTimeoutError, Sender};
+use std::thread;
+use std::time::{Duration, Instant};
+
+// Live broadcasting of a game to any number of spectators, shared by the line server and the JSON
+// API. Each game has a thread that keeps everything the spectators have been told and sends it
+// on once it is old enough, so with a delay nobody watching can pass a move on to a player before
+// the delay is over. Someone who starts watching a game under way is first sent its history, up
+// to what the delay allows.
+
+// What a game tells its broadcast
+pub enum Spectate<W = TcpStream> {
+    // A line that stays part of the game's history, such as a move or the result
+    Record(String),
+    // The game's current state, which replaces the one before for anyone who starts watching later
+    State(String),
+    // A spectator starts or stops watching, known by a number the caller chooses, with where to
+    // write to them
+    Watch(u32, W),
+    Unwatch(u32),
+}
+
+// How a spectator is sent a line, which fails once they have gone away
+pub type WriteLine<W = TcpStream> = fn(&mut W, &str) -> io::Result<()>;
+
+// Start broadcasting a game, holding everything back by the delay, and get where to send what happens
+pub fn spawn<W: Send + 'static>(delay: Duration, write: WriteLine<W>) -> Sender<Spectate<W>> {
+    let (sender, receiver) = mpsc::channel();
+    thread::spawn(move || run(receiver, delay, write));
+    sender
+}
+
+// Send the spectators each line once its delay is over, until the game is over and everything has gone out
+fn run<W>(events: Receiver<Spectate<W>>, delay: Duration, write: WriteLine<W>) {
+    // Every line with when it happened and whether it is a state, and how many have been sent on
+    let mut history: Vec<(Instant, String, bool)> = Vec::new();
+    let mut sent = 0;
+    let mut spectators: Vec<(u32, W)> = Vec::new();
+    let mut over = false;
+
+    loop {
+        while let Some((at, text, _)) = history.get(sent) {
+            if at.elapsed() < delay {
+                break;
+            }
+            spectators.retain_mut(|(_, stream)| write(stream, text).is_ok());
+            sent += 1;
+        }
+
+        let next_due = history.get(sent).map(|(at, _, _)| delay.saturating_sub(at.elapsed()));
+        if over {
+            match next_due {
+                Some(wait) => {
+                    thread::sleep(wait);
+                    continue;
+                },
+                None => return,
+            }
+        }
+        let received = match next_due {
+            Some(wait) => events.recv_timeout(wait),
+            None => events.recv().map_err(|_| RecvTimeoutError::Disconnected),
+        };
+
+        match received {
+            Ok(Spectate::Record(text)) => history.push((Instant::now(), text, false)),
+            Ok(Spectate::State(text)) => history.push((Instant::now(), text, true)),
+            Ok(Spectate::Watch(id, mut stream)) => {
+                // Catch up with the history sent so far, where only the latest state matters
+                let latest_state = history[..sent].iter().rposition(|(_, _, state)| *state);
+                let caught_up = history[..sent].iter().enumerate()
+                    .filter(|&(i, (_, _, state))| !state || Some(i) == latest_state)
+                    .all(|(_, (_, text, _))| write(&mut stream, text).is_ok());
+                if caught_up {
+                    spectators.push((id, stream));
+                }
+            },
+            Ok(Spectate::Unwatch(id)) => spectators.retain(|(watching, _)| *watching != id),
+            Err(RecvTimeoutError::Timeout) => {},
+            Err(RecvTimeoutError::Disconnected) => over = true,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::Arc;
+
+    // A spectator whose lines arrive on a channel, counting every line the broadcast tries to send
+    struct TestSpectator {
+        lines: Sender<String>,
+        attempts: Arc<AtomicUsize>,
+    }
+
+    fn write_test_line(spectator: &mut TestSpectator, text: &str) -> io::Result<()> {
+        spectator.attempts.fetch_add(1, Ordering::SeqCst);
+        spectator.lines.send(text.to_string()).map_err(|_| io::Error::new(io::ErrorKind::BrokenPipe, "gone"))
+    }
+
+    // Start watching a broadcast, and get where the lines arrive and how many were tried
+    fn watch(broadcast: &Sender<Spectate<TestSpectator>>, id: u32) -> (Receiver<String>, Arc<AtomicUsize>) {
+        let (lines, received) = mpsc::channel();
+        let attempts = Arc::new(AtomicUsize::new(0));
+        broadcast.send(Spectate::Watch(id, TestSpectator { lines, attempts: Arc::clone(&attempts) })).unwrap();
+        (received, attempts)
+    }
+
+    const WAIT: Duration = Duration::from_secs(5);
+
+    #[test]
+    fn lines_are_held_back_by_the_delay() {
+        let delay = Duration::from_millis(300);
+        let broadcast = spawn(delay, write_test_line);
+        let (received, _) = watch(&broadcast, 1);
+        let played = Instant::now();
+        broadcast.send(Spectate::Record("moved White e2e4".to_string())).unwrap();
+        assert!(received.recv_timeout(Duration::from_millis(100)).is_err());
+        assert_eq!(received.recv_timeout(WAIT).unwrap(), "moved White e2e4");
+        assert!(played.elapsed() >= delay);
+
+        // What happened before the game ended still goes out, after which the broadcast stops
+        broadcast.send(Spectate::Record("end White wins: Black resigned".to_string())).unwrap();
+        drop(broadcast);
+        assert_eq!(received.recv_timeout(WAIT).unwrap(), "end White wins: Black resigned");
+        assert_eq!(received.recv_timeout(WAIT), Err(RecvTimeoutError::Disconnected));
+    }
+
+    #[test]
+    fn late_spectators_catch_up_with_what_was_released() {
+        let delay = Duration::from_millis(400);
+        let broadcast = spawn(delay, write_test_line);
+        let (first, _) = watch(&broadcast, 1);
+        for (text, state) in [("position start", true), ("moved White e2e4", false), ("position after e4", true)] {
+            broadcast.send(if state { Spectate::State(text.to_string()) } else { Spectate::Record(text.to_string()) }).unwrap();
+        }
+        for _ in 0..3 {
+            first.recv_timeout(WAIT).unwrap();
+        }
+        broadcast.send(Spectate::Record("moved Black e7e5".to_string())).unwrap();
+
+        // The history released so far comes at once, with only the latest state, but not the move
+        // still held back
+        let (late, _) = watch(&broadcast, 2);
+        assert_eq!(late.recv_timeout(WAIT).unwrap(), "moved White e2e4");
+        assert_eq!(late.recv_timeout(WAIT).unwrap(), "position after e4");
+        assert!(late.recv_timeout(Duration::from_millis(50)).is_err());
+        assert_eq!(late.recv_timeout(WAIT).unwrap(), "moved Black e7e5");
+        assert_eq!(first.recv_timeout(WAIT).unwrap(), "moved Black e7e5");
+
+        // A spectator who stops watching is sent nothing more
+        broadcast.send(Spectate::Unwatch(2)).unwrap();
+        broadcast.send(Spectate::Record("moved White g1f3".to_string())).unwrap();
+        assert_eq!(first.recv_timeout(WAIT).unwrap(), "moved White g1f3");
+        assert!(late.try_recv().is_err());
+    }
+
+    #[test]
+    fn spectators_who_have_gone_away_are_dropped() {
+        let broadcast = spawn(Duration::ZERO, write_test_line);
+        let (gone, gone_attempts) = watch(&broadcast, 1);
+        drop(gone);
+        let (staying, _) = watch(&broadcast, 2);
+        for text in ["moved White e2e4", "moved Black e7e5", "moved White g1f3"] {
+            broadcast.send(Spectate::Record(text.to_string())).unwrap();
+        }
+        for text in ["moved White e2e4", "moved Black e7e5", "moved White g1f3"] {
+            assert_eq!(staying.recv_timeout(WAIT).unwrap(), text);
+        }
+        // The first failed write was the last one tried
+        assert_eq!(gone_attempts.load(Ordering::SeqCst), 1);
+    }
+}
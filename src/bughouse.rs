@@ -0,0 +1,250 @@
+use std::io::{self, Write};
+use std::time::{Duration, Instant};
+
+use crate::position::{opponent, pocket_slot, pocket_to_string, Move, Position};
+use crate::variant::Variant;
+use crate::{print_board, Color};
+
+// The two teams: team A plays White on board 1 and Black on board 2, team B the other two seats
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Team {
+    A,
+    B,
+}
+
+// How a bughouse match ended, always decided for both boards at once
+#[derive(Debug, Clone, PartialEq)]
+pub enum BughouseResult {
+    Win(Team, String),
+    Draw(String),
+}
+
+// Get the team of the player with the given color on the given board (0 or 1)
+pub fn team(board: usize, color: Color) -> Team {
+    match (board, color) {
+        (0, Color::White) | (1, Color::Black) => Team::A,
+        _ => Team::B,
+    }
+}
+
+// Get the name of a board for messages, counting from 1
+fn board_name(board: usize) -> String {
+    format!("board {}", board + 1)
+}
+
+// Format a clock as minutes and seconds
+fn format_clock(time: Duration) -> String {
+    let seconds = time.as_secs();
+    format!("{}:{:02}", seconds / 60, seconds % 60)
+}
+
+// Two games played at the same time, where every captured piece goes to the capturer's partner
+pub struct Bughouse {
+    pub boards: [Position; 2],
+    // The time left for (White, Black) on each board
+    pub clocks: [(Duration, Duration); 2],
+    last_tick: Instant,
+    pub result: Option<BughouseResult>,
+}
+
+impl Bughouse {
+    // Start both boards from the standard position with the same time for every player
+    pub fn new(time: Duration) -> Bughouse {
+        let position = Position::with_variant(Variant::Bughouse);
+        Bughouse {
+            boards: [position.clone(), position],
+            clocks: [(time, time); 2],
+            last_tick: Instant::now(),
+            result: None,
+        }
+    }
+
+    // Get the time left for one player
+    pub fn clock(&self, board: usize, color: Color) -> Duration {
+        match color {
+            Color::White => self.clocks[board].0,
+            Color::Black => self.clocks[board].1,
+        }
+    }
+
+    // Run the clocks of the players to move on both boards, ending the match if one of them runs out
+    pub fn tick(&mut self) {
+        let elapsed = self.last_tick.elapsed();
+        self.last_tick = Instant::now();
+        if self.result.is_some() {
+            return;
+        }
+        for board in 0..2 {
+            let color = self.boards[board].side_to_move;
+            let clock = match color {
+                Color::White => &mut self.clocks[board].0,
+                Color::Black => &mut self.clocks[board].1,
+            };
+            *clock = clock.saturating_sub(elapsed);
+            if clock.is_zero() && self.result.is_none() {
+                let reason = format!("{:?} lost on time on {}", color, board_name(board));
+                self.result = Some(BughouseResult::Win(team(board, opponent(color)), reason));
+            }
+        }
+    }
+
+    // Play a legal move on one board, passing any captured piece to the partner on the other board
+    pub fn play(&mut self, board: usize, mv: &Move) {
+        self.tick();
+        if self.result.is_some() {
+            return;
+        }
+        let color = self.boards[board].side_to_move;
+        if let Some(piece_type) = self.boards[board].pocket_capture(mv) {
+            // The captured piece has the partner's color, since the partner plays the other color
+            if let Some(slot) = pocket_slot(&piece_type) {
+                self.boards[1 - board].pocket_mut(opponent(color))[slot] += 1;
+            }
+        }
+        self.boards[board].make_move(mv);
+        self.result = self.board_result(board);
+    }
+
+    // Resign for the player to move on one board, which loses the match for their team
+    pub fn resign(&mut self, board: usize) {
+        self.tick();
+        if self.result.is_none() {
+            let color = self.boards[board].side_to_move;
+            let reason = format!("{:?} resigned on {}", color, board_name(board));
+            self.result = Some(BughouseResult::Win(team(board, opponent(color)), reason));
+        }
+    }
+
+    // Check if the game on one board has ended, which ends the whole match
+    fn board_result(&self, board: usize) -> Option<BughouseResult> {
+        let position = &self.boards[board];
+        if !position.legal_moves().is_empty() {
+            return None;
+        }
+        let color = position.side_to_move;
+        if position.in_check() {
+            let reason = format!("{:?} was checkmated on {}", color, board_name(board));
+            Some(BughouseResult::Win(team(board, opponent(color)), reason))
+        } else {
+            Some(BughouseResult::Draw(format!("stalemate on {}", board_name(board))))
+        }
+    }
+
+    // Show both boards with their pockets and clocks
+    pub fn print(&self) {
+        for (board, position) in self.boards.iter().enumerate() {
+            println!("Board {} ({:?} to move)", board + 1, position.side_to_move);
+            println!("Black {}  pocket: {}", format_clock(self.clock(board, Color::Black)), pocket_to_string(position.pocket(Color::Black), Color::Black));
+            print_board(&position.board);
+            println!("White {}  pocket: {}", format_clock(self.clock(board, Color::White)), pocket_to_string(position.pocket(Color::White), Color::White));
+            println!();
+        }
+    }
+}
+
+// Parse a move for one board, given either in long algebraic notation or in SAN
+fn parse_bughouse_move(position: &Position, text: &str) -> Option<Move> {
+    position.parse_move(text).or_else(|| position.parse_san(text))
+}
+
+// Play a hot-seat bughouse match with all four players at one terminal
+pub fn play() {
+    print!("Minutes per player (default 5): ");
+    io::stdout().flush().unwrap();
+    let mut input = String::new();
+    io::stdin().read_line(&mut input).unwrap();
+    let minutes = input.trim().parse::<u64>().unwrap_or(5);
+
+    let mut game = Bughouse::new(Duration::from_secs(minutes * 60));
+    println!("Team A plays White on board 1 and Black on board 2, team B the other seats.");
+    println!("Enter moves as '<board> <move>', e.g. '1 e2e4', '2 Nf3' or '1 N@f3', or 'resign <board>'.");
+
+    while game.result.is_none() {
+        game.tick();
+        if game.result.is_some() {
+            break;
+        }
+        game.print();
+        print!("Enter move: ");
+        io::stdout().flush().unwrap();
+        let mut input = String::new();
+        if io::stdin().read_line(&mut input).unwrap() == 0 {
+            return;
+        }
+        let tokens: Vec<&str> = input.split_whitespace().collect();
+        match tokens.as_slice() {
+            ["resign", board] => match board.parse::<usize>() {
+                Ok(board @ 1..=2) => game.resign(board - 1),
+                _ => println!("Invalid input"),
+            },
+            [board, text] => {
+                let board = match board.parse::<usize>() {
+                    Ok(board @ 1..=2) => board - 1,
+                    _ => {
+                        println!("Invalid input");
+                        continue;
+                    }
+                };
+                match parse_bughouse_move(&game.boards[board], text) {
+                    Some(mv) => {
+                        println!("{:?} plays {} on {}", game.boards[board].side_to_move, game.boards[board].move_to_string(&mv), board_name(board));
+                        game.play(board, &mv);
+                    },
+                    None => println!("Invalid move"),
+                }
+            },
+            _ => println!("Invalid input"),
+        }
+    }
+
+    game.print();
+    match game.result {
+        Some(BughouseResult::Win(team, reason)) => println!("Team {:?} wins: {}", team, reason),
+        Some(BughouseResult::Draw(reason)) => println!("The match is drawn: {}", reason),
+        None => {},
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Play moves on one board, written as at the terminal
+    fn play_moves(bughouse: &mut Bughouse, board: usize, moves: &[&str]) {
+        for text in moves {
+            let mv = parse_bughouse_move(&bughouse.boards[board], text).unwrap_or_else(|| panic!("Illegal move '{}'", text));
+            bughouse.play(board, &mv);
+        }
+    }
+
+    #[test]
+    fn captured_pieces_go_to_the_partner() {
+        let mut bughouse = Bughouse::new(Duration::from_secs(300));
+        play_moves(&mut bughouse, 0, &["e4", "d5", "exd5"]);
+        // White on board 1 took a black pawn, which their partner plays Black with on board 2
+        assert_eq!(bughouse.boards[0].to_fen(), "rnbqkbnr/ppp1pppp/8/3P4/8/8/PPPP1PPP/RNBQKBNR[] b KQkq - 0 2");
+        assert_eq!(bughouse.boards[1].to_fen(), "rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR[p] w KQkq - 0 1");
+
+        play_moves(&mut bughouse, 1, &["e4"]);
+        let drop = parse_bughouse_move(&bughouse.boards[1], "P@e6").unwrap();
+        bughouse.play(1, &drop);
+        assert_eq!(bughouse.boards[1].to_fen(), "rnbqkbnr/pppppppp/4p3/8/4P3/8/PPPP1PPP/RNBQKBNR[] w KQkq - 0 2");
+        assert!(bughouse.result.is_none());
+    }
+
+    #[test]
+    fn one_board_decides_the_match() {
+        let mut bughouse = Bughouse::new(Duration::from_secs(300));
+        play_moves(&mut bughouse, 1, &["f3", "e5", "g4", "Qh4"]);
+        // Black on board 2 plays for team A
+        assert_eq!(bughouse.result, Some(BughouseResult::Win(Team::A, "White was checkmated on board 2".to_string())));
+        assert_eq!(team(1, Color::Black), Team::A);
+
+        let mut bughouse = Bughouse::new(Duration::from_secs(300));
+        bughouse.resign(0);
+        assert_eq!(bughouse.result, Some(BughouseResult::Win(Team::B, "White resigned on board 1".to_string())));
+        // Nothing more is played once the match is over
+        play_moves(&mut bughouse, 1, &["e4"]);
+        assert_eq!(bughouse.boards[1].side_to_move, Color::White);
+    }
+}
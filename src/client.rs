@@ -0,0 +1,412 @@
+use std::io::{self, BufRead, BufReader, Write};
+use std::net::{Shutdown, TcpStream};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::Duration;
+
+use crate::position::{opponent, Position};
+use crate::variant::{outcome, parse_player_move, print_position, Variant};
+use crate::Color;
+
+// A client for the server in server.rs, so a game on another machine is played the same way as at
+// the terminal. One thread reads what the server sends and draws the board whenever the position
+// changes, while the player's moves are checked here before they are sent. When the connection
+// drops during a game the client connects again and sits back down with the key the server gave.
+
+// How often and how far apart the client tries to get back to a game after losing the connection
+const RECONNECT_ATTEMPTS: u32 = 10;
+const RECONNECT_DELAY: Duration = Duration::from_secs(1);
+
+// The commands passed on to the server as they are typed; anything else is taken for a move
+const COMMANDS: [&str; 13] = [
+    "name", "seek", "challenge", "accept", "decline", "new", "list", "join", "watch", "unwatch", "rejoin", "board", "resign",
+];
+
+// What both threads know about the connection and the game being played
+struct Connection {
+    writer: Option<TcpStream>,
+    reconnecting: bool,
+    closing: bool,
+    // The game the player sits at, with the key to rejoin it
+    seat: Option<(u32, Variant, Color, String)>,
+    // The variant of the game the player watches
+    watching: Option<Variant>,
+    position: Option<Position>,
+}
+
+type SharedConnection = Arc<Mutex<Connection>>;
+
+// Send a line to the server, if connected
+fn send(connection: &mut Connection, text: &str) -> bool {
+    match connection.writer {
+        Some(ref mut writer) => writer.write_all(text.as_bytes()).and_then(|_| writer.write_all(b"\n")).is_ok(),
+        None => false,
+    }
+}
+
+// Write milliseconds left on a clock as minutes and seconds, counting a started second as a whole one
+fn clock_text(millis: &str) -> String {
+    let seconds = millis.parse::<u64>().unwrap_or(0).div_ceil(1000);
+    format!("{}:{:02}", seconds / 60, seconds % 60)
+}
+
+// Tell the player what to do next in the game
+fn prompt(position: &Position, color: Color) {
+    if outcome(position).is_some() {
+        return;
+    }
+    if position.side_to_move != color {
+        println!("Waiting for {:?} to move...", opponent(color));
+    } else if position.variant == Variant::Duck {
+        print!("Your move, with the duck square (e.g. e4 d5): ");
+    } else {
+        print!("Your move: ");
+    }
+    io::stdout().flush().unwrap();
+}
+
+// Act on one line from the server. The board lines following a position are only shown when the
+// client does not know the variant, and so could not read the position itself.
+fn handle_line(line: &str, shared: &SharedConnection, show_board: &mut bool) {
+    let (keyword, rest) = line.split_once(' ').unwrap_or((line, ""));
+    let mut connection = shared.lock().unwrap();
+    match keyword {
+        "info" => println!("{}", rest),
+        "error" => println!("Error: {}", rest),
+        "game" => {
+            let words: Vec<&str> = rest.split_whitespace().collect();
+            let seat = match words[..] {
+                [id, variant, color, key] => id.parse::<u32>().ok().map(|id| {
+                    let variant = Variant::from_name(variant).unwrap_or(Variant::Standard);
+                    let color = if color == "Black" { Color::Black } else { Color::White };
+                    (id, variant, color, key.to_string())
+                }),
+                _ => None,
+            };
+            if let Some((id, variant, color, ref key)) = seat {
+                println!("Playing game {} of {} as {:?}.", id, variant.title(), color);
+                println!("If the connection is lost for good, come back with: rejoin {} {}", id, key);
+            }
+            connection.seat = seat;
+            connection.watching = None;
+        },
+        "waiting" => match rest.split_once(' ') {
+            Some((id, variant)) => println!("Game {} of {} is waiting for an opponent", id, variant),
+            None => println!("{}", rest),
+        },
+        "seek" => match rest.split_whitespace().collect::<Vec<&str>>()[..] {
+            [id, user, rating, variant, time, color, ratings] => {
+                println!("Seek {}: {} ({}) offers {} {}, playing {}, to ratings {}. Type accept {} to play.", id, user, rating, variant, time, color, ratings, id);
+            },
+            _ => println!("{}", rest),
+        },
+        "challenge" => match rest.split_whitespace().collect::<Vec<&str>>()[..] {
+            [id, user, rating, target, variant, time, color, _] => {
+                println!("Challenge {}: {} ({}) challenges {} to {} {}, playing {}. Answer with accept {} or decline {}.", id, user, rating, target, variant, time, color, id, id);
+            },
+            _ => println!("{}", rest),
+        },
+        "playing" => match rest.split_whitespace().collect::<Vec<&str>>()[..] {
+            [id, variant, time, white, black] => println!("Game {} of {} {}: {} against {}", id, variant, time, white, black),
+            _ => println!("{}", rest),
+        },
+        "clock" => match rest.split_once(' ') {
+            Some((white, black)) => println!("Clock: White {}, Black {}", clock_text(white), clock_text(black)),
+            None => println!("{}", rest),
+        },
+        "watching" => match rest.split_whitespace().collect::<Vec<&str>>()[..] {
+            [id, variant, time, white, black, delay] => {
+                connection.watching = Some(Variant::from_name(variant).unwrap_or(Variant::Standard));
+                println!("Watching game {} of {} {}: {} against {}", id, variant, time, white, black);
+                if delay != "0" {
+                    println!("The moves are shown {} seconds after they are played.", delay);
+                }
+            },
+            _ => println!("{}", rest),
+        },
+        "start" => println!("The game begins."),
+        "moved" => match rest.split_once(' ') {
+            Some((color, played)) => println!("{} played {}", color, played),
+            None => println!("{}", rest),
+        },
+        "position" => {
+            // Spectators see the board too, without being asked for a move
+            let (variant, color) = match (connection.seat.as_ref(), connection.watching) {
+                (Some(&(_, variant, color, _)), _) => (variant, Some(color)),
+                (None, Some(variant)) => (variant, None),
+                (None, None) => return,
+            };
+            connection.position = Position::from_fen(rest, Some(variant)).ok();
+            match connection.position {
+                Some(ref position) => {
+                    print_position(position);
+                    if let Some(color) = color {
+                        prompt(position, color);
+                    }
+                },
+                None => *show_board = true,
+            }
+            return;
+        },
+        "end" => {
+            println!("Game over. {}", rest);
+            connection.seat = None;
+            connection.watching = None;
+            connection.position = None;
+        },
+        _ if *show_board => println!("{}", line),
+        _ => {},
+    }
+    *show_board = false;
+}
+
+// Connect again after losing the connection and take back the seat at the game, if there is one
+fn reconnect(address: &str, shared: &SharedConnection) -> Option<TcpStream> {
+    let (id, key) = {
+        let mut connection = shared.lock().unwrap();
+        connection.writer = None;
+        connection.position = None;
+        match connection.seat.take() {
+            Some((id, _, _, key)) => (id, key),
+            None => {
+                println!("The server closed the connection.");
+                return None;
+            },
+        }
+    };
+
+    println!("Lost the connection to the server, trying to get back to game {}...", id);
+    shared.lock().unwrap().reconnecting = true;
+    for _ in 0..RECONNECT_ATTEMPTS {
+        thread::sleep(RECONNECT_DELAY);
+        if shared.lock().unwrap().closing {
+            return None;
+        }
+        let stream = match TcpStream::connect(address) {
+            Ok(stream) => stream,
+            Err(_) => continue,
+        };
+        let mut connection = shared.lock().unwrap();
+        connection.writer = stream.try_clone().ok();
+        connection.reconnecting = false;
+        send(&mut connection, &format!("rejoin {} {}", id, key));
+        return Some(stream);
+    }
+    shared.lock().unwrap().reconnecting = false;
+    println!("Could not reconnect. Once the server is back, connect again and type: rejoin {} {}", id, key);
+    None
+}
+
+// Read what the server sends until the connection is closed for good
+fn read_server(address: String, mut stream: TcpStream, shared: SharedConnection) {
+    loop {
+        let mut show_board = false;
+        for line in BufReader::new(stream).lines() {
+            match line {
+                Ok(line) => handle_line(line.trim_end(), &shared, &mut show_board),
+                Err(_) => break,
+            }
+        }
+        if shared.lock().unwrap().closing {
+            return;
+        }
+        stream = match reconnect(&address, &shared) {
+            Some(stream) => stream,
+            None => return,
+        };
+    }
+}
+
+// Play on a server at the given address, until the player quits or the server is gone
+pub fn play(address: &str) {
+    let stream = match TcpStream::connect(address) {
+        Ok(stream) => stream,
+        Err(error) => {
+            println!("Cannot connect to {}: {}", address, error);
+            return;
+        },
+    };
+    let shared = Arc::new(Mutex::new(Connection {
+        writer: stream.try_clone().ok(),
+        reconnecting: false,
+        closing: false,
+        seat: None,
+        watching: None,
+        position: None,
+    }));
+    println!("Connected to {}. Type a command, or a move once the game has begun.", address);
+    let reader_shared = Arc::clone(&shared);
+    let reader_address = address.to_string();
+    let reader = thread::spawn(move || read_server(reader_address, stream, reader_shared));
+
+    loop {
+        let mut input = String::new();
+        if io::stdin().read_line(&mut input).unwrap() == 0 {
+            break;
+        }
+        let input = input.trim();
+        let command = input.split_whitespace().next().unwrap_or("");
+
+        let mut connection = shared.lock().unwrap();
+        if connection.writer.is_none() {
+            if connection.reconnecting {
+                println!("Not connected, still trying to reconnect");
+                continue;
+            }
+            break;
+        }
+        if input.is_empty() {
+            continue;
+        }
+        if command == "quit" {
+            break;
+        }
+        if command == "unwatch" {
+            connection.watching = None;
+            connection.position = None;
+        }
+        if COMMANDS.contains(&command) {
+            send(&mut connection, input);
+            continue;
+        }
+
+        // Check the move here, so a typo does not have to travel to the server and back
+        let played = match (&connection.position, &connection.seat) {
+            (Some(position), &Some((_, _, color, _))) => {
+                if position.side_to_move != color {
+                    println!("It is not your turn");
+                    continue;
+                }
+                match parse_player_move(position, input) {
+                    Some(mv) => position.move_to_string(&mv),
+                    None => {
+                        println!("Invalid move");
+                        continue;
+                    },
+                }
+            },
+            // The server can still check moves in a game the client cannot follow itself
+            (None, Some(_)) => input.to_string(),
+            (_, None) => {
+                println!("Unknown command '{}'. The commands are {}, and quit.", command, COMMANDS.join(", "));
+                continue;
+            },
+        };
+        send(&mut connection, &format!("move {}", played));
+    }
+
+    // Leave the server, so the reading thread sees the connection close and stops
+    let mut connection = shared.lock().unwrap();
+    connection.closing = true;
+    send(&mut connection, "quit");
+    if let Some(ref writer) = connection.writer {
+        let _ = writer.shutdown(Shutdown::Both);
+    }
+    drop(connection);
+    let _ = reader.join();
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::net::TcpListener;
+
+    // A client's view of the connection, before it has sat down anywhere
+    fn new_connection(writer: Option<TcpStream>) -> SharedConnection {
+        Arc::new(Mutex::new(Connection { writer, reconnecting: false, closing: false, seat: None, watching: None, position: None }))
+    }
+
+    #[test]
+    fn lines_from_the_server_follow_the_game() {
+        let shared = new_connection(None);
+        let mut show_board = false;
+        // A position means nothing before the player sits down
+        handle_line("position rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KQkq - 0 1", &shared, &mut show_board);
+        assert!(shared.lock().unwrap().position.is_none());
+
+        handle_line("game 3 kingofthehill Black 0123abcd", &shared, &mut show_board);
+        assert_eq!(shared.lock().unwrap().seat, Some((3, Variant::KingOfTheHill, Color::Black, "0123abcd".to_string())));
+        handle_line("position rnbqkbnr/pppppppp/8/8/4P3/8/PPPP1PPP/RNBQKBNR b KQkq e3 0 1", &shared, &mut show_board);
+        let position = shared.lock().unwrap().position.clone().unwrap();
+        assert_eq!(position.variant, Variant::KingOfTheHill);
+        assert_eq!(position.side_to_move, Color::Black);
+        assert!(!show_board);
+
+        // A position the client cannot read has the board the server draws after it shown instead
+        handle_line("position not a position", &shared, &mut show_board);
+        assert!(shared.lock().unwrap().position.is_none());
+        assert!(show_board);
+        handle_line("8 r n b q k b n r", &shared, &mut show_board);
+        handle_line("moved White e2e4", &shared, &mut show_board);
+        assert!(!show_board);
+
+        handle_line("end Black wins: White resigned", &shared, &mut show_board);
+        let connection = shared.lock().unwrap();
+        assert!(connection.seat.is_none() && connection.position.is_none());
+    }
+
+    #[test]
+    fn spectators_follow_the_game_they_watch() {
+        let shared = new_connection(None);
+        let mut show_board = false;
+        handle_line("watching 2 atomic 3+2 alice bob 60", &shared, &mut show_board);
+        assert_eq!(shared.lock().unwrap().watching, Some(Variant::Atomic));
+        handle_line("position rnbqkbnr/pppppppp/8/8/4P3/8/PPPP1PPP/RNBQKBNR b KQkq e3 0 1", &shared, &mut show_board);
+        assert_eq!(shared.lock().unwrap().position.as_ref().map(|position| position.variant), Some(Variant::Atomic));
+
+        // Sitting down at a game of one's own stops the watching
+        handle_line("game 4 chess White 0123abcd", &shared, &mut show_board);
+        assert_eq!(shared.lock().unwrap().watching, None);
+        handle_line("end Draw: agreed", &shared, &mut show_board);
+
+        // A malformed header watches nothing
+        handle_line("watching 2 atomic", &shared, &mut show_board);
+        handle_line("position rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KQkq - 0 1", &shared, &mut show_board);
+        let connection = shared.lock().unwrap();
+        assert!(connection.watching.is_none() && connection.position.is_none());
+    }
+
+    #[test]
+    fn malformed_game_lines_leave_no_seat() {
+        let shared = new_connection(None);
+        let mut show_board = false;
+        for line in ["game", "game 1 chess White", "game x chess White key", "game 1 chess White key extra"] {
+            handle_line(line, &shared, &mut show_board);
+            assert!(shared.lock().unwrap().seat.is_none(), "{}", line);
+        }
+        // A variant the client does not know is followed as standard chess
+        handle_line("game 1 mystery White key", &shared, &mut show_board);
+        assert_eq!(shared.lock().unwrap().seat, Some((1, Variant::Standard, Color::White, "key".to_string())));
+    }
+
+    #[test]
+    fn a_dropped_connection_rejoins_the_game() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = listener.local_addr().unwrap().to_string();
+        let stream = TcpStream::connect(&address).unwrap();
+        let shared = new_connection(stream.try_clone().ok());
+        let reader_shared = Arc::clone(&shared);
+        let reader_address = address.clone();
+        let reader = thread::spawn(move || read_server(reader_address, stream, reader_shared));
+
+        // The server seats the player, then the connection drops
+        let (mut first, _) = listener.accept().unwrap();
+        writeln!(first, "game 7 chess White 00ff00ff00ff00ff").unwrap();
+        while shared.lock().unwrap().seat.is_none() {
+            thread::sleep(Duration::from_millis(10));
+        }
+        first.shutdown(Shutdown::Both).unwrap();
+
+        // The client comes back on its own and asks for its seat with the key
+        let (second, _) = listener.accept().unwrap();
+        let mut line = String::new();
+        BufReader::new(second.try_clone().unwrap()).read_line(&mut line).unwrap();
+        assert_eq!(line.trim_end(), "rejoin 7 00ff00ff00ff00ff");
+        assert!(!shared.lock().unwrap().reconnecting);
+
+        // Once closing, a dropped connection is left alone
+        shared.lock().unwrap().closing = true;
+        second.shutdown(Shutdown::Both).unwrap();
+        reader.join().unwrap();
+    }
+}
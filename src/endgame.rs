@@ -0,0 +1,782 @@
+use std::collections::HashMap;
+use std::fs;
+use std::io;
+use std::path::Path;
+
+use crate::book::random_u64;
+use crate::position::{opponent, piece_from_char, piece_to_char, Position};
+use crate::variant::Variant;
+use crate::{empty_board, Color, Piece, PieceType};
+
+// The endgames the solver builds tables for, named by White's pieces followed by Black's.
+// They are listed so that every table comes after the smaller tables its captures and promotions lead to.
+pub const ENDGAMES: [&str; 5] = ["KQK", "KRK", "KPK", "KBNK", "KQKR"];
+
+// Byte values in a table: anything below these is the distance to mate in half-moves,
+// odd when the side to move wins and even when it gets mated
+const DRAW: u8 = 255;
+const ILLEGAL: u8 = 254;
+const UNKNOWN: u8 = 253;
+
+// Written at the start of every table file
+const MAGIC: &[u8; 4] = b"CCEG";
+const VERSION: u8 = 1;
+
+// The theoretical result of a position for the side to move
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum EndgameResult {
+    // The side to move mates in this many half-moves
+    Win(u32),
+    // The side to move gets mated in this many half-moves
+    Loss(u32),
+    Draw,
+}
+
+// Turn a table byte into a result
+fn decode_value(value: u8) -> Option<EndgameResult> {
+    match value {
+        DRAW => Some(EndgameResult::Draw),
+        ILLEGAL | UNKNOWN => None,
+        plies if plies % 2 == 1 => Some(EndgameResult::Win(plies as u32)),
+        plies => Some(EndgameResult::Loss(plies as u32)),
+    }
+}
+
+// Describe a result in words, counting full moves like chess players do
+pub fn describe_result(result: EndgameResult) -> String {
+    match result {
+        EndgameResult::Win(plies) => format!("win, mate in {}", plies.div_ceil(2)),
+        EndgameResult::Loss(0) => "loss, checkmated".to_string(),
+        EndgameResult::Loss(plies) => format!("loss, mated in {}", plies / 2),
+        EndgameResult::Draw => "draw".to_string(),
+    }
+}
+
+// Split an endgame name such as "KQKR" into its pieces, White's first
+fn parse_endgame_name(name: &str) -> Option<Vec<(Color, PieceType)>> {
+    let black_king = name.rfind('K').filter(|&i| i > 0)?;
+    let mut pieces = Vec::new();
+    for (i, c) in name.chars().enumerate() {
+        let color = if i < black_king { Color::White } else { Color::Black };
+        pieces.push((color, piece_from_char(c)?.piece_type));
+    }
+    Some(pieces)
+}
+
+// Order pieces like endgame names are written: king first, then queen, chancellor, archbishop,
+// rook, bishop, knight and pawn
+fn name_order(piece_type: &PieceType) -> usize {
+    match piece_type {
+        PieceType::King => 0,
+        PieceType::Queen => 1,
+        PieceType::Chancellor => 2,
+        PieceType::Archbishop => 3,
+        PieceType::Rook => 4,
+        PieceType::Bishop => 5,
+        PieceType::Knight => 6,
+        PieceType::Pawn => 7,
+        PieceType::Fairy(_) | PieceType::Duck => 8,
+    }
+}
+
+// Name the material on the board, e.g. "KRK", listing White's pieces first
+fn material_name(pieces: &[(Color, PieceType, usize)]) -> String {
+    let mut name = String::new();
+    for color in [Color::White, Color::Black] {
+        let mut side: Vec<&PieceType> = pieces.iter().filter(|piece| piece.0 == color).map(|piece| &piece.1).collect();
+        side.sort_by_key(|piece_type| name_order(piece_type));
+        for piece_type in side {
+            name.push(piece_to_char(&Piece { piece_type: piece_type.clone(), color: Color::White }));
+        }
+    }
+    name
+}
+
+// Apply one of the 8 symmetries of the board to a square numbered x * 8 + y
+fn transform(square: usize, symmetry: usize) -> usize {
+    let (mut x, mut y) = (square / 8, square % 8);
+    if symmetry & 1 != 0 {
+        y = 7 - y;
+    }
+    if symmetry & 2 != 0 {
+        x = 7 - x;
+    }
+    if symmetry & 4 != 0 {
+        (x, y) = (y, x);
+    }
+    x * 8 + y
+}
+
+// Index of the white king among the squares a table stores it on: the a1-d1-d4 triangle
+// when the board can be mirrored every way, or the a-d files when pawns only allow left-right mirroring
+fn king_slot(square: usize, has_pawns: bool) -> Option<usize> {
+    let (x, y) = (square / 8, square % 8);
+    if y > 3 {
+        return None;
+    }
+    if has_pawns {
+        return Some(x * 4 + y);
+    }
+    let rank = 7 - x;
+    if rank > y {
+        return None;
+    }
+    // Squares on file y come after the 1 + 2 + ... + y squares of the earlier files
+    Some(y * (y + 1) / 2 + rank)
+}
+
+// The square of a king slot, the reverse of king_slot
+fn slot_square(slot: usize, has_pawns: bool) -> usize {
+    (0..64).find(|&square| king_slot(square, has_pawns) == Some(slot)).unwrap()
+}
+
+// A solved endgame, stored compactly with the white king on one of its canonical squares
+#[derive(Debug, Clone)]
+pub struct EndgameTable {
+    pub name: String,
+    pieces: Vec<(Color, PieceType)>,
+    has_pawns: bool,
+    data: Vec<u8>,
+}
+
+impl EndgameTable {
+    // Number of squares the white king is stored on
+    fn king_slots(&self) -> usize {
+        if self.has_pawns { 32 } else { 10 }
+    }
+
+    // Index of the stored byte for squares already placed in canonical form
+    fn index(&self, slot: usize, squares: &[usize], side_to_move: Color) -> usize {
+        let mut index = if side_to_move == Color::White { 0 } else { 1 };
+        index = index * self.king_slots() + slot;
+        for &square in &squares[1..] {
+            index = index * 64 + square;
+        }
+        index
+    }
+
+    // Look up a position given the squares of the table's pieces in order
+    fn lookup(&self, squares: &[usize], side_to_move: Color) -> u8 {
+        let symmetries = if self.has_pawns { 2 } else { 8 };
+        for symmetry in 0..symmetries {
+            if let Some(slot) = king_slot(transform(squares[0], symmetry), self.has_pawns) {
+                let moved: Vec<usize> = squares.iter().map(|&square| transform(square, symmetry)).collect();
+                return self.data[self.index(slot, &moved, side_to_move)];
+            }
+        }
+        ILLEGAL
+    }
+
+    // Write the table to a file: magic, version, name length and name, then one byte per position
+    pub fn save(&self, path: &Path) -> io::Result<()> {
+        let mut bytes = Vec::with_capacity(self.data.len() + 16);
+        bytes.extend_from_slice(MAGIC);
+        bytes.push(VERSION);
+        bytes.push(self.name.len() as u8);
+        bytes.extend_from_slice(self.name.as_bytes());
+        bytes.extend_from_slice(&self.data);
+        fs::write(path, bytes)
+    }
+
+    // Read a table written by save
+    pub fn load(path: &Path) -> io::Result<EndgameTable> {
+        let invalid = |message: &str| io::Error::new(io::ErrorKind::InvalidData, format!("{}: {}", path.display(), message));
+        let bytes = fs::read(path)?;
+        if bytes.len() < 6 || &bytes[0..4] != MAGIC || bytes[4] != VERSION {
+            return Err(invalid("not an endgame table"));
+        }
+        let name_end = 6 + bytes[5] as usize;
+        let name = String::from_utf8(bytes.get(6..name_end).ok_or(invalid("truncated"))?.to_vec()).map_err(|_| invalid("bad name"))?;
+        let pieces = parse_endgame_name(&name).ok_or(invalid("bad name"))?;
+        let mut table = EndgameTable {
+            has_pawns: pieces.iter().any(|piece| piece.1 == PieceType::Pawn),
+            pieces,
+            name,
+            data: bytes[name_end..].to_vec(),
+        };
+        if table.data.len() != 2 * table.king_slots() * 64usize.pow(table.pieces.len() as u32 - 1) {
+            return Err(invalid("wrong size"));
+        }
+        table.data.shrink_to_fit();
+        Ok(table)
+    }
+}
+
+// A set of solved endgames, looked up by name
+#[derive(Debug, Clone, Default)]
+pub struct EndgameTables {
+    tables: HashMap<String, EndgameTable>,
+}
+
+impl EndgameTables {
+    pub fn new() -> EndgameTables {
+        EndgameTables { tables: HashMap::new() }
+    }
+
+    // Names of the loaded tables, in the order of ENDGAMES
+    pub fn names(&self) -> Vec<&str> {
+        ENDGAMES.iter().copied().filter(|name| self.tables.contains_key(*name)).collect()
+    }
+
+    pub fn get(&self, name: &str) -> Option<&EndgameTable> {
+        self.tables.get(name)
+    }
+
+    // Load every table file found in a directory
+    pub fn load_dir(dir: &Path) -> io::Result<EndgameTables> {
+        let mut tables = EndgameTables::new();
+        for name in ENDGAMES {
+            let path = dir.join(format!("{}.cceg", name));
+            if path.exists() {
+                let table = EndgameTable::load(&path)?;
+                tables.tables.insert(table.name.clone(), table);
+            }
+        }
+        Ok(tables)
+    }
+
+    // Probe a list of pieces, swapping colors if Black is the stronger side
+    fn probe_pieces(&self, pieces: &[(Color, PieceType, usize)], side_to_move: Color) -> u8 {
+        let name = material_name(pieces);
+        // Two kings, or a king and a single minor piece against a king, can never mate
+        if matches!(name.as_str(), "KK" | "KBK" | "KNK" | "KKB" | "KKN") {
+            return DRAW;
+        }
+
+        let mut pieces = pieces.to_vec();
+        let mut side_to_move = side_to_move;
+        if !self.tables.contains_key(&name) {
+            // Mirror the board top to bottom and swap the colors
+            for piece in pieces.iter_mut() {
+                piece.0 = opponent(piece.0);
+                piece.2 = (7 - piece.2 / 8) * 8 + piece.2 % 8;
+            }
+            side_to_move = opponent(side_to_move);
+        }
+        let table = match self.tables.get(&material_name(&pieces)) {
+            Some(table) => table,
+            None => return UNKNOWN,
+        };
+
+        // Put the squares in the order of the table's pieces
+        let squares: Vec<usize> = table
+            .pieces
+            .iter()
+            .map(|wanted| pieces.iter().find(|piece| piece.0 == wanted.0 && piece.1 == wanted.1).unwrap().2)
+            .collect();
+        table.lookup(&squares, side_to_move)
+    }
+
+    // Find the theoretical result of a position if one of the tables covers it
+    pub fn probe(&self, position: &Position) -> Option<EndgameResult> {
+        // The tables know nothing about castling or variants
+        if position.variant != Variant::Standard {
+            return None;
+        }
+        if position.white_castling != (false, false) || position.black_castling != (false, false) {
+            return None;
+        }
+        let mut pieces = Vec::new();
+        for (x, row) in position.board.iter().enumerate() {
+            for (y, square) in row.iter().enumerate() {
+                if let Some(piece) = square {
+                    pieces.push((piece.color, piece.piece_type.clone(), x * 8 + y));
+                }
+            }
+        }
+        if pieces.len() > 4 {
+            return None;
+        }
+        decode_value(self.probe_pieces(&pieces, position.side_to_move))
+    }
+}
+
+// Piece codes on the solver's own compact board
+const WHITE_PIECE: u8 = 16;
+const BLACK_PIECE: u8 = 32;
+
+fn piece_code(color: Color, piece_type: &PieceType) -> u8 {
+    let kind = match piece_type {
+        PieceType::Pawn => 1,
+        PieceType::Knight => 2,
+        PieceType::Bishop => 3,
+        PieceType::Rook => 4,
+        PieceType::Queen => 5,
+        PieceType::King => 6,
+        PieceType::Archbishop => 7,
+        PieceType::Chancellor => 8,
+        PieceType::Fairy(_) | PieceType::Duck => unreachable!("Endgame tables only hold the built-in pieces"),
+    };
+    kind | if color == Color::White { WHITE_PIECE } else { BLACK_PIECE }
+}
+
+const KNIGHT_OFFSETS: [(i32, i32); 8] = [(-2, -1), (-2, 1), (-1, -2), (-1, 2), (1, -2), (1, 2), (2, -1), (2, 1)];
+const KING_OFFSETS: [(i32, i32); 8] = [(-1, -1), (-1, 0), (-1, 1), (0, -1), (0, 1), (1, -1), (1, 0), (1, 1)];
+const ROOK_DIRECTIONS: [(i32, i32); 4] = [(-1, 0), (1, 0), (0, -1), (0, 1)];
+const BISHOP_DIRECTIONS: [(i32, i32); 4] = [(-1, -1), (-1, 1), (1, -1), (1, 1)];
+
+// Step from a square by an offset, if that stays on the board
+fn offset_square(square: usize, (dx, dy): (i32, i32)) -> Option<usize> {
+    let x = (square / 8) as i32 + dx;
+    let y = (square % 8) as i32 + dy;
+    if (0..8).contains(&x) && (0..8).contains(&y) {
+        Some((x * 8 + y) as usize)
+    } else {
+        None
+    }
+}
+
+// A list of (row, file) offsets
+type Offsets = &'static [(i32, i32)];
+
+// The offsets a piece jumps by and the directions it slides in
+fn piece_steps(piece_type: &PieceType) -> (Offsets, Offsets) {
+    match piece_type {
+        PieceType::Knight => (&KNIGHT_OFFSETS, &[]),
+        PieceType::King => (&KING_OFFSETS, &[]),
+        PieceType::Rook => (&[], &ROOK_DIRECTIONS),
+        PieceType::Bishop => (&[], &BISHOP_DIRECTIONS),
+        PieceType::Queen => (&[], &KING_OFFSETS),
+        PieceType::Archbishop => (&KNIGHT_OFFSETS, &BISHOP_DIRECTIONS),
+        PieceType::Chancellor => (&KNIGHT_OFFSETS, &ROOK_DIRECTIONS),
+        PieceType::Pawn => (&[], &[]),
+        PieceType::Fairy(_) | PieceType::Duck => unreachable!("Endgame tables only hold the built-in pieces"),
+    }
+}
+
+// Check if the piece on a square attacks the target square
+fn attacks(board: &[u8; 64], from: usize, piece_type: &PieceType, color: Color, target: usize) -> bool {
+    if piece_type == &PieceType::Pawn {
+        let dx = if color == Color::White { -1 } else { 1 };
+        return offset_square(from, (dx, -1)) == Some(target) || offset_square(from, (dx, 1)) == Some(target);
+    }
+    let (jumps, slides) = piece_steps(piece_type);
+    if jumps.iter().any(|&offset| offset_square(from, offset) == Some(target)) {
+        return true;
+    }
+    for &direction in slides {
+        let mut square = from;
+        while let Some(next) = offset_square(square, direction) {
+            if next == target {
+                return true;
+            }
+            if board[next] != 0 {
+                break;
+            }
+            square = next;
+        }
+    }
+    false
+}
+
+// The solver's view of one position: the squares of the table's pieces, some of which may have been captured
+struct Placement<'a> {
+    pieces: &'a [(Color, PieceType)],
+    squares: Vec<Option<usize>>,
+    board: [u8; 64],
+}
+
+impl<'a> Placement<'a> {
+    fn new(pieces: &'a [(Color, PieceType)], squares: &[usize]) -> Option<Placement<'a>> {
+        let mut board = [0; 64];
+        for (piece, &square) in pieces.iter().zip(squares) {
+            if board[square] != 0 {
+                return None;
+            }
+            board[square] = piece_code(piece.0, &piece.1);
+        }
+        Some(Placement { pieces, squares: squares.iter().map(|&square| Some(square)).collect(), board })
+    }
+
+    // Check if the king of a color is attacked
+    fn in_check(&self, color: Color) -> bool {
+        let king = (0..self.pieces.len())
+            .find(|&i| self.pieces[i] == (color, PieceType::King))
+            .and_then(|i| self.squares[i]);
+        let king = match king {
+            Some(king) => king,
+            None => return false,
+        };
+        self.pieces.iter().zip(&self.squares).any(|(piece, square)| match square {
+            Some(square) if piece.0 != color => attacks(&self.board, *square, &piece.1, piece.0, king),
+            _ => false,
+        })
+    }
+
+    // Move piece i to a square, capturing whatever stands there; returns the captured piece index
+    fn apply(&mut self, i: usize, to: usize) -> Option<usize> {
+        let captured = (0..self.pieces.len()).find(|&j| self.squares[j] == Some(to));
+        if let Some(j) = captured {
+            self.squares[j] = None;
+        }
+        let from = self.squares[i].unwrap();
+        self.board[to] = self.board[from];
+        self.board[from] = 0;
+        self.squares[i] = Some(to);
+        captured
+    }
+
+    // The squares piece i can move to, or with backwards set, the squares it could have come from
+    fn destinations(&self, i: usize, backwards: bool) -> Vec<usize> {
+        let (color, ref piece_type) = self.pieces[i];
+        let from = match self.squares[i] {
+            Some(square) => square,
+            None => return Vec::new(),
+        };
+        let mut targets = Vec::new();
+        let own = if color == Color::White { WHITE_PIECE } else { BLACK_PIECE };
+        // Retracted moves never capture, so they need an empty square
+        let open = |square: usize| self.board[square] == 0 || (!backwards && self.board[square] & own == 0);
+
+        if piece_type == &PieceType::Pawn {
+            let forward = if (color == Color::White) != backwards { -1 } else { 1 };
+            let x = from / 8;
+            let home_x = if color == Color::White { 6 } else { 1 };
+            if let Some(one) = offset_square(from, (forward, 0)) {
+                // A pawn can never have stood on its first rank
+                let first_rank = if color == Color::White { 7 } else { 0 };
+                if self.board[one] == 0 && !(backwards && one / 8 == first_rank) {
+                    targets.push(one);
+                    let double_x = if backwards { home_x - 2 * forward } else { home_x };
+                    if x as i32 == double_x {
+                        if let Some(two) = offset_square(one, (forward, 0)) {
+                            if self.board[two] == 0 {
+                                targets.push(two);
+                            }
+                        }
+                    }
+                }
+            }
+            if !backwards {
+                for dy in [-1, 1] {
+                    if let Some(square) = offset_square(from, (forward, dy)) {
+                        if self.board[square] != 0 && self.board[square] & own == 0 {
+                            targets.push(square);
+                        }
+                    }
+                }
+            }
+            return targets;
+        }
+
+        let (jumps, slides) = piece_steps(piece_type);
+        for &offset in jumps {
+            if let Some(square) = offset_square(from, offset) {
+                if open(square) {
+                    targets.push(square);
+                }
+            }
+        }
+        for &direction in slides {
+            let mut square = from;
+            while let Some(next) = offset_square(square, direction) {
+                if open(next) {
+                    targets.push(next);
+                }
+                if self.board[next] != 0 {
+                    break;
+                }
+                square = next;
+            }
+        }
+        targets
+    }
+
+    // The pieces left on the board with their squares
+    fn remaining(&self) -> Vec<(Color, PieceType, usize)> {
+        self.pieces
+            .iter()
+            .zip(&self.squares)
+            .filter_map(|(piece, square)| square.map(|square| (piece.0, piece.1.clone(), square)))
+            .collect()
+    }
+}
+
+// Index into the full, unreduced table used while solving
+fn full_index(squares: &[usize], side_to_move: Color) -> usize {
+    let mut index = if side_to_move == Color::White { 0 } else { 1 };
+    for &square in squares {
+        index = index * 64 + square;
+    }
+    index
+}
+
+// The squares and side to move of a full table index
+fn full_position(index: usize, count: usize) -> (Vec<usize>, Color) {
+    let mut squares = vec![0; count];
+    let mut rest = index;
+    for square in squares.iter_mut().rev() {
+        *square = rest % 64;
+        rest /= 64;
+    }
+    (squares, if rest == 0 { Color::White } else { Color::Black })
+}
+
+// The solver's bookkeeping for one position
+#[derive(Clone, Copy)]
+struct SolveState {
+    value: u8,
+    // Moves staying inside the table that are not yet known to lose
+    remaining: u8,
+    // Fastest win by capturing or promoting into a smaller table
+    conversion_win: u8,
+    // Slowest loss by capturing or promoting into a smaller table
+    conversion_loss: u8,
+    // Whether a capture or promotion reaches a drawn smaller table
+    conversion_draw: bool,
+}
+
+// Solve an endgame by retrograde analysis, using the already solved smaller tables for captures and promotions
+pub fn solve(name: &str, smaller: &EndgameTables) -> Option<EndgameTable> {
+    let pieces = parse_endgame_name(name)?;
+    let count = pieces.len();
+    let size = 2 * 64usize.pow(count as u32);
+    let blank = SolveState { value: ILLEGAL, remaining: 0, conversion_win: UNKNOWN, conversion_loss: 0, conversion_draw: false };
+    let mut states = vec![blank; size];
+
+    // Look at every position once: find the illegal ones, the mates and stalemates, and the
+    // results reached straight away by captures and promotions
+    for (index, state) in states.iter_mut().enumerate() {
+        let (squares, side_to_move) = full_position(index, count);
+        let placement = match Placement::new(&pieces, &squares) {
+            Some(placement) => placement,
+            None => continue,
+        };
+        let pawn_on_last_rank = pieces.iter().zip(&squares).any(|(piece, square)| piece.1 == PieceType::Pawn && (square / 8 == 0 || square / 8 == 7));
+        if pawn_on_last_rank || placement.in_check(opponent(side_to_move)) {
+            continue;
+        }
+
+        let mut legal_moves = 0;
+        state.value = UNKNOWN;
+        for i in (0..count).filter(|&i| pieces[i].0 == side_to_move) {
+            for to in placement.destinations(i, false) {
+                let mut next = Placement::new(&pieces, &squares).unwrap();
+                let captured = next.apply(i, to);
+                if next.in_check(side_to_move) {
+                    continue;
+                }
+                legal_moves += 1;
+
+                let promotes = pieces[i].1 == PieceType::Pawn && (to / 8 == 0 || to / 8 == 7);
+                if captured.is_none() && !promotes {
+                    state.remaining += 1;
+                    continue;
+                }
+
+                // Captures and promotions leave this table, so their result comes from a smaller one
+                let mut outcomes = Vec::new();
+                if promotes {
+                    for promotion in [PieceType::Queen, PieceType::Rook, PieceType::Bishop, PieceType::Knight] {
+                        let mut remaining = next.remaining();
+                        remaining.iter_mut().find(|piece| piece.2 == to).unwrap().1 = promotion;
+                        outcomes.push(smaller.probe_pieces(&remaining, opponent(side_to_move)));
+                    }
+                } else {
+                    outcomes.push(smaller.probe_pieces(&next.remaining(), opponent(side_to_move)));
+                }
+                for outcome in outcomes {
+                    match outcome {
+                        DRAW => state.conversion_draw = true,
+                        UNKNOWN | ILLEGAL => return None,
+                        plies if plies % 2 == 0 => state.conversion_win = state.conversion_win.min(plies + 1),
+                        plies => state.conversion_loss = state.conversion_loss.max(plies + 1),
+                    }
+                }
+            }
+        }
+
+        if legal_moves == 0 {
+            state.value = if placement.in_check(side_to_move) { 0 } else { DRAW };
+        } else if state.remaining == 0 && state.conversion_win == UNKNOWN {
+            state.value = if state.conversion_draw { DRAW } else { state.conversion_loss };
+        }
+    }
+
+    // Work outwards one half-move at a time from the positions already decided
+    for plies in 0..UNKNOWN - 1 {
+        let mut progress = false;
+        for index in 0..size {
+            let state = &mut states[index];
+            if state.value == UNKNOWN && state.conversion_win == plies {
+                state.value = plies;
+            }
+            if state.value != plies {
+                continue;
+            }
+            progress = true;
+
+            // Retract every move of the side that just moved to find the positions leading here
+            let (squares, side_to_move) = full_position(index, count);
+            let placement = Placement::new(&pieces, &squares).unwrap();
+            let mover = opponent(side_to_move);
+            for i in (0..count).filter(|&i| pieces[i].0 == mover) {
+                for from in placement.destinations(i, true) {
+                    let mut previous = squares.clone();
+                    previous[i] = from;
+                    let previous = &mut states[full_index(&previous, mover)];
+                    if previous.value != UNKNOWN {
+                        continue;
+                    }
+                    if plies % 2 == 0 {
+                        // Moving into a lost position for the opponent wins
+                        previous.value = plies + 1;
+                    } else {
+                        // Every move into a won position for the opponent loses; when none are left, so does this position
+                        previous.remaining -= 1;
+                        if previous.remaining == 0 && previous.conversion_win == UNKNOWN && !previous.conversion_draw {
+                            previous.value = (plies + 1).max(previous.conversion_loss);
+                        }
+                    }
+                }
+            }
+        }
+        let pending = states.iter().any(|state| state.value > plies && state.value < UNKNOWN || state.value == UNKNOWN && state.conversion_win > plies && state.conversion_win < UNKNOWN);
+        if !progress && !pending {
+            break;
+        }
+    }
+
+    // Keep only the positions with the white king on its canonical squares
+    let has_pawns = pieces.iter().any(|piece| piece.1 == PieceType::Pawn);
+    let mut table = EndgameTable { name: name.to_string(), pieces: pieces.clone(), has_pawns, data: Vec::new() };
+    let others = 64usize.pow(count as u32 - 1);
+    table.data = vec![ILLEGAL; 2 * table.king_slots() * others];
+    for side_to_move in [Color::White, Color::Black] {
+        for slot in 0..table.king_slots() {
+            for rest in 0..others {
+                let (mut squares, _) = full_position(rest, count - 1);
+                squares.insert(0, slot_square(slot, has_pawns));
+                let value = match states[full_index(&squares, side_to_move)].value {
+                    UNKNOWN => DRAW,
+                    value => value,
+                };
+                let index = table.index(slot, &squares, side_to_move);
+                table.data[index] = value;
+            }
+        }
+    }
+
+    Some(table)
+}
+
+// Load the tables in a directory for an engine option, where an empty path means no tables
+pub fn load_endgames(dir: &str) -> Result<EndgameTables, String> {
+    if dir.is_empty() || dir == "<empty>" {
+        return Ok(EndgameTables::new());
+    }
+    EndgameTables::load_dir(Path::new(dir)).map_err(|error| format!("Could not load endgame tables from {}: {}", dir, error))
+}
+
+// Command line tool: solve all the endgames and write the tables to a directory
+pub fn generate_command(args: &[String]) -> Result<(), String> {
+    let dir = Path::new(args.first().map(String::as_str).unwrap_or("endgames"));
+    fs::create_dir_all(dir).map_err(|error| format!("Could not create {}: {}", dir.display(), error))?;
+
+    let mut tables = EndgameTables::new();
+    for name in ENDGAMES {
+        println!("Solving {}...", name);
+        let table = solve(name, &tables).ok_or(format!("Could not solve {}", name))?;
+        let longest = table.data.iter().filter(|&&value| value < UNKNOWN).max().copied().unwrap_or(0);
+        let path = dir.join(format!("{}.cceg", name));
+        table.save(&path).map_err(|error| format!("Could not write {}: {}", path.display(), error))?;
+        println!("Wrote {} ({} positions, longest mate {} half-moves)", path.display(), table.data.len(), longest);
+        tables.tables.insert(name.to_string(), table);
+    }
+    Ok(())
+}
+
+// Pick a random position from a table where White to move wins in at least min_plies half-moves
+pub fn random_winning_position(table: &EndgameTable, min_plies: u8) -> Option<Position> {
+    let others = 64usize.pow(table.pieces.len() as u32 - 1);
+    let candidates = table.king_slots() * others;
+    let longest = table.data[..candidates].iter().filter(|&&value| value < UNKNOWN && value % 2 == 1).max().copied()?;
+    let min_plies = min_plies.min(longest);
+
+    // Pick random positions until one fits, which never takes long since most are wins
+    loop {
+        let index = (random_u64() % candidates as u64) as usize;
+        let value = table.data[index];
+        if value >= UNKNOWN || value.is_multiple_of(2) || value < min_plies {
+            continue;
+        }
+        let (mut squares, _) = full_position(index % others, table.pieces.len() - 1);
+        squares.insert(0, slot_square(index / others, table.has_pawns));
+
+        let mut position = Position::new();
+        position.board = empty_board();
+        for (piece, square) in table.pieces.iter().zip(&squares) {
+            position.board[square / 8][square % 8] = Some(Piece { piece_type: piece.1.clone(), color: piece.0 });
+        }
+        position.white_castling = (false, false);
+        position.black_castling = (false, false);
+        return Some(position);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // The longest win for White to move in a table, in half-moves
+    fn longest_win(table: &EndgameTable) -> u8 {
+        let candidates = table.king_slots() * 64usize.pow(table.pieces.len() as u32 - 1);
+        table.data[..candidates].iter().copied().filter(|&value| value < UNKNOWN && value % 2 == 1).max().unwrap()
+    }
+
+    #[test]
+    fn three_piece_endgames() {
+        let mut tables = EndgameTables::new();
+        for name in ["KQK", "KRK", "KPK"] {
+            let table = solve(name, &tables).unwrap();
+            tables.tables.insert(name.to_string(), table);
+        }
+        // The longest wins are known to be mate in 10, 16 and 28
+        assert_eq!(longest_win(tables.get("KQK").unwrap()), 19);
+        assert_eq!(longest_win(tables.get("KRK").unwrap()), 31);
+        assert_eq!(longest_win(tables.get("KPK").unwrap()), 55);
+
+        let probe = |fen: &str| tables.probe(&Position::from_fen(fen, None).unwrap());
+        assert_eq!(probe("k7/8/1K6/8/8/8/7Q/8 w - - 0 1"), Some(EndgameResult::Win(1)));
+        assert_eq!(probe("k7/8/1K6/8/8/8/8/7R w - - 0 1"), Some(EndgameResult::Win(1)));
+        assert_eq!(probe("k7/1Q6/1K6/8/8/8/8/8 b - - 0 1"), Some(EndgameResult::Loss(0)));
+        assert_eq!(probe("k7/2Q5/1K6/8/8/8/8/8 b - - 0 1"), Some(EndgameResult::Draw));
+        // Black's queen is looked up with the colors swapped
+        assert_eq!(probe("6q1/8/8/8/8/8/2k5/K7 b - - 0 1"), Some(EndgameResult::Win(1)));
+        // A pawn can never stand on its first rank
+        assert_eq!(probe("k7/8/1K6/8/8/8/8/7P w - - 0 1"), None);
+
+        // The king on the sixth rank in front of its pawn wins whoever is to move, except when
+        // the pawn has gone on and stalemated the defender
+        assert_eq!(probe("4k3/8/4K3/4P3/8/8/8/8 w - - 0 1"), Some(EndgameResult::Win(21)));
+        assert_eq!(probe("4k3/8/4K3/4P3/8/8/8/8 b - - 0 1"), Some(EndgameResult::Loss(24)));
+        assert_eq!(probe("4k3/4P3/4K3/8/8/8/8/8 b - - 0 1"), Some(EndgameResult::Draw));
+        // A rook pawn cannot drive the defending king out of the corner
+        assert_eq!(probe("k7/8/K7/P7/8/8/8/8 w - - 0 1"), Some(EndgameResult::Draw));
+        assert_eq!(probe("8/8/8/8/8/8/4P3/4K2k w - - 0 1"), Some(EndgameResult::Win(23)));
+    }
+
+    // Solving the four-piece endings takes a few minutes even in release mode, so run these
+    // with: cargo test --release -- --ignored
+    #[test]
+    #[ignore]
+    fn four_piece_endgames() {
+        let mut tables = EndgameTables::new();
+        for name in ENDGAMES {
+            let table = solve(name, &tables).unwrap();
+            tables.tables.insert(name.to_string(), table);
+        }
+        // Mating with bishop and knight takes up to 33 moves, winning the rook with the queen up to 35
+        assert_eq!(longest_win(tables.get("KBNK").unwrap()), 65);
+        assert_eq!(longest_win(tables.get("KQKR").unwrap()), 69);
+
+        let probe = |fen: &str| tables.probe(&Position::from_fen(fen, None).unwrap());
+        assert_eq!(probe("7k/8/6KN/8/8/8/8/2B5 w - - 0 1"), Some(EndgameResult::Win(1)));
+        assert_eq!(probe("7k/8/6K1/5B2/8/8/8/N7 w - - 0 1"), Some(EndgameResult::Win(47)));
+        assert_eq!(probe("8/8/8/8/8/8/8/KBNk4 w - - 0 1"), Some(EndgameResult::Win(45)));
+        assert_eq!(probe("3k4/8/3K4/8/8/8/8/r6Q w - - 0 1"), Some(EndgameResult::Win(1)));
+        assert_eq!(probe("8/8/8/3k4/8/8/1r6/KQ6 w - - 0 1"), Some(EndgameResult::Win(17)));
+        // Black to move takes the queen and wins with the rook
+        assert_eq!(probe("k7/8/1K6/8/8/8/8/rQ6 b - - 0 1"), Some(EndgameResult::Win(29)));
+    }
+}
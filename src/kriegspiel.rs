@@ -0,0 +1,372 @@
+use std::io::{self, Write};
+
+use crate::position::{parse_square, piece_from_char, square_to_string, Move, Position};
+use crate::rules::{find_king, is_valid_move};
+use crate::variant::{outcome, Outcome};
+use crate::{print_board, print_board_view, Color, PieceType};
+
+// In both modes a player only sees part of the board, and a referee who sees everything decides
+// whether their moves are legal:
+//
+// - Kriegspiel: a player sees only their own pieces. The referee rejects illegal moves, after which
+//   the player tries another, and announces every capture (and whether a pawn or a piece was
+//   taken) and every check (and the line it comes from). The game ends in checkmate or stalemate
+//   as usual.
+// - Dark chess: a player sees their own pieces and every square they can move to. There is no
+//   check, so the king may walk into an attack, and the game is won by capturing the enemy king.
+
+// The two ways of hiding the opponent's pieces
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum FogMode {
+    Kriegspiel,
+    DarkChess,
+}
+
+// Describe the line a check comes from, as the referee announces it, on a board of the given ranks and files
+fn check_direction(attacker: &PieceType, from: (usize, usize), king: (usize, usize), size: (usize, usize)) -> &'static str {
+    if *attacker == PieceType::Knight {
+        "a knight"
+    } else if from.0 == king.0 {
+        "the rank"
+    } else if from.1 == king.1 {
+        "the file"
+    } else {
+        // The two diagonals through the king's square have different lengths
+        let (ranks, files) = size;
+        let squares = || (0..ranks).flat_map(move |x| (0..files).map(move |y| (x, y)));
+        let falling = squares().filter(|&(x, y)| x + king.1 == y + king.0).count();
+        let rising = squares().filter(|&(x, y)| x + y == king.0 + king.1).count();
+        let on_falling = from.0 + king.1 == from.1 + king.0;
+        if on_falling == (falling > rising) { "the long diagonal" } else { "the short diagonal" }
+    }
+}
+
+// The referee sees the whole game and tells the players only what the rules let them know
+pub struct Referee {
+    pub position: Position,
+    pub mode: FogMode,
+    pub result: Option<Outcome>,
+}
+
+impl Referee {
+    pub fn new(mode: FogMode) -> Referee {
+        Referee { position: Position::new(), mode, result: None }
+    }
+
+    // Get the moves the player to move may make. In Dark chess the king can move into an attack,
+    // since the game is won by taking it.
+    fn allowed_moves(&self) -> Vec<Move> {
+        match self.mode {
+            FogMode::Kriegspiel => self.position.legal_moves(),
+            FogMode::DarkChess => self.position.pseudo_legal_moves(),
+        }
+    }
+
+    // Answer a player's question whether a move is legal, without playing it
+    pub fn is_legal(&self, mv: &Move) -> bool {
+        self.result.is_none() && self.allowed_moves().contains(mv)
+    }
+
+    // Play a move if it is legal, returning what the referee announces to both players
+    pub fn attempt(&mut self, mv: &Move) -> Result<Vec<String>, String> {
+        if !self.is_legal(mv) {
+            return Err("Illegal move".to_string());
+        }
+        let color = self.position.side_to_move;
+        let mut announcements = vec![format!("{:?} has moved", color)];
+
+        // Captures are announced by square, saying only whether a pawn or a piece was taken
+        if self.position.is_capture(mv) {
+            let square = if self.position.board[mv.end.0][mv.end.1].is_some() { mv.end } else { (mv.start.0, mv.end.1) };
+            let taken = match self.position.board[square.0][square.1] {
+                Some(ref piece) if piece.piece_type == PieceType::Pawn => "Pawn",
+                _ => "Piece",
+            };
+            announcements.push(format!("{} captured on {}", taken, square_to_string(square, self.position.board.len())));
+        }
+        self.position.make_move(mv);
+
+        let defender = self.position.side_to_move;
+        match self.mode {
+            FogMode::Kriegspiel => {
+                announcements.extend(self.checks(defender));
+                self.result = outcome(&self.position);
+            },
+            FogMode::DarkChess => {
+                self.result = if find_king(&self.position.board, defender).is_none() {
+                    Some(Outcome::Win(color, format!("{:?} captured the king", color)))
+                } else if self.allowed_moves().is_empty() {
+                    Some(Outcome::Draw(format!("{:?} has no move", defender)))
+                } else {
+                    None
+                };
+            },
+        }
+        match self.result {
+            Some(Outcome::Win(winner, ref reason)) => announcements.push(format!("{:?} wins: {}", winner, reason)),
+            Some(Outcome::Draw(ref reason)) => announcements.push(format!("Draw: {}", reason)),
+            None => {},
+        }
+        Ok(announcements)
+    }
+
+    // Announce each piece giving check to the king of the given color
+    fn checks(&self, color: Color) -> Vec<String> {
+        let board = &self.position.board;
+        let king = match find_king(board, color) {
+            Some(king) => king,
+            None => return Vec::new(),
+        };
+        let mut checks = Vec::new();
+        for (x, row) in board.iter().enumerate() {
+            for (y, square) in row.iter().enumerate() {
+                if let Some(piece) = square {
+                    if piece.color != color && is_valid_move(board, piece, (x, y), king) {
+                        let size = (board.len(), row.len());
+                        checks.push(format!("Check from {}", check_direction(&piece.piece_type, (x, y), king, size)));
+                    }
+                }
+            }
+        }
+        checks
+    }
+
+    // Get the squares a player can see: their own pieces, and in Dark chess every square they can move to
+    pub fn visible_squares(&self, color: Color) -> Vec<Vec<bool>> {
+        let board = &self.position.board;
+        let mut visible = vec![vec![false; board[0].len()]; board.len()];
+        for (x, row) in board.iter().enumerate() {
+            for (y, square) in row.iter().enumerate() {
+                visible[x][y] = matches!(square, Some(piece) if piece.color == color);
+            }
+        }
+        if self.mode == FogMode::DarkChess {
+            let mut own_turn = self.position.clone();
+            own_turn.side_to_move = color;
+            for mv in own_turn.pseudo_legal_moves() {
+                visible[mv.end.0][mv.end.1] = true;
+            }
+        }
+        visible
+    }
+}
+
+// Parse a move attempt such as "e2e4", "e2 e4" or "e7e8n". Pawns reaching the last rank become
+// queens unless another piece is asked for.
+fn parse_attempt(position: &Position, text: &str) -> Option<Move> {
+    let compact: String = text.split_whitespace().collect();
+    if compact.len() != 4 && compact.len() != 5 {
+        return None;
+    }
+    let ranks = position.board.len();
+    let start = parse_square(compact.get(0..2)?, ranks)?;
+    let end = parse_square(compact.get(2..4)?, ranks)?;
+    let mut promotion = match compact.chars().nth(4) {
+        Some(c) => Some(piece_from_char(c)?.piece_type),
+        None => None,
+    };
+    let last_rank = if position.side_to_move == Color::White { 0 } else { ranks - 1 };
+    if promotion.is_none() && end.0 == last_rank && matches!(position.board[start.0].get(start.1), Some(Some(piece)) if piece.piece_type == PieceType::Pawn) {
+        promotion = Some(PieceType::Queen);
+    }
+    Some(Move { start, end, promotion, drop: None, duck: None, gating: None })
+}
+
+// Blank the terminal so the next player cannot see what the previous one saw
+fn clear_screen() {
+    print!("\x1B[2J\x1B[H");
+    io::stdout().flush().unwrap();
+}
+
+// Wait for the player to press Enter, returning false at the end of input
+fn wait_for_enter(prompt: &str) -> bool {
+    print!("{}", prompt);
+    io::stdout().flush().unwrap();
+    let mut input = String::new();
+    io::stdin().read_line(&mut input).unwrap() > 0
+}
+
+// Play a hot-seat game with the screen blanked between turns, so each player only sees their own view
+pub fn play() {
+    println!("1. Kriegspiel (you see only your own pieces)");
+    println!("2. Dark chess (you also see the squares your pieces can move to)");
+    print!("Enter your choice: ");
+    io::stdout().flush().unwrap();
+    let mut input = String::new();
+    io::stdin().read_line(&mut input).unwrap();
+    let mode = match input.trim() {
+        "1" => FogMode::Kriegspiel,
+        "2" => FogMode::DarkChess,
+        _ => {
+            println!("Invalid choice");
+            return;
+        }
+    };
+
+    let mut referee = Referee::new(mode);
+    // What the player to move has not heard yet from the referee
+    let mut news: Vec<String> = Vec::new();
+    while referee.result.is_none() {
+        let color = referee.position.side_to_move;
+        clear_screen();
+        if !wait_for_enter(&format!("Pass the terminal to {:?} and press Enter", color)) {
+            return;
+        }
+        for line in news.drain(..) {
+            println!("Referee: {}", line);
+        }
+        print_board_view(&referee.position.board, &referee.visible_squares(color));
+
+        let mut illegal = 0;
+        loop {
+            print!("{:?}, enter your move (e.g. e2e4): ", color);
+            io::stdout().flush().unwrap();
+            let mut input = String::new();
+            if io::stdin().read_line(&mut input).unwrap() == 0 {
+                return;
+            }
+            let attempt = match parse_attempt(&referee.position, input.trim()) {
+                Some(mv) => mv,
+                None => {
+                    println!("Invalid input");
+                    continue;
+                },
+            };
+            match referee.attempt(&attempt) {
+                Ok(announcements) => {
+                    // The opponent hears about the rejected tries too
+                    if illegal > 0 {
+                        news.push(format!("{:?} tried {} illegal move(s)", color, illegal));
+                    }
+                    for line in &announcements {
+                        println!("Referee: {}", line);
+                    }
+                    news.extend(announcements);
+                    break;
+                },
+                Err(error) => {
+                    illegal += 1;
+                    println!("Referee: {}", error);
+                },
+            }
+        }
+
+        if !wait_for_enter("Press Enter to end your turn") {
+            return;
+        }
+    }
+
+    clear_screen();
+    for line in news {
+        println!("Referee: {}", line);
+    }
+    print_board(&referee.position.board);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Set up a referee for a position given as FEN
+    fn set_up(mode: FogMode, fen: &str) -> Referee {
+        let mut referee = Referee::new(mode);
+        referee.position = Position::from_fen(fen, None).unwrap();
+        referee
+    }
+
+    // Try a move written as its squares and get what the referee announces
+    fn attempt(referee: &mut Referee, text: &str) -> Result<Vec<String>, String> {
+        let mv = parse_attempt(&referee.position, text).unwrap();
+        referee.attempt(&mv)
+    }
+
+    #[test]
+    fn captures_are_announced_by_square() {
+        let mut referee = set_up(FogMode::Kriegspiel, "4k3/8/8/3pP3/8/8/8/4K3 w - d6 0 1");
+        // En passant takes the pawn beside the capturing pawn, not on the square it moves to
+        assert_eq!(attempt(&mut referee, "e5d6").unwrap(), ["White has moved", "Pawn captured on d5"]);
+
+        let mut referee = set_up(FogMode::Kriegspiel, "4k3/8/8/3n4/4P3/8/8/4K3 w - - 0 1");
+        assert_eq!(attempt(&mut referee, "e4d5").unwrap(), ["White has moved", "Piece captured on d5"]);
+        assert_eq!(attempt(&mut referee, "e8e7").unwrap(), ["Black has moved"]);
+    }
+
+    #[test]
+    fn checks_are_announced_by_direction() {
+        for (fen, mv, check) in [
+            ("4k3/8/8/8/8/8/8/R3K3 w - - 0 1", "a1a8", "Check from the rank"),
+            ("4k3/8/8/8/8/8/8/K6R w - - 0 1", "h1e1", "Check from the file"),
+            ("4k3/8/8/8/8/8/8/3BK3 w - - 0 1", "d1a4", "Check from the long diagonal"),
+            ("4k3/8/8/8/8/8/8/3BK3 w - - 0 1", "d1h5", "Check from the short diagonal"),
+            ("4k3/8/8/8/4N3/8/8/4K3 w - - 0 1", "e4d6", "Check from a knight"),
+        ] {
+            let mut referee = set_up(FogMode::Kriegspiel, fen);
+            assert_eq!(attempt(&mut referee, mv).unwrap(), ["White has moved", check], "{} in {}", mv, fen);
+        }
+
+        // A discovered double check is announced once for each piece
+        let mut referee = set_up(FogMode::Kriegspiel, "4k3/8/8/8/4N3/8/8/4RK2 w - - 0 1");
+        assert_eq!(attempt(&mut referee, "e4d6").unwrap(), ["White has moved", "Check from a knight", "Check from the file"]);
+    }
+
+    #[test]
+    fn illegal_moves_are_rejected_without_being_played() {
+        let mut referee = set_up(FogMode::Kriegspiel, "4k3/8/8/8/8/8/4r3/4K3 w - - 0 1");
+        let fen = referee.position.to_fen();
+        // The king may not step into check, but may take the checking rook or step away
+        assert!(!referee.is_legal(&parse_attempt(&referee.position, "e1d2").unwrap()));
+        assert!(!referee.is_legal(&parse_attempt(&referee.position, "e1f2").unwrap()));
+        assert!(referee.is_legal(&parse_attempt(&referee.position, "e1e2").unwrap()));
+        assert!(referee.is_legal(&parse_attempt(&referee.position, "e1f1").unwrap()));
+        assert_eq!(attempt(&mut referee, "e1d2"), Err("Illegal move".to_string()));
+        assert_eq!(referee.position.to_fen(), fen);
+
+        // The game ends in checkmate, after which nothing is legal
+        let mut referee = set_up(FogMode::Kriegspiel, "k7/8/1K6/8/8/8/8/7R w - - 0 1");
+        let announcements = attempt(&mut referee, "h1h8").unwrap();
+        assert_eq!(announcements[1], "Check from the rank");
+        assert!(announcements[2].starts_with("White wins"));
+        assert!(!referee.is_legal(&parse_attempt(&referee.position, "a8a7").unwrap()));
+    }
+
+    #[test]
+    fn dark_chess_is_won_by_taking_the_king() {
+        // Without check the king may walk next to the enemy king
+        let mut referee = set_up(FogMode::DarkChess, "8/8/8/3k4/8/4K3/8/8 w - - 0 1");
+        assert_eq!(attempt(&mut referee, "e3e4").unwrap(), ["White has moved"]);
+        let announcements = attempt(&mut referee, "d5e4").unwrap();
+        assert_eq!(announcements, ["Black has moved", "Piece captured on e4", "Black wins: Black captured the king"]);
+        assert_eq!(referee.result, Some(Outcome::Win(Color::Black, "Black captured the king".to_string())));
+    }
+
+    // Count the squares a player can see
+    fn count(visible: &[Vec<bool>]) -> usize {
+        visible.iter().flatten().filter(|&&seen| seen).count()
+    }
+
+    #[test]
+    fn players_see_their_own_view_of_the_board() {
+        // In Kriegspiel each player sees only their own sixteen pieces
+        let kriegspiel = Referee::new(FogMode::Kriegspiel);
+        let white = kriegspiel.visible_squares(Color::White);
+        let black = kriegspiel.visible_squares(Color::Black);
+        assert_eq!((count(&white), count(&black)), (16, 16));
+        assert!(white[7][4] && white[6][0] && !white[0][4] && !white[5][0]);
+        assert!(black[0][4] && !black[7][4]);
+
+        // In Dark chess they also see the squares their pieces can move to, whoever is to move
+        let dark = Referee::new(FogMode::DarkChess);
+        let white = dark.visible_squares(Color::White);
+        let black = dark.visible_squares(Color::Black);
+        assert_eq!((count(&white), count(&black)), (32, 32));
+        assert!(white[4][4] && white[5][5] && !white[3][4]);
+        assert!(black[3][4] && !black[4][4]);
+
+        // A blocked pawn does not see the piece in front of it, but does see a piece it could take
+        let dark = set_up(FogMode::DarkChess, "4k3/8/8/3pn3/3P4/8/8/4K3 w - - 0 1");
+        let white = dark.visible_squares(Color::White);
+        assert!(!white[3][3] && white[3][4] && !white[2][3]);
+        let black = dark.visible_squares(Color::Black);
+        assert!(black[5][3] && black[5][5] && !black[4][3] && !black[7][4]);
+    }
+}
@@ -1,5 +1,29 @@
+mod api;
+mod betza;
+mod book;
+mod broadcast;
+mod bughouse;
+mod client;
+mod endgame;
+mod kriegspiel;
+mod matchmaking;
+mod position;
 mod rules;
-use crate::rules::is_valid_move;
+mod search;
+mod server;
+mod synchronous;
+mod syzygy;
+mod uci;
+mod variant;
+mod variant_file;
+mod websocket;
+mod xboard;
+use std::sync::Arc;
+
+use crate::betza::FairyPiece;
+use crate::position::Position;
+use crate::rules::{is_checkmate, is_stalemate, is_valid_move};
+use crate::syzygy::{describe_position, load_syzygy, SyzygyTables};
 
 
 #[derive(Debug, Clone, PartialEq)]
@@ -10,6 +34,15 @@ pub enum PieceType {
     Bishop,
     Queen,
     King,
+    // The fairy pieces of Capablanca chess and its relatives: the archbishop moves like a bishop
+    // or a knight, and the chancellor like a rook or a knight
+    Archbishop,
+    Chancellor,
+    // A piece defined at runtime by Betza notation
+    Fairy(Arc<FairyPiece>),
+    // The duck of Duck chess, which belongs to neither player: it blocks the squares it stands on,
+    // cannot be captured and is only ever moved as the second part of a move
+    Duck,
 }
 
 #[derive(Debug, Clone, PartialEq, Copy)]
@@ -24,26 +57,45 @@ pub struct Piece {
     color: Color,
 }
 
-// Define a type alias for the chess board, which is an 8x8 array of optional pieces
+// Define a type alias for the chess board, which is an array of ranks of optional pieces. The
+// board is usually 8x8, and its size is taken from the array wherever it matters.
 pub type Board = Vec<Vec<Option<Piece>>>;
 
 // Define a function to create an empty chess board
 fn empty_board() -> Board {
-    vec![vec![None; 8]; 8]
+    sized_board(8, 8)
 }
 
-// Define a function to create a chess board with the starting position
-fn starting_position() -> Board {
-    let mut board = empty_board();
+// Create an empty board with the given number of ranks and files
+fn sized_board(ranks: usize, files: usize) -> Board {
+    vec![vec![None; files]; ranks]
+}
+
+// Create a starting position with the given pieces on the back rows and pawns in front of them,
+// on a board with as many files as there are pieces
+fn position_with_back_row(ranks: usize, back_row: &[PieceType]) -> Board {
+    let mut board = sized_board(ranks, back_row.len());
 
     // Place black pawns on the second row
-    for i in 0..8 {
-        board[1][i] = Some(Piece { piece_type: PieceType::Pawn, color: Color::Black });
-        board[6][i] = Some(Piece { piece_type: PieceType::Pawn, color: Color::White });
+    for square in board[1].iter_mut() {
+        *square = Some(Piece { piece_type: PieceType::Pawn, color: Color::Black });
+    }
+    for square in board[ranks - 2].iter_mut() {
+        *square = Some(Piece { piece_type: PieceType::Pawn, color: Color::White });
     }
 
     // Place black and white back row pieces in their starting positions
-    let back_row = [
+    for (i, piece_type) in back_row.iter().enumerate() {
+        board[0][i] = Some(Piece { piece_type: piece_type.clone(), color: Color::Black });
+        board[ranks - 1][i] = Some(Piece { piece_type: piece_type.clone(), color: Color::White });
+    }
+
+    board
+}
+
+// Define a function to create a chess board with the starting position
+fn starting_position() -> Board {
+    position_with_back_row(8, &[
         PieceType::Rook,
         PieceType::Knight,
         PieceType::Bishop,
@@ -52,16 +104,122 @@ fn starting_position() -> Board {
         PieceType::Bishop,
         PieceType::Knight,
         PieceType::Rook,
-    ];
+    ])
+}
 
-    for (i, piece_type) in back_row.iter().enumerate() {
-        board[0][i] = Some(Piece { piece_type: piece_type.clone(), color: Color::Black });
-        board[7][i] = Some(Piece { piece_type: piece_type.clone(), color: Color::White });
+// Create the starting position of Capablanca chess on a 10x8 board, with the archbishop between
+// the queenside knight and bishop and the chancellor between the kingside bishop and knight
+fn capablanca_position() -> Board {
+    position_with_back_row(8, &[
+        PieceType::Rook,
+        PieceType::Knight,
+        PieceType::Archbishop,
+        PieceType::Bishop,
+        PieceType::Queen,
+        PieceType::King,
+        PieceType::Bishop,
+        PieceType::Chancellor,
+        PieceType::Knight,
+        PieceType::Rook,
+    ])
+}
+
+// Create the starting position of Gothic chess, which uses the pieces of Capablanca chess arranged
+// so that no pawn is left undefended
+fn gothic_position() -> Board {
+    position_with_back_row(8, &[
+        PieceType::Rook,
+        PieceType::Knight,
+        PieceType::Bishop,
+        PieceType::Queen,
+        PieceType::Chancellor,
+        PieceType::King,
+        PieceType::Archbishop,
+        PieceType::Bishop,
+        PieceType::Knight,
+        PieceType::Rook,
+    ])
+}
+
+// Create the starting position of Gardner's 5x5 minichess, which is the standard position with
+// the kingside bishop, knight and rook and three files cut off
+fn gardner_position() -> Board {
+    position_with_back_row(5, &[PieceType::Rook, PieceType::Knight, PieceType::Bishop, PieceType::Queen, PieceType::King])
+}
+
+// Create the starting position of Los Alamos chess on a 6x6 board without bishops
+fn los_alamos_position() -> Board {
+    position_with_back_row(6, &[PieceType::Rook, PieceType::Knight, PieceType::Queen, PieceType::King, PieceType::Knight, PieceType::Rook])
+}
+
+// Define a function to create a Chess960 starting position from its number from 0 to 959,
+// using the standard numbering in which 518 is the usual starting position
+fn chess960_position(index: usize) -> Board {
+    let mut board = starting_position();
+    let mut back_row: [Option<PieceType>; 8] = Default::default();
+    let mut n = index % 960;
+
+    // The bishops go on opposite colors: first the light-squared one on b, d, f or h, then the dark-squared one on a, c, e or g
+    back_row[n % 4 * 2 + 1] = Some(PieceType::Bishop);
+    n /= 4;
+    back_row[n % 4 * 2] = Some(PieceType::Bishop);
+    n /= 4;
+
+    // The queen and the knights take some of the remaining empty squares, counted from the a-file
+    let empty_files = |back_row: &[Option<PieceType>; 8]| -> Vec<usize> { (0..8).filter(|&y| back_row[y].is_none()).collect() };
+    back_row[empty_files(&back_row)[n % 6]] = Some(PieceType::Queen);
+    n /= 6;
+    let knights = [(0, 1), (0, 2), (0, 3), (0, 4), (1, 2), (1, 3), (1, 4), (2, 3), (2, 4), (3, 4)][n];
+    let files = empty_files(&back_row);
+    back_row[files[knights.0]] = Some(PieceType::Knight);
+    back_row[files[knights.1]] = Some(PieceType::Knight);
+
+    // The king goes between the two rooks on the last three squares
+    for (y, piece_type) in empty_files(&back_row).into_iter().zip([PieceType::Rook, PieceType::King, PieceType::Rook]) {
+        back_row[y] = Some(piece_type);
+    }
+
+    for (y, piece_type) in back_row.into_iter().enumerate() {
+        let piece_type = piece_type.unwrap();
+        board[0][y] = Some(Piece { piece_type: piece_type.clone(), color: Color::Black });
+        board[7][y] = Some(Piece { piece_type, color: Color::White });
     }
 
     board
 }
 
+// Create the Horde starting position: Black as usual, and White with 36 pawns filling the first
+// four ranks and the b, c, f and g files of the fifth
+fn horde_position() -> Board {
+    let mut board = starting_position();
+    for row in board[4..].iter_mut() {
+        for square in row.iter_mut() {
+            *square = Some(Piece { piece_type: PieceType::Pawn, color: Color::White });
+        }
+    }
+    for y in [1, 2, 5, 6] {
+        board[3][y] = Some(Piece { piece_type: PieceType::Pawn, color: Color::White });
+    }
+    board
+}
+
+// Create the Racing Kings starting position, with both sides on the first two ranks: Black on the
+// a to d files and White mirrored on the h to e files
+fn racing_kings_position() -> Board {
+    let mut board = empty_board();
+    let ranks = [
+        (6, [PieceType::King, PieceType::Rook, PieceType::Bishop, PieceType::Knight]),
+        (7, [PieceType::Queen, PieceType::Rook, PieceType::Bishop, PieceType::Knight]),
+    ];
+    for (x, pieces) in ranks {
+        for (y, piece_type) in pieces.into_iter().enumerate() {
+            board[x][y] = Some(Piece { piece_type: piece_type.clone(), color: Color::Black });
+            board[x][7 - y] = Some(Piece { piece_type, color: Color::White });
+        }
+    }
+    board
+}
+
 // Define a function to get the possible moves for a pawn at a given position
 pub fn get_pawn_moves(board: &Board, x: usize, y: usize) -> Vec<(usize, usize)> {
     let mut moves = Vec::new();
@@ -78,7 +236,7 @@ pub fn get_pawn_moves(board: &Board, x: usize, y: usize) -> Vec<(usize, usize)>
             if x > 0 && board[x - 1][y].is_none() {
                 moves.push((x - 1, y));  
             }
-            if x > 0 && y < 7 && board[x - 1][y + 1].is_some() {
+            if x > 0 && y + 1 < board[x].len() && board[x - 1][y + 1].is_some() {
                 moves.push((x - 1, y + 1));
             }
             if x > 0 && y > 0 && board[x - 1][y - 1].is_some() {
@@ -86,13 +244,13 @@ pub fn get_pawn_moves(board: &Board, x: usize, y: usize) -> Vec<(usize, usize)>
             }
         },
         Color::Black => {
-            if x < 7 && board[x + 1][y].is_none() {
+            if x + 1 < board.len() && board[x + 1][y].is_none() {
                 moves.push((x + 1, y)); 
             }
-            if x < 7 && y < 7 && board[x + 1][y + 1].is_some() {
+            if x + 1 < board.len() && y + 1 < board[x].len() && board[x + 1][y + 1].is_some() {
                 moves.push((x + 1, y + 1));
             }
-            if x < 7 && y > 0 && board[x + 1][y - 1].is_some() {
+            if x + 1 < board.len() && y > 0 && board[x + 1][y - 1].is_some() {
                 moves.push((x + 1, y - 1));
             }
         },
@@ -106,10 +264,9 @@ pub fn get_rook_moves(board: &Board, x: usize, y: usize) -> Vec<(usize, usize)>
     let mut moves = Vec::new();
 
     // Get the piece at the given position
-    let piece = match board[x][y] {
-        Some(ref piece) => piece,
-        None => return moves,  // If there is no piece at this position, return an empty vector
-    };
+    if board[x][y].is_none() {
+        return moves;  // If there is no piece at this position, return an empty vector
+    }
 
     // Check the possible moves for a rook
     // Vertical moves
@@ -122,8 +279,8 @@ pub fn get_rook_moves(board: &Board, x: usize, y: usize) -> Vec<(usize, usize)>
             None => moves.push((i, y)),
         }
     }
-    for i in x+1..8 {
-        match board[i][y] {
+    for (i, row) in board.iter().enumerate().skip(x + 1) {
+        match row[y] {
             Some(_) => {
                 moves.push((i, y));
                 break;
@@ -142,8 +299,8 @@ pub fn get_rook_moves(board: &Board, x: usize, y: usize) -> Vec<(usize, usize)>
             None => moves.push((x, i)),
         }
     }
-    for i in y+1..8 {
-        match board[x][i] {
+    for (i, square) in board[x].iter().enumerate().skip(y + 1) {
+        match square {
             Some(_) => {
                 moves.push((x, i));
                 break;
@@ -160,10 +317,9 @@ pub fn get_knight_moves(board: &Board, x: usize, y: usize) -> Vec<(usize, usize)
     let mut moves = Vec::new();
 
     // Get the piece at the given position, if there is one
-    let piece = match board[x][y] {
-        Some(ref piece) => piece,
-        None => return moves,  // If there is no piece at this position, return an empty vector
-    };
+    if board[x][y].is_none() {
+        return moves;  // If there is no piece at this position, return an empty vector
+    }
 
     // Define the possible offsets for a knight's move
     let offsets = [
@@ -181,7 +337,7 @@ pub fn get_knight_moves(board: &Board, x: usize, y: usize) -> Vec<(usize, usize)
     for &(dx, dy) in offsets.iter() {
         let nx = x as i32 + dx;
         let ny = y as i32 + dy;
-        if nx >= 0 && nx < 8 && ny >= 0 && ny < 8 {
+        if (0..board.len() as i32).contains(&nx) && (0..board[x].len() as i32).contains(&ny) {
             moves.push((nx as usize, ny as usize));
         }
     }
@@ -194,10 +350,9 @@ pub fn get_bishop_moves(board: &Board, x: usize, y: usize) -> Vec<(usize, usize)
     let mut moves = Vec::new();
 
     // Get the piece at the given position, if there is one
-    let piece = match board[x][y] {
-        Some(ref piece) => piece,
-        None => return moves,  // If there is no piece at this position, return an empty vector
-    };
+    if board[x][y].is_none() {
+        return moves;  // If there is no piece at this position, return an empty vector
+    }
 
     // Define the possible diagonal directions for a bishop's move
     let directions = [(1, 1), (1, -1), (-1, 1), (-1, -1)];
@@ -205,8 +360,8 @@ pub fn get_bishop_moves(board: &Board, x: usize, y: usize) -> Vec<(usize, usize)
     // Check each possible move in each direction and add it to the vector if it is valid
     for &(dx, dy) in directions.iter() {
         let mut i = 1;
-        while let Some((nx, ny)) = (x as i32 + dx * i).checked_rem_euclid(8)
-            .zip((y as i32 + dy * i).checked_rem_euclid(8)) {
+        while let Some((nx, ny)) = (x as i32 + dx * i).checked_rem_euclid(board.len() as i32)
+            .zip((y as i32 + dy * i).checked_rem_euclid(board[x].len() as i32)) {
             match board[nx as usize][ny as usize] {
                 Some(_) => {
                     moves.push((nx as usize, ny as usize));
@@ -226,10 +381,9 @@ pub fn get_queen_moves(board: &Board, x: usize, y: usize) -> Vec<(usize, usize)>
     let mut moves = Vec::new();
 
     // Get the piece at the given position, if there is one
-    let piece = match board[x][y] {
-        Some(ref piece) => piece,
-        None => return moves,  // If there is no piece at this position, return an empty vector
-    };
+    if board[x][y].is_none() {
+        return moves;  // If there is no piece at this position, return an empty vector
+    }
 
     // Combine the valid moves for a rook and a bishop to get the valid moves for a queen
     moves.extend(get_rook_moves(board, x, y));
@@ -243,10 +397,9 @@ pub fn get_king_moves(board: &Board, x: usize, y: usize) -> Vec<(usize, usize)>
     let mut moves = Vec::new();
 
     // Get the piece at the given position, if there is one
-    let piece = match board[x][y] {
-        Some(ref piece) => piece,
-        None => return moves,  // If there is no piece at this position, return an empty vector
-    };
+    if board[x][y].is_none() {
+        return moves;  // If there is no piece at this position, return an empty vector
+    }
 
     // Define the possible offsets for a king's move
     let offsets = [
@@ -264,7 +417,7 @@ pub fn get_king_moves(board: &Board, x: usize, y: usize) -> Vec<(usize, usize)>
     for &(dx, dy) in offsets.iter() {
         let nx = x as i32 + dx;
         let ny = y as i32 + dy;
-        if nx >= 0 && nx < 8 && ny >= 0 && ny < 8 {
+        if (0..board.len() as i32).contains(&nx) && (0..board[x].len() as i32).contains(&ny) {
             moves.push((nx as usize, ny as usize));
         }
     }
@@ -274,53 +427,60 @@ pub fn get_king_moves(board: &Board, x: usize, y: usize) -> Vec<(usize, usize)>
 
 
 use std::io::{self, Write};
+use std::path::Path;
+use std::time::Duration;
+
+use crate::endgame::{describe_result, random_winning_position, EndgameResult, EndgameTables};
+use crate::position::parse_square;
 
 // Function to print the current state of the board
 fn print_board(board: &Board) {
-    println!("  a b c d e f g h");
+    print!("{}", board_to_string(board));
+}
+
+// Draw the board as text, one line per rank with the file letters above
+fn board_to_string(board: &Board) -> String {
+    board_view_to_string(board, &vec![vec![true; board[0].len()]; board.len()])
+}
+
+// Print the board as one player sees it, with a '?' on every square hidden from them
+fn print_board_view(board: &Board, visible: &[Vec<bool>]) {
+    print!("{}", board_view_to_string(board, visible));
+}
+
+// Draw the board as text as one player sees it
+fn board_view_to_string(board: &Board, visible: &[Vec<bool>]) -> String {
+    let files: String = (0..board[0].len()).map(|y| format!(" {}", (b'a' + y as u8) as char)).collect();
+    let mut s = format!(" {}\n", files);
     for (i, row) in board.iter().enumerate() {
-        print!("{}", 8 - i);
-        for square in row {
-            print!(" ");
+        s += &(board.len() - i).to_string();
+        for (j, square) in row.iter().enumerate() {
+            s.push(' ');
+            if !visible[i][j] {
+                s.push('?');
+                continue;
+            }
             match square {
-                Some(piece) => print!("{}", match piece.color {
-                    Color::White => match piece.piece_type {
-                        PieceType::Pawn => "P",
-                        PieceType::Rook => "R",
-                        PieceType::Knight => "N",
-                        PieceType::Bishop => "B",
-                        PieceType::Queen => "Q",
-                        PieceType::King => "K",
-                    },
-                    Color::Black => match piece.piece_type {
-                        PieceType::Pawn => "p",
-                        PieceType::Rook => "r",
-                        PieceType::Knight => "n",
-                        PieceType::Bishop => "b",
-                        PieceType::Queen => "q",
-                        PieceType::King => "k",
-                    },
-                }),
-                None => print!("."),
+                // The same letters as in FEN, which also covers pieces defined at runtime
+                Some(piece) => s.push(position::piece_to_char(piece)),
+                None => s.push('.'),
             }
         }
-        println!();
+        s.push('\n');
     }
+    s
 }
 
-// Function to read a move from the user
-fn read_move() -> io::Result<(usize, usize, usize, usize)> {
-    let mut input = String::new();
-    io::stdin().read_line(&mut input)?;
-    let chars: Vec<char> = input.chars().collect();
-    if chars.len() < 5 || chars[2] != ' ' {
-        return Err(io::Error::new(io::ErrorKind::InvalidInput, "Invalid input"));
-    }
-    let start_x = 8 - chars[1].to_digit(10).unwrap() as usize;
-    let start_y = chars[0].to_digit(36).unwrap() as usize - 10;
-    let end_x = 8 - chars[4].to_digit(10).unwrap() as usize;
-    let end_y = chars[3].to_digit(36).unwrap() as usize - 10;
-    Ok((start_x, start_y, end_x, end_y))
+// Read a move typed as its two squares, e.g. "e2 e4", checking that both are on the board
+fn read_move(input: &str, board: &Board) -> io::Result<(usize, usize, usize, usize)> {
+    let squares: Vec<Option<(usize, usize)>> = input
+        .split_whitespace()
+        .map(|square| parse_square(square, board.len()).filter(|&(_, y)| y < board[0].len()))
+        .collect();
+    match squares[..] {
+        [Some((start_x, start_y)), Some((end_x, end_y))] => Ok((start_x, start_y, end_x, end_y)),
+        _ => Err(io::Error::new(io::ErrorKind::InvalidInput, "Invalid input")),
+    }
 }
 
 fn print_menu() {
@@ -328,7 +488,26 @@ fn print_menu() {
     println!("1. Start a new game");
     println!("2. Load a saved game");
     println!("3. Instructions");
-    println!("4. Quit");
+    println!("4. Endgame trainer");
+    println!("5. Bughouse");
+    println!("6. Synchronous chess");
+    println!("7. Play a variant");
+    println!("8. Kriegspiel and Dark chess");
+    println!("9. Play over the network");
+    println!("10. Quit");
+}
+
+// Ask for the server to play on, the default address if none is given, and connect to it
+fn connect_menu() {
+    print!("Server address as host:port (Enter for {}): ", server::DEFAULT_ADDRESS);
+    io::stdout().flush().unwrap();
+    let mut input = String::new();
+    io::stdin().read_line(&mut input).unwrap();
+    let address = match input.trim() {
+        "" => server::DEFAULT_ADDRESS,
+        address => address,
+    };
+    client::play(address);
 }
 
 fn print_instructions() {
@@ -337,11 +516,31 @@ fn print_instructions() {
     println!("Pawns are represented by 'p' or 'P', rooks by 'r' or 'R', knights by 'n' or 'N', bishops by 'b' or 'B', queens by 'q' or 'Q', and kings by 'k' or 'K'.");
     println!("Lowercase letters represent black pieces and uppercase letters represent white pieces.");
     println!("The game ends when a king is in checkmate (the king is in a position to be captured next turn and there is no way to move the king out of capture).");
+    println!("Type 'tbpath' followed by a directory of Syzygy tablebases to load them, then 'tb' to look up the current position.");
+}
+
+// The game as a position the tablebases can look up. The terminal game does not keep castling
+// rights or en passant squares, so castling counts as possible while the king and rook are home.
+fn game_position(board: &Board, side_to_move: Color) -> Position {
+    let home = |x: usize, y: usize, piece_type: PieceType, color: Color| {
+        matches!(board[x][y], Some(ref piece) if piece.piece_type == piece_type && piece.color == color)
+    };
+    let castling = |x: usize, color: Color| {
+        let king = home(x, 4, PieceType::King, color);
+        (king && home(x, 0, PieceType::Rook, color), king && home(x, 7, PieceType::Rook, color))
+    };
+    let mut position = Position::new();
+    position.board = board.clone();
+    position.side_to_move = side_to_move;
+    position.white_castling = castling(7, Color::White);
+    position.black_castling = castling(0, Color::Black);
+    position
 }
 
 fn start_new_game() {
     let mut board = starting_position();
     let mut current_color = Color::White;
+    let mut syzygy = SyzygyTables::new();
 
     loop {
         print_board(&board);
@@ -351,7 +550,26 @@ fn start_new_game() {
         });
         print!("Enter move: ");
         io::stdout().flush().unwrap();
-        match read_move() {
+        let mut input = String::new();
+        if io::stdin().read_line(&mut input).unwrap() == 0 {
+            return;
+        }
+        let command = input.trim();
+        if command == "tb" {
+            println!("Tablebase: {}", describe_position(&syzygy, &game_position(&board, current_color)));
+            continue;
+        }
+        if let Some(path) = command.strip_prefix("tbpath") {
+            match load_syzygy(path.trim()) {
+                Ok(tables) => {
+                    println!("Found Syzygy tables for up to {} pieces", tables.max_pieces());
+                    syzygy = tables;
+                },
+                Err(error) => println!("{}", error),
+            }
+            continue;
+        }
+        match read_move(&input, &board) {
             Ok((start_x, start_y, end_x, end_y)) => {
                 let piece = match board[start_x][start_y] {
                     Some(ref piece) => piece.clone(),
@@ -374,6 +592,16 @@ fn start_new_game() {
                     Color::White => Color::Black,
                     Color::Black => Color::White,
                 };
+                if is_checkmate(&board, current_color) {
+                    print_board(&board);
+                    println!("Checkmate!");
+                    return;
+                }
+                if is_stalemate(&board, current_color) {
+                    print_board(&board);
+                    println!("Stalemate!");
+                    return;
+                }
             }
             Err(_) => {
                 println!("Invalid input");
@@ -383,13 +611,185 @@ fn start_new_game() {
     }
 }
 
+// Practice converting a won endgame as White against perfect defence from the endgame tables
+fn endgame_trainer() {
+    let tables = match EndgameTables::load_dir(Path::new("endgames")) {
+        Ok(tables) => tables,
+        Err(error) => {
+            println!("Could not load endgame tables: {}", error);
+            return;
+        }
+    };
+    let names = tables.names();
+    if names.is_empty() {
+        println!("No endgame tables found in the 'endgames' directory.");
+        println!("Generate them first with: cargo run --release -- --generate-endgames");
+        return;
+    }
+
+    println!("Choose an endgame to practice:");
+    for (i, name) in names.iter().enumerate() {
+        println!("{}. {}", i + 1, name);
+    }
+    print!("Enter your choice: ");
+    io::stdout().flush().unwrap();
+    let mut input = String::new();
+    io::stdin().read_line(&mut input).unwrap();
+    let table = match input.trim().parse::<usize>().ok().and_then(|choice| names.get(choice.wrapping_sub(1))) {
+        Some(name) => tables.get(name).unwrap(),
+        None => {
+            println!("Invalid choice");
+            return;
+        }
+    };
+
+    let mut position = match random_winning_position(table, 15) {
+        Some(position) => position,
+        None => {
+            println!("The {} table has no won positions", table.name);
+            return;
+        }
+    };
+    println!("You play White. Win the game against perfect defence!");
+
+    loop {
+        print_board(&position.board);
+        let moves = position.legal_moves();
+        if moves.is_empty() {
+            if position.in_check() {
+                println!("Checkmate! {} wins.", if position.side_to_move == Color::White { "Black" } else { "White" });
+            } else {
+                println!("Stalemate! The game is a draw.");
+            }
+            return;
+        }
+        let before = match tables.probe(&position) {
+            Some(result) => result,
+            None => {
+                println!("The position has left the endgame tables.");
+                return;
+            }
+        };
+
+        if position.side_to_move == Color::Black {
+            // Black defends as stubbornly as possible: a draw if there is one, otherwise the slowest loss
+            let resistance = |mv: &position::Move| {
+                let mut next = position.clone();
+                next.make_move(mv);
+                match tables.probe(&next) {
+                    Some(EndgameResult::Loss(plies)) => 1000 + plies as i32,
+                    Some(EndgameResult::Draw) | None => 500,
+                    Some(EndgameResult::Win(plies)) => plies as i32,
+                }
+            };
+            let defence = moves.iter().max_by_key(|mv| resistance(mv)).unwrap().clone();
+            println!("Black plays {}", position.move_to_string(&defence));
+            position.make_move(&defence);
+            continue;
+        }
+
+        println!("Theoretical result for White: {}", describe_result(before));
+        print!("Enter move: ");
+        io::stdout().flush().unwrap();
+        let mut input = String::new();
+        if io::stdin().read_line(&mut input).unwrap_or(0) == 0 {
+            return;
+        }
+        // Moves are typed in SAN or as their squares, and pawns reaching the last rank become queens
+        let text: String = input.split_whitespace().collect();
+        let chosen = position.parse_san(&text).or_else(|| position.parse_move(&text)).or_else(|| position.parse_move(&(text + "q")));
+        let chosen = match chosen {
+            Some(mv) => mv,
+            None => {
+                println!("Invalid move");
+                continue;
+            }
+        };
+        position.make_move(&chosen);
+
+        // Compare the result after the move with the best that was possible before it
+        match (before, tables.probe(&position)) {
+            (EndgameResult::Win(best), Some(EndgameResult::Loss(plies))) if plies + 1 == best => println!("Best move!"),
+            (EndgameResult::Win(best), Some(EndgameResult::Loss(plies))) => {
+                println!("Still winning, but mate now takes {} more moves than it had to.", (plies + 1 - best) / 2)
+            },
+            (EndgameResult::Win(_), Some(EndgameResult::Draw)) => {
+                println!("Oops, that throws away the win: the position is now a draw.");
+                return;
+            },
+            _ => {},
+        }
+    }
+}
+
 fn main() {
+    let args: Vec<String> = std::env::args().collect();
+
+    // Run as an engine for chess GUIs instead of the interactive menu
+    if args.iter().any(|arg| arg == "--uci") {
+        uci::run();
+        return;
+    }
+    if args.iter().any(|arg| arg == "--xboard") {
+        xboard::run();
+        return;
+    }
+
+    // Both servers can hold games back from spectators by a number of seconds, given after --delay
+    let spectator_delay = match args.iter().position(|arg| arg == "--delay").map(|i| args.get(i + 1).and_then(|seconds| seconds.parse::<u64>().ok())) {
+        Some(Some(seconds)) => Duration::from_secs(seconds),
+        Some(None) => {
+            println!("--delay needs a number of seconds");
+            return;
+        },
+        None => Duration::ZERO,
+    };
+    // The address may be left out, as in "--server --delay 60"
+    let address_after = |i: usize| args.get(i + 1).filter(|arg| !arg.starts_with("--")).map(String::as_str);
+
+    // Host games for players connecting over the network, on the given address or the default one
+    if let Some(i) = args.iter().position(|arg| arg == "--server") {
+        let address = address_after(i).unwrap_or(server::DEFAULT_ADDRESS);
+        if let Err(error) = server::run(address, spectator_delay) {
+            println!("Cannot run the server: {}", error);
+        }
+        return;
+    }
+
+    // Serve the JSON API for dashboards and bots, on the given address or the default local one
+    if let Some(i) = args.iter().position(|arg| arg == "--api") {
+        let address = address_after(i).unwrap_or(api::DEFAULT_API_ADDRESS);
+        if let Err(error) = api::run(address, spectator_delay) {
+            println!("Cannot run the API: {}", error);
+        }
+        return;
+    }
+
+    // Solve the small endgames and store their tables
+    if args.get(1).map(String::as_str) == Some("--generate-endgames") {
+        if let Err(error) = endgame::generate_command(&args[2..]) {
+            println!("{}", error);
+        }
+        return;
+    }
+
+    // Build an opening book from a PGN file
+    if args.get(1).map(String::as_str) == Some("--make-book") {
+        if let Err(error) = book::make_book_command(&args[2..]) {
+            println!("{}", error);
+        }
+        return;
+    }
+
     loop {
         print_menu();
         print!("Enter your choice: ");
         io::stdout().flush().unwrap();
         let mut input = String::new();
-        io::stdin().read_line(&mut input).unwrap();
+        if io::stdin().read_line(&mut input).unwrap() == 0 {
+            // Stop at the end of input instead of asking forever
+            break;
+        }
         match input.trim().parse() {
             Ok(1) => {
                 // Start a new game
@@ -404,6 +804,30 @@ fn main() {
                 print_instructions();
             },
             Ok(4) => {
+                // Practice winning endgames against perfect defence
+                endgame_trainer();
+            },
+            Ok(5) => {
+                // Play two linked games at once, four players at one terminal
+                bughouse::play();
+            },
+            Ok(6) => {
+                // Both players move at the same time
+                synchronous::play();
+            },
+            Ok(7) => {
+                // Play one of the other rule sets at one terminal
+                variant::variant_menu();
+            },
+            Ok(8) => {
+                // Play without seeing the opponent's pieces
+                kriegspiel::play();
+            },
+            Ok(9) => {
+                // Play a game hosted by a server, possibly on another machine
+                connect_menu();
+            },
+            Ok(10) => {
                 // Quit the game
                 break;
             },
@@ -0,0 +1,197 @@
+use std::time::Duration;
+
+use crate::variant::Variant;
+use crate::Color;
+
+// The pieces of the server's lobby that do not depend on connections: time controls, what a seek
+// asks for, and ratings. A seek is an offer to play anyone whose rating falls in its range, on the
+// seeker's terms; a challenge is the same offer made to one named player.
+
+// The rating players start with
+pub const INITIAL_RATING: i32 = 1500;
+
+// How far one game can move a rating
+const RATING_K: f64 = 32.0;
+
+// Minutes for the whole game and seconds added after every move, as in "5+3"
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct TimeControl {
+    pub minutes: u32,
+    pub increment: u32,
+}
+
+impl TimeControl {
+    pub fn initial(self) -> Duration {
+        Duration::from_secs(self.minutes as u64 * 60)
+    }
+
+    pub fn increment(self) -> Duration {
+        Duration::from_secs(self.increment as u64)
+    }
+}
+
+// Parse a time control such as "5+3", or "untimed" for none
+pub fn parse_time_control(text: &str) -> Result<Option<TimeControl>, String> {
+    if text == "untimed" {
+        return Ok(None);
+    }
+    let invalid = || format!("Invalid time control '{}', give minutes and increment as in 5+3", text);
+    let (minutes, increment) = text.split_once('+').ok_or_else(invalid)?;
+    match (minutes.parse::<u32>(), increment.parse::<u32>()) {
+        (Ok(minutes), Ok(increment)) if minutes > 0 && minutes <= 180 && increment <= 180 => {
+            Ok(Some(TimeControl { minutes, increment }))
+        },
+        _ => Err(invalid()),
+    }
+}
+
+// Write a time control as players type it
+pub fn time_control_text(time_control: Option<TimeControl>) -> String {
+    match time_control {
+        Some(TimeControl { minutes, increment }) => format!("{}+{}", minutes, increment),
+        None => "untimed".to_string(),
+    }
+}
+
+// What a seek or a challenge asks for: the variant, the time control, the seeker's color if they
+// chose one, and the ratings an opponent may have
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct SeekTerms {
+    pub variant: Variant,
+    pub time_control: Option<TimeControl>,
+    pub color: Option<Color>,
+    pub rating_range: Option<(i32, i32)>,
+}
+
+// Parse the words of a seek in any order, e.g. "atomic 3+2 white 1400-1800". Whatever is left out
+// means standard chess, untimed, either color and any rating.
+pub fn parse_seek_terms(words: &[&str]) -> Result<SeekTerms, String> {
+    let mut terms = SeekTerms { variant: Variant::Standard, time_control: None, color: None, rating_range: None };
+    for &word in words {
+        let range = word.split_once('-').and_then(|(min, max)| Some((min.parse::<i32>().ok()?, max.parse::<i32>().ok()?)));
+        match word {
+            "white" => terms.color = Some(Color::White),
+            "black" => terms.color = Some(Color::Black),
+            "random" => terms.color = None,
+            _ if word == "untimed" || word.contains('+') => terms.time_control = parse_time_control(word)?,
+            _ if range.is_some() => match range {
+                Some((min, max)) if min <= max => terms.rating_range = Some((min, max)),
+                _ => return Err(format!("Invalid rating range '{}'", word)),
+            },
+            _ => {
+                terms.variant = Variant::from_name(word)
+                    .filter(|&variant| variant != Variant::Bughouse)
+                    .ok_or(format!("Unknown variant or term '{}'", word))?;
+            },
+        }
+    }
+    Ok(terms)
+}
+
+// Write the terms of a seek as the fields of the server's seek and challenge lines
+pub fn seek_terms_text(terms: &SeekTerms) -> String {
+    let color = match terms.color {
+        Some(Color::White) => "white",
+        Some(Color::Black) => "black",
+        None => "random",
+    };
+    let range = match terms.rating_range {
+        Some((min, max)) => format!("{}-{}", min, max),
+        None => "any".to_string(),
+    };
+    format!("{} {} {} {}", terms.variant.name(), time_control_text(terms.time_control), color, range)
+}
+
+// Check if a rating falls in the range a seek asks for
+pub fn rating_allowed(terms: &SeekTerms, rating: i32) -> bool {
+    terms.rating_range.is_none_or(|(min, max)| (min..=max).contains(&rating))
+}
+
+// Get both players' new ratings after a game, from White's score: 1 for a win, 0.5 for a draw, 0
+// for a loss
+pub fn update_ratings(white: i32, black: i32, white_score: f64) -> (i32, i32) {
+    let expected = 1.0 / (1.0 + 10f64.powf((black - white) as f64 / 400.0));
+    let change = (RATING_K * (white_score - expected)).round() as i32;
+    (white + change, black - change)
+}
+
+// Check if a user name can be used: letters, digits, '_' and '-', up to 20 of them
+pub fn valid_name(name: &str) -> bool {
+    !name.is_empty() && name.len() <= 20 && name.chars().all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '-')
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn time_controls_are_bounded() {
+        assert_eq!(parse_time_control("5+3"), Ok(Some(TimeControl { minutes: 5, increment: 3 })));
+        assert_eq!(parse_time_control("180+180"), Ok(Some(TimeControl { minutes: 180, increment: 180 })));
+        assert_eq!(parse_time_control("1+0").unwrap().unwrap().initial(), Duration::from_secs(60));
+        assert_eq!(parse_time_control("untimed"), Ok(None));
+        for text in ["0+5", "181+0", "5+181", "5", "5+", "+3", "-1+3", "5+3+1", "five+3"] {
+            assert!(parse_time_control(text).is_err(), "{}", text);
+        }
+        assert_eq!(time_control_text(parse_time_control("15+10").unwrap()), "15+10");
+        assert_eq!(time_control_text(None), "untimed");
+    }
+
+    #[test]
+    fn seek_terms_come_in_any_order() {
+        let terms = SeekTerms {
+            variant: Variant::Atomic,
+            time_control: Some(TimeControl { minutes: 3, increment: 2 }),
+            color: Some(Color::White),
+            rating_range: Some((1400, 1800)),
+        };
+        assert_eq!(parse_seek_terms(&["atomic", "3+2", "white", "1400-1800"]), Ok(terms));
+        assert_eq!(parse_seek_terms(&["1400-1800", "white", "3+2", "atomic"]), Ok(terms));
+        assert_eq!(seek_terms_text(&terms), "atomic 3+2 white 1400-1800");
+
+        // Whatever is left out takes the defaults
+        let defaults = parse_seek_terms(&[]).unwrap();
+        assert_eq!(defaults, SeekTerms { variant: Variant::Standard, time_control: None, color: None, rating_range: None });
+        assert_eq!(seek_terms_text(&defaults), "chess untimed random any");
+        assert_eq!(parse_seek_terms(&["black", "random"]).unwrap().color, None);
+    }
+
+    #[test]
+    fn bad_seek_terms_are_rejected() {
+        assert_eq!(parse_seek_terms(&["1800-1400"]), Err("Invalid rating range '1800-1400'".to_string()));
+        assert!(parse_seek_terms(&["0+0"]).is_err());
+        assert_eq!(parse_seek_terms(&["fast"]), Err("Unknown variant or term 'fast'".to_string()));
+        // Bughouse needs four players, so it cannot be sought
+        assert_eq!(parse_seek_terms(&["bughouse"]), Err("Unknown variant or term 'bughouse'".to_string()));
+    }
+
+    #[test]
+    fn ratings_are_checked_against_the_range() {
+        let terms = parse_seek_terms(&["1400-1800"]).unwrap();
+        assert!(rating_allowed(&terms, 1400) && rating_allowed(&terms, 1800));
+        assert!(!rating_allowed(&terms, 1399) && !rating_allowed(&terms, 1801));
+        assert!(rating_allowed(&parse_seek_terms(&[]).unwrap(), -5000));
+    }
+
+    #[test]
+    fn ratings_change_by_the_same_amount_for_both_players() {
+        assert_eq!(update_ratings(1500, 1500, 1.0), (1516, 1484));
+        assert_eq!(update_ratings(1500, 1500, 0.5), (1500, 1500));
+        assert_eq!(update_ratings(1500, 1500, 0.0), (1484, 1516));
+        // An upset moves the ratings more than the expected result
+        let (white, black) = update_ratings(1200, 1800, 1.0);
+        assert_eq!(white - 1200, 1800 - black);
+        assert_eq!(white - 1200, 31);
+        assert_eq!(update_ratings(1800, 1200, 1.0), (1801, 1199));
+        for (white, black, score) in [(1500, 1700, 0.5), (2100, 1350, 0.0), (1600, 1601, 1.0)] {
+            let (new_white, new_black) = update_ratings(white, black, score);
+            assert_eq!(new_white + new_black, white + black);
+        }
+    }
+
+    #[test]
+    fn names_are_short_and_plain() {
+        assert!(valid_name("alice") && valid_name("Bob_2") && valid_name("x-y") && valid_name(&"a".repeat(20)));
+        assert!(!valid_name("") && !valid_name(&"a".repeat(21)) && !valid_name("two words") && !valid_name("café"));
+    }
+}
@@ -0,0 +1,1293 @@
+use std::sync::Arc;
+
+use crate::rules::{castling_king_files, find_king, is_in_atomic_check, is_in_check, is_valid_castling, is_valid_chess960_castling, is_valid_drop, is_valid_en_passant, is_valid_first_rank_double_step, is_valid_move, is_valid_pawn_promotion, perform_explosion, perform_pawn_promotion};
+use crate::betza::FairyPiece;
+use crate::variant::{variant_outcome, Variant, ENGINE_VARIANTS};
+use crate::{chess960_position, starting_position, Board, Color, Piece, PieceType};
+
+// A move from one square to another, with the piece to promote to if a pawn reaches the last rank.
+// A drop puts a piece from the pocket on the board and has the same start and end square. In Duck
+// chess every move has a second part, the square the duck is moved to afterwards, and in S-Chess a
+// move can gate a piece from the reserve onto the square left behind.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Move {
+    pub start: (usize, usize),
+    pub end: (usize, usize),
+    pub promotion: Option<PieceType>,
+    pub drop: Option<PieceType>,
+    pub duck: Option<(usize, usize)>,
+    pub gating: Option<(PieceType, (usize, usize))>,
+}
+
+// The pieces that can be held in a pocket, in the order their counts are stored
+pub const POCKET_PIECES: [PieceType; 5] = [PieceType::Pawn, PieceType::Knight, PieceType::Bishop, PieceType::Rook, PieceType::Queen];
+
+// How many pieces of each type in POCKET_PIECES a side holds in hand
+pub type Pocket = [u32; 5];
+
+// The full state of a game: the board plus everything else needed to know which moves are legal
+#[derive(Debug, Clone)]
+pub struct Position {
+    pub board: Board,
+    pub side_to_move: Color,
+    // Castling rights for each side as (queenside, kingside), in the order is_valid_castling expects
+    pub white_castling: (bool, bool),
+    pub black_castling: (bool, bool),
+    // In Chess960 the king and rooks can start on other files, and castling moves are written as
+    // the king taking its own rook. The files of the (queenside, kingside) rooks are kept here.
+    pub chess960: bool,
+    pub castling_rooks: (usize, usize),
+    pub en_passant: Option<(usize, usize)>,
+    pub halfmove_clock: u32,
+    pub fullmove_number: u32,
+    pub variant: Variant,
+    // Variants with drops keep captured pieces in hand, and need to know which pieces were
+    // promoted since those go back to the pocket as pawns
+    pub white_pocket: Pocket,
+    pub black_pocket: Pocket,
+    pub promoted: Vec<(usize, usize)>,
+    // The number of checks each side has given, for three-check
+    pub white_checks: u32,
+    pub black_checks: u32,
+    // The pieces each side can still gate in S-Chess, and the squares on the back ranks whose
+    // pieces have not moved yet, which are the only ones a piece can be gated onto
+    pub white_reserve: Vec<PieceType>,
+    pub black_reserve: Vec<PieceType>,
+    pub unmoved: Vec<(usize, usize)>,
+}
+
+// Get the opposite color
+pub fn opponent(color: Color) -> Color {
+    match color {
+        Color::White => Color::Black,
+        Color::Black => Color::White,
+    }
+}
+
+// Convert a piece to its FEN letter, uppercase for white and lowercase for black
+pub fn piece_to_char(piece: &Piece) -> char {
+    let c = match piece.piece_type {
+        PieceType::Pawn => 'p',
+        PieceType::Rook => 'r',
+        PieceType::Knight => 'n',
+        PieceType::Bishop => 'b',
+        PieceType::Queen => 'q',
+        PieceType::King => 'k',
+        PieceType::Archbishop => 'a',
+        PieceType::Chancellor => 'c',
+        PieceType::Fairy(ref fairy) => fairy.symbol,
+        PieceType::Duck => '*',
+    };
+    match piece.color {
+        Color::White => c.to_ascii_uppercase(),
+        Color::Black => c,
+    }
+}
+
+// Get the uppercase letter a piece type is written with in SAN
+pub fn piece_letter(piece_type: &PieceType) -> char {
+    piece_to_char(&Piece { piece_type: piece_type.clone(), color: Color::White })
+}
+
+// Convert a FEN letter back to a piece. The duck is written as '*' and, since every piece needs a
+// color, is stored as Black's, though it belongs to neither player.
+pub fn piece_from_char(c: char) -> Option<Piece> {
+    let piece_type = match c.to_ascii_lowercase() {
+        'p' => PieceType::Pawn,
+        'r' => PieceType::Rook,
+        'n' => PieceType::Knight,
+        'b' => PieceType::Bishop,
+        'q' => PieceType::Queen,
+        'k' => PieceType::King,
+        'a' => PieceType::Archbishop,
+        'c' => PieceType::Chancellor,
+        '*' => PieceType::Duck,
+        _ => return None,
+    };
+    let color = if c.is_ascii_uppercase() { Color::White } else { Color::Black };
+    Some(Piece { piece_type, color })
+}
+
+// Convert board coordinates to a square name such as "e4". The first row of the board is the last
+// rank, so the board's number of ranks is needed to name it.
+pub fn square_to_string(square: (usize, usize), ranks: usize) -> String {
+    let (x, y) = square;
+    format!("{}{}", (b'a' + y as u8) as char, ranks - x)
+}
+
+// Parse a square name such as "e4" into board coordinates on a board with the given number of
+// ranks, allowing the files of boards up to ten wide
+pub fn parse_square(s: &str, ranks: usize) -> Option<(usize, usize)> {
+    let chars: Vec<char> = s.chars().collect();
+    if chars.len() != 2 {
+        return None;
+    }
+    let y = match chars[0] {
+        'a'..='j' => chars[0] as usize - 'a' as usize,
+        _ => return None,
+    };
+    let x = match chars[1].to_digit(10)? as usize {
+        rank @ 1.. if rank <= ranks => ranks - rank,
+        _ => return None,
+    };
+    Some((x, y))
+}
+
+// Get the place of a piece type in a pocket, if it can be held in one
+pub fn pocket_slot(piece_type: &PieceType) -> Option<usize> {
+    POCKET_PIECES.iter().position(|pocket_piece| pocket_piece == piece_type)
+}
+
+// Format a pocket as its piece letters, strongest first, or "-" if it is empty
+pub fn pocket_to_string(pocket: &Pocket, color: Color) -> String {
+    let mut s = String::new();
+    for (piece_type, &count) in POCKET_PIECES.iter().zip(pocket).rev() {
+        let c = piece_to_char(&Piece { piece_type: piece_type.clone(), color });
+        s.extend(std::iter::repeat_n(c, count as usize));
+    }
+    if s.is_empty() {
+        s.push('-');
+    }
+    s
+}
+
+// Format the pieces held in an S-Chess reserve, or "-" if there are none
+pub fn reserve_to_string(reserve: &[PieceType], color: Color) -> String {
+    let s: String = reserve.iter().map(|piece_type| piece_to_char(&Piece { piece_type: piece_type.clone(), color })).collect();
+    if s.is_empty() { "-".to_string() } else { s }
+}
+
+// Format a move on a board with the given number of ranks in long algebraic notation as used by
+// UCI, e.g. "e2e4", "e7e8q" or "N@f3", followed in Duck chess by the duck's square as in "e2e4,d5".
+// A gated piece is written like a promotion, as in "g1f3h", and when castling gates it onto the
+// rook's square the move is written from the rook to the king, as in "h1e1h".
+pub fn move_to_string(mv: &Move, ranks: usize) -> String {
+    let mut s = match (&mv.drop, &mv.gating) {
+        (Some(piece_type), _) => {
+            let piece = Piece { piece_type: piece_type.clone(), color: Color::White };
+            format!("{}@{}", piece_to_char(&piece), square_to_string(mv.end, ranks))
+        },
+        (None, Some((_, square))) if *square != mv.start => square_to_string(*square, ranks) + &square_to_string(mv.start, ranks),
+        (None, _) => square_to_string(mv.start, ranks) + &square_to_string(mv.end, ranks),
+    };
+    if let Some(ref promotion) = mv.promotion {
+        s.push(piece_to_char(&Piece { piece_type: promotion.clone(), color: Color::Black }));
+    }
+    if let Some((ref piece_type, _)) = mv.gating {
+        s.push(piece_to_char(&Piece { piece_type: piece_type.clone(), color: Color::Black }));
+    }
+    if let Some(duck) = mv.duck {
+        s.push(',');
+        s.push_str(&square_to_string(duck, ranks));
+    }
+    s
+}
+
+impl Position {
+    // Create the starting position of a variant
+    pub fn with_variant(variant: Variant) -> Position {
+        // Some variants start from a FEN, which for a variant file was checked when the file was loaded
+        if let Some(fen) = variant.starting_fen() {
+            return Position::from_fen(fen, Some(variant)).unwrap();
+        }
+        let board = variant.starting_board();
+        let files = board[0].len();
+        let castling = |color| {
+            let can_castle = variant.has_castling() && find_king(&board, color).is_some();
+            (can_castle, can_castle)
+        };
+        Position {
+            white_castling: castling(Color::White),
+            black_castling: castling(Color::Black),
+            castling_rooks: (0, files - 1),
+            board,
+            variant,
+            ..Position::new()
+        }
+    }
+
+    // Create one of the 960 Chess960 starting positions by its number
+    pub fn chess960(index: usize) -> Position {
+        let board = chess960_position(index);
+        let rook_files: Vec<usize> = (0..8).filter(|&y| matches!(board[7][y], Some(ref piece) if piece.piece_type == PieceType::Rook)).collect();
+        Position {
+            board,
+            chess960: true,
+            castling_rooks: (rook_files[0], rook_files[1]),
+            ..Position::new()
+        }
+    }
+
+    // Create the standard starting position
+    pub fn new() -> Position {
+        Position {
+            board: starting_position(),
+            side_to_move: Color::White,
+            white_castling: (true, true),
+            black_castling: (true, true),
+            chess960: false,
+            castling_rooks: (0, 7),
+            en_passant: None,
+            halfmove_clock: 0,
+            fullmove_number: 1,
+            variant: Variant::Standard,
+            white_pocket: Pocket::default(),
+            black_pocket: Pocket::default(),
+            promoted: Vec::new(),
+            white_checks: 0,
+            black_checks: 0,
+            white_reserve: Vec::new(),
+            black_reserve: Vec::new(),
+            unmoved: Vec::new(),
+        }
+    }
+
+    // Parse a position from Forsyth-Edwards Notation for the given variant. Without one, a FEN with
+    // pockets or check counters is taken to be crazyhouse or three-check, and any other FEN standard chess.
+    pub fn from_fen(fen: &str, variant: Option<Variant>) -> Result<Position, String> {
+        Position::from_fen_with_pieces(fen, variant, &[])
+    }
+
+    // Parse a position from FEN like from_fen, where the letters of the given fairy pieces stand for
+    // them, as well as those of the variant's own fairy pieces
+    pub fn from_fen_with_pieces(fen: &str, variant: Option<Variant>, fairy_pieces: &[Arc<FairyPiece>]) -> Result<Position, String> {
+        let fields: Vec<&str> = fen.split_whitespace().collect();
+        if fields.len() < 4 {
+            return Err(format!("FEN needs at least 4 fields: {}", fen));
+        }
+        let fairy_pieces = [fairy_pieces, &variant.map(Variant::fairy_pieces).unwrap_or_default()].concat();
+        let char_to_piece = |c: char| piece_from_char(c).or_else(|| {
+            fairy_pieces.iter().find(|fairy| fairy.symbol == c.to_ascii_lowercase()).map(|fairy| Piece {
+                piece_type: PieceType::Fairy(fairy.clone()),
+                color: if c.is_ascii_uppercase() { Color::White } else { Color::Black },
+            })
+        });
+        let gating = variant.is_some_and(Variant::has_gating);
+
+        // Pockets follow the placement either in brackets, as in "...RNBQKBNR[Qp]", or as a ninth rank
+        let ranks = variant.map_or(8, |variant| variant.dimensions().0);
+        let (placement, pocket_pieces) = match fields[0].split_once('[') {
+            Some((placement, pocket)) => (placement, Some(pocket.trim_end_matches(']'))),
+            None if fields[0].matches('/').count() == ranks => {
+                let (placement, pocket) = fields[0].rsplit_once('/').unwrap();
+                (placement, Some(pocket))
+            },
+            None => (fields[0], None),
+        };
+        let mut white_pocket = Pocket::default();
+        let mut black_pocket = Pocket::default();
+        let mut white_reserve = Vec::new();
+        let mut black_reserve = Vec::new();
+        for c in pocket_pieces.unwrap_or("").chars() {
+            let piece = char_to_piece(c).ok_or(format!("Invalid pocket piece '{}' in FEN", c))?;
+            // In S-Chess the brackets hold the pieces waiting to be gated instead
+            if gating {
+                match piece.color {
+                    Color::White => white_reserve.push(piece.piece_type),
+                    Color::Black => black_reserve.push(piece.piece_type),
+                }
+                continue;
+            }
+            let slot = pocket_slot(&piece.piece_type).ok_or(format!("A {:?} cannot be held in a pocket", piece.piece_type))?;
+            match piece.color {
+                Color::White => white_pocket[slot] += 1,
+                Color::Black => black_pocket[slot] += 1,
+            }
+        }
+
+        // Piece placement, from the last rank down to rank 1, with promoted pieces marked by a '~'.
+        // Runs of ten or more empty squares on wide boards are written with two digits.
+        let mut board: Board = Vec::new();
+        let mut promoted = Vec::new();
+        for (x, rank) in placement.split('/').enumerate() {
+            let mut row = Vec::new();
+            let mut empty = 0;
+            for c in rank.chars() {
+                if let Some(digit) = c.to_digit(10) {
+                    empty = empty * 10 + digit as usize;
+                    continue;
+                }
+                row.extend(std::iter::repeat_n(None, std::mem::take(&mut empty)));
+                if c == '~' && !row.is_empty() {
+                    promoted.push((x, row.len() - 1));
+                } else {
+                    row.push(Some(char_to_piece(c).ok_or(format!("Invalid piece '{}' in FEN", c))?));
+                }
+            }
+            row.extend(std::iter::repeat_n(None, empty));
+            board.push(row);
+        }
+
+        // Without a variant the size of the board decides it, as in Capablanca chess or minichess
+        let sized = ENGINE_VARIANTS.into_iter().find(|variant| variant.dimensions() == (board.len(), board[0].len())).unwrap_or(Variant::Standard);
+        let (ranks, files) = variant.unwrap_or(sized).dimensions();
+        if board.len() != ranks {
+            return Err(format!("FEN needs {} ranks: {}", ranks, fields[0]));
+        }
+        for (x, row) in board.iter().enumerate() {
+            if row.len() != files {
+                return Err(format!("Wrong number of squares on rank {}", ranks - x));
+            }
+        }
+
+        // Every rule in the rules module needs both kings on the board, unless the king is an ordinary piece
+        for color in [Color::White, Color::Black] {
+            if !variant.is_none_or(|variant| variant.needs_king(color)) {
+                continue;
+            }
+            let kings = board.iter().flatten().flatten()
+                .filter(|piece| piece.piece_type == PieceType::King && piece.color == color)
+                .count();
+            if kings != 1 {
+                return Err(format!("FEN must have exactly one {:?} king", color));
+            }
+        }
+
+        let side_to_move = match fields[1] {
+            "w" => Color::White,
+            "b" => Color::Black,
+            other => return Err(format!("Invalid side to move '{}'", other)),
+        };
+
+        // Castling rights are either KQkq, meaning the outermost rook on that side of the king, or the
+        // files of the rooks as in X-FEN and Shredder-FEN. Rights without a king and rook to go with them are ignored.
+        // In S-Chess the file letters give the other pieces on the back rank that have not moved yet,
+        // while a castling right means the king and that rook have not moved either.
+        let castling = fields[2];
+        if castling != "-" && !castling.chars().all(|c| "KQkqABCDEFGHIJabcdefghij".contains(c)) {
+            return Err(format!("Invalid castling rights '{}'", castling));
+        }
+        let mut white_castling = (false, false);
+        let mut black_castling = (false, false);
+        let mut castling_rooks = (0, files - 1);
+        let mut chess960 = false;
+        let mut unmoved = Vec::new();
+        for c in castling.chars().filter(|&c| c != '-') {
+            let color = if c.is_ascii_uppercase() { Color::White } else { Color::Black };
+            let x = if color == Color::White { ranks - 1 } else { 0 };
+            if gating && !"KQkq".contains(c) {
+                let y = c.to_ascii_lowercase() as usize - 'a' as usize;
+                if matches!(board[x].get(y), Some(Some(piece)) if piece.color == color) && !unmoved.contains(&(x, y)) {
+                    unmoved.push((x, y));
+                }
+                continue;
+            }
+            let is_own = |y: usize, piece_type: PieceType| matches!(board[x].get(y), Some(Some(piece)) if piece.piece_type == piece_type && piece.color == color);
+            let king_y = match (0..files).find(|&y| is_own(y, PieceType::King)) {
+                Some(king_y) => king_y,
+                None => continue,
+            };
+            let rook_y = match c.to_ascii_lowercase() {
+                'k' => (king_y + 1..files).rev().find(|&y| is_own(y, PieceType::Rook)),
+                'q' => (0..king_y).find(|&y| is_own(y, PieceType::Rook)),
+                file => Some(file as usize - 'a' as usize).filter(|&y| y != king_y && is_own(y, PieceType::Rook)),
+            };
+            let rook_y = match rook_y {
+                Some(rook_y) => rook_y,
+                None => continue,
+            };
+            let rights = if color == Color::White { &mut white_castling } else { &mut black_castling };
+            if rook_y > king_y {
+                rights.1 = true;
+                castling_rooks.1 = rook_y;
+            } else {
+                rights.0 = true;
+                castling_rooks.0 = rook_y;
+            }
+            if king_y != files / 2 || (rook_y != 0 && rook_y != files - 1) {
+                chess960 = true;
+            }
+            if gating {
+                for square in [(x, king_y), (x, rook_y)] {
+                    if !unmoved.contains(&square) {
+                        unmoved.push(square);
+                    }
+                }
+            }
+        }
+
+        let en_passant = match fields[3] {
+            "-" => None,
+            square => Some(parse_square(square, ranks).ok_or(format!("Invalid en passant square '{}'", square))?),
+        };
+
+        // Three-check counters come either as the checks left before the move counters, as in "3+3 0 1",
+        // or as the checks given after them, as in "0 1 +0+0". Variants counting another number of
+        // checks write them the same way.
+        let limit = variant.and_then(Variant::check_limit).unwrap_or(3);
+        let mut counters = Vec::new();
+        let mut checks = None;
+        for field in &fields[4..] {
+            match field.split('+').map(|n| n.parse::<u32>()).collect::<Vec<_>>()[..] {
+                [Ok(white_left), Ok(black_left)] => checks = Some((limit - white_left.min(limit), limit - black_left.min(limit))),
+                [Err(_), Ok(white_given), Ok(black_given)] => checks = Some((white_given, black_given)),
+                _ => counters.push(*field),
+            }
+        }
+        let halfmove_clock = counters.first().and_then(|f| f.parse().ok()).unwrap_or(0);
+        let fullmove_number = counters.get(1).and_then(|f| f.parse().ok()).unwrap_or(1);
+        let (white_checks, black_checks) = checks.unwrap_or_default();
+
+        let variant = variant.unwrap_or(match (pocket_pieces, checks) {
+            (Some(_), _) => Variant::Crazyhouse,
+            (None, Some(_)) => Variant::ThreeCheck,
+            (None, None) => sized,
+        });
+        // Fairy pieces that were given are allowed whatever the variant
+        let allowed = |piece_type: &PieceType| matches!(piece_type, PieceType::Fairy(_)) || variant.has_piece(piece_type);
+        if let Some(piece) = board.iter().flatten().flatten().find(|piece| !allowed(&piece.piece_type)) {
+            return Err(format!("There is no {:?} in {}", piece.piece_type, variant.title()));
+        }
+
+        Ok(Position {
+            board,
+            side_to_move,
+            white_castling,
+            black_castling,
+            chess960,
+            castling_rooks,
+            en_passant,
+            halfmove_clock,
+            fullmove_number,
+            variant,
+            white_pocket,
+            black_pocket,
+            promoted,
+            white_checks,
+            black_checks,
+            white_reserve,
+            black_reserve,
+            unmoved,
+        })
+    }
+
+    // Write the position in Forsyth-Edwards Notation
+    pub fn to_fen(&self) -> String {
+        let mut placement = Vec::new();
+        for (x, row) in self.board.iter().enumerate() {
+            let mut rank = String::new();
+            let mut empty = 0;
+            for (y, square) in row.iter().enumerate() {
+                match square {
+                    Some(piece) => {
+                        if empty > 0 {
+                            rank.push_str(&empty.to_string());
+                            empty = 0;
+                        }
+                        rank.push(piece_to_char(piece));
+                        if self.promoted.contains(&(x, y)) {
+                            rank.push('~');
+                        }
+                    },
+                    None => empty += 1,
+                }
+            }
+            if empty > 0 {
+                rank.push_str(&empty.to_string());
+            }
+            placement.push(rank);
+        }
+
+        // Castling rights are written as in X-FEN: KQkq unless another rook stands further out
+        // on the same side in Chess960, in which case the rook's file is given instead
+        let mut castling = String::new();
+        for (color, rights) in [(Color::White, self.white_castling), (Color::Black, self.black_castling)] {
+            let x = if color == Color::White { self.board.len() - 1 } else { 0 };
+            let is_own_rook = |y: usize| matches!(self.board[x][y], Some(ref piece) if piece.piece_type == PieceType::Rook && piece.color == color);
+            let (queenside, kingside) = self.castling_rooks;
+            for (has_right, rook_y, letter, mut outside) in [(rights.1, kingside, 'k', kingside + 1..self.board[x].len()), (rights.0, queenside, 'q', 0..queenside)] {
+                if !has_right {
+                    continue;
+                }
+                let c = if self.chess960 && outside.any(is_own_rook) { (b'a' + rook_y as u8) as char } else { letter };
+                castling.push(if color == Color::White { c.to_ascii_uppercase() } else { c });
+            }
+
+            // S-Chess adds the files of the unmoved pieces the castling rights do not already cover
+            if self.variant.has_gating() {
+                let king_y = find_king(&self.board, color).filter(|&(king_x, _)| king_x == x && (rights.0 || rights.1)).map(|(_, y)| y);
+                let mut files: Vec<usize> = self.unmoved.iter().filter(|&&(unmoved_x, _)| unmoved_x == x).map(|&(_, y)| y).collect();
+                files.retain(|&y| Some(y) != king_y && !(rights.0 && y == queenside) && !(rights.1 && y == kingside));
+                files.sort();
+                for y in files {
+                    let c = (b'a' + y as u8) as char;
+                    castling.push(if color == Color::White { c.to_ascii_uppercase() } else { c });
+                }
+            }
+        }
+        if castling.is_empty() {
+            castling.push('-');
+        }
+
+        let mut placement = placement.join("/");
+        if self.variant.has_drops() {
+            placement.push('[');
+            for (color, pocket) in [(Color::White, &self.white_pocket), (Color::Black, &self.black_pocket)] {
+                placement.push_str(pocket_to_string(pocket, color).trim_end_matches('-'));
+            }
+            placement.push(']');
+        } else if self.variant.has_gating() {
+            placement.push('[');
+            for color in [Color::White, Color::Black] {
+                placement.push_str(reserve_to_string(self.reserve(color), color).trim_end_matches('-'));
+            }
+            placement.push(']');
+        }
+
+        let mut en_passant = self.en_passant.map(|square| square_to_string(square, self.board.len())).unwrap_or("-".to_string());
+        if let Some(limit) = self.variant.check_limit() {
+            let checks_left = |given: u32| limit.saturating_sub(given);
+            en_passant += &format!(" {}+{}", checks_left(self.white_checks), checks_left(self.black_checks));
+        }
+
+        format!(
+            "{} {} {} {} {} {}",
+            placement,
+            if self.side_to_move == Color::White { "w" } else { "b" },
+            castling,
+            en_passant,
+            self.halfmove_clock,
+            self.fullmove_number,
+        )
+    }
+
+    // Get the castling rights of the given color
+    pub fn castling_rights(&self, color: Color) -> (bool, bool) {
+        match color {
+            Color::White => self.white_castling,
+            Color::Black => self.black_castling,
+        }
+    }
+
+    // Get the pocket of the given color
+    pub fn pocket(&self, color: Color) -> &Pocket {
+        match color {
+            Color::White => &self.white_pocket,
+            Color::Black => &self.black_pocket,
+        }
+    }
+
+    // Get the S-Chess reserve of the given color
+    pub fn reserve(&self, color: Color) -> &Vec<PieceType> {
+        match color {
+            Color::White => &self.white_reserve,
+            Color::Black => &self.black_reserve,
+        }
+    }
+
+    // Get the pocket of the given color to add or remove pieces
+    pub fn pocket_mut(&mut self, color: Color) -> &mut Pocket {
+        match color {
+            Color::White => &mut self.white_pocket,
+            Color::Black => &mut self.black_pocket,
+        }
+    }
+
+    // Check if a move is castling, which in Chess960 is written as the king taking its own rook
+    pub fn is_castling(&self, mv: &Move) -> bool {
+        let (start_x, start_y) = mv.start;
+        let (end_x, end_y) = mv.end;
+        let color = match self.board[start_x][start_y] {
+            Some(ref piece) if piece.piece_type == PieceType::King && mv.drop.is_none() => piece.color,
+            _ => return false,
+        };
+        if self.chess960 {
+            matches!(self.board[end_x][end_y], Some(ref piece) if piece.piece_type == PieceType::Rook && piece.color == color)
+        } else {
+            // The king moves two squares, or three on a 10x8 board
+            start_x == end_x && start_y.abs_diff(end_y) >= 2
+        }
+    }
+
+    // Write a castling move the other way from how this position writes it: as the king taking its
+    // rook in standard chess, or as the king moving to the c or g file in Chess960
+    fn castling_alternative(&self, mv: &Move) -> String {
+        let kingside = mv.end.1 > mv.start.1;
+        let (queenside_to, kingside_to) = castling_king_files(self.board[mv.start.0].len());
+        let end_y = match (self.chess960, kingside) {
+            (true, true) => kingside_to,
+            (true, false) => queenside_to,
+            (false, true) => self.castling_rooks.1,
+            (false, false) => self.castling_rooks.0,
+        };
+        let ranks = self.board.len();
+        square_to_string(mv.start, ranks) + &square_to_string((mv.start.0, end_y), ranks)
+    }
+
+    // Get the piece a move captures as it would go into a pocket, with promoted pieces turned back into pawns
+    pub fn pocket_capture(&self, mv: &Move) -> Option<PieceType> {
+        if mv.drop.is_some() || self.is_castling(mv) {
+            return None;
+        }
+        let (end_x, end_y) = mv.end;
+        match self.board[end_x][end_y] {
+            Some(_) if self.promoted.contains(&mv.end) => Some(PieceType::Pawn),
+            Some(ref piece) => Some(piece.piece_type.clone()),
+            None => {
+                let is_pawn = matches!(self.board[mv.start.0][mv.start.1], Some(ref piece) if piece.piece_type == PieceType::Pawn);
+                if is_pawn && Some(mv.end) == self.en_passant && mv.start.1 != end_y {
+                    Some(PieceType::Pawn)
+                } else {
+                    None
+                }
+            },
+        }
+    }
+
+    // Check if the side to move is in check, which never happens when the variant's king is not royal
+    pub fn in_check(&self) -> bool {
+        match self.variant {
+            Variant::Atomic => is_in_atomic_check(&self.board, self.side_to_move),
+            variant => variant.has_royal_king() && is_in_check(&self.board, self.side_to_move),
+        }
+    }
+
+    // Get the function deciding if a king is in check under the variant's rules. Duck chess has
+    // no check, so the king may even castle through an attack.
+    fn check_rule(&self) -> fn(&Board, Color) -> bool {
+        match self.variant {
+            Variant::Atomic => is_in_atomic_check,
+            Variant::Duck => |_, _| false,
+            _ => is_in_check,
+        }
+    }
+
+    // Check if a player's last move left their own king in check, or in atomic chess blew it up.
+    // Blowing up the enemy king and not the own one wins the game even from check.
+    fn exposes_king(&self, color: Color) -> bool {
+        match self.variant {
+            Variant::Atomic if find_king(&self.board, color).is_none() => true,
+            Variant::Atomic => find_king(&self.board, opponent(color)).is_some() && is_in_atomic_check(&self.board, color),
+            variant => variant.has_royal_king() && is_in_check(&self.board, color),
+        }
+    }
+
+    // Check if the side to move has been checkmated
+    pub fn is_checkmate(&self) -> bool {
+        self.in_check() && self.legal_moves().is_empty()
+    }
+
+    // Check if a move takes a piece, including en passant
+    pub fn is_capture(&self, mv: &Move) -> bool {
+        mv.drop.is_none() && !self.is_castling(mv) && (self.board[mv.end.0][mv.end.1].is_some() || self.is_en_passant(mv))
+    }
+
+    // Check if a move is a pawn taking en passant
+    fn is_en_passant(&self, mv: &Move) -> bool {
+        Some(mv.end) == self.en_passant && matches!(self.board[mv.start.0][mv.start.1], Some(ref piece) if piece.piece_type == PieceType::Pawn)
+    }
+
+    // Generate the moves allowed by the rules module, without checking if they leave the king in check
+    pub fn pseudo_legal_moves(&self) -> Vec<Move> {
+        let mut moves = Vec::new();
+        let color = self.side_to_move;
+        let last_rank = if color == Color::White { 0 } else { self.board.len() - 1 };
+
+        for (x, row) in self.board.iter().enumerate() {
+            for (y, square) in row.iter().enumerate() {
+                let piece = match square {
+                    Some(piece) if piece.color == color => piece,
+                    _ => continue,
+                };
+                for end_x in 0..self.board.len() {
+                    for end_y in 0..row.len() {
+                        let start = (x, y);
+                        let end = (end_x, end_y);
+                        let valid = is_valid_move(&self.board, piece, start, end)
+                            || is_valid_en_passant(&self.board, piece, start, end, self.en_passant)
+                            || self.variant == Variant::Horde && is_valid_first_rank_double_step(&self.board, piece, start, end)
+                            || self.variant.has_castling() && if self.chess960 {
+                                is_valid_chess960_castling(&self.board, piece, start, end, self.castling_rights(color), self.castling_rooks, self.check_rule())
+                            } else {
+                                is_valid_castling(&self.board, piece, start, end, self.castling_rights(color), self.check_rule())
+                            };
+                        if !valid {
+                            continue;
+                        }
+                        // Minichess pawns only ever step forward one square
+                        if !self.variant.has_double_step() && piece.piece_type == PieceType::Pawn && x.abs_diff(end_x) == 2 {
+                            continue;
+                        }
+                        // In atomic chess the king cannot capture, as it would blow itself up
+                        if self.variant == Variant::Atomic && piece.piece_type == PieceType::King
+                            && matches!(self.board[end_x][end_y], Some(ref other) if other.color != color) {
+                            continue;
+                        }
+                        if piece.piece_type == PieceType::Pawn && end_x == last_rank {
+                            if is_valid_pawn_promotion(&self.board, piece, start, end, PieceType::Queen) {
+                                for promotion in self.variant.promotion_pieces() {
+                                    moves.push(Move { start, end, promotion: Some(promotion.clone()), drop: None, duck: None, gating: None });
+                                }
+                            }
+                        } else {
+                            moves.push(Move { start, end, promotion: None, drop: None, duck: None, gating: None });
+                        }
+                    }
+                }
+            }
+        }
+
+        // Any piece in hand can be dropped on an empty square
+        if self.variant.has_drops() {
+            for (piece_type, &count) in POCKET_PIECES.iter().zip(self.pocket(color)) {
+                if count == 0 {
+                    continue;
+                }
+                let piece = Piece { piece_type: piece_type.clone(), color };
+                for x in 0..self.board.len() {
+                    for y in 0..self.board[x].len() {
+                        if is_valid_drop(&self.board, &piece, (x, y)) {
+                            moves.push(Move { start: (x, y), end: (x, y), promotion: None, drop: Some(piece_type.clone()), duck: None, gating: None });
+                        }
+                    }
+                }
+            }
+        }
+
+        // In S-Chess a piece leaving its back rank square for the first time can let a piece from
+        // the reserve in behind it
+        if self.variant.has_gating() && !self.reserve(color).is_empty() {
+            let gated: Vec<Move> = moves.iter().flat_map(|mv| self.gating_moves(mv)).collect();
+            moves.extend(gated);
+        }
+
+        // In Duck chess each move is completed by putting the duck on another empty square
+        if self.variant == Variant::Duck {
+            moves = moves.into_iter().flat_map(|mv| self.with_duck_moves(mv)).collect();
+        }
+        moves
+    }
+
+    // Get the ways of gating a piece from the reserve onto a square the move leaves empty. Castling
+    // empties both the king's and the rook's square, and the piece can go on either.
+    fn gating_moves(&self, mv: &Move) -> Vec<Move> {
+        let mut squares = vec![mv.start];
+        if self.is_castling(mv) {
+            let rook_y = match (self.chess960, mv.end.1 > mv.start.1) {
+                (true, _) => mv.end.1,
+                (false, true) => self.castling_rooks.1,
+                (false, false) => self.castling_rooks.0,
+            };
+            squares.push((mv.start.0, rook_y));
+        }
+        let mut next = self.clone();
+        next.make_move(mv);
+
+        let mut moves = Vec::new();
+        for square in squares.into_iter().filter(|square| self.unmoved.contains(square) && next.board[square.0][square.1].is_none()) {
+            for (i, piece_type) in self.reserve(self.side_to_move).iter().enumerate() {
+                if !self.reserve(self.side_to_move)[..i].contains(piece_type) {
+                    moves.push(Move { gating: Some((piece_type.clone(), square)), ..mv.clone() });
+                }
+            }
+        }
+        moves
+    }
+
+    // Get the ways of completing a move by moving the duck to an empty square other than its own,
+    // looking at the board as it will be after the move
+    fn with_duck_moves(&self, mv: Move) -> Vec<Move> {
+        let mut next = self.clone();
+        next.make_move(&mv);
+        let mut moves = Vec::new();
+        for (x, row) in next.board.iter().enumerate() {
+            for (y, square) in row.iter().enumerate() {
+                if square.is_none() {
+                    moves.push(Move { duck: Some((x, y)), ..mv.clone() });
+                }
+            }
+        }
+        moves
+    }
+
+    // Generate all legal moves for the side to move, which are none once the variant's own goal has been reached
+    pub fn legal_moves(&self) -> Vec<Move> {
+        if variant_outcome(self).is_some() {
+            return Vec::new();
+        }
+        self.legal_moves_ignoring_outcome()
+    }
+
+    // Generate the moves the rules allow without checking if the game is already over
+    pub fn legal_moves_ignoring_outcome(&self) -> Vec<Move> {
+        let moves: Vec<Move> = self.pseudo_legal_moves()
+            .into_iter()
+            .filter(|mv| {
+                if !self.variant.has_royal_king() {
+                    return true;
+                }
+                let mut next = self.clone();
+                next.make_move(mv);
+                if next.exposes_king(self.side_to_move) {
+                    return false;
+                }
+                // Racing Kings does not allow giving check either
+                self.variant != Variant::RacingKings || !is_in_check(&next.board, opponent(self.side_to_move))
+            })
+            .collect();
+
+        // Where captures are compulsory, any capture rules out every other move
+        if self.variant.captures_compulsory() && moves.iter().any(|mv| self.is_capture(mv)) {
+            return moves.into_iter().filter(|mv| self.is_capture(mv)).collect();
+        }
+        moves
+    }
+
+    // Format a move on this position's board in long algebraic notation
+    pub fn move_to_string(&self, mv: &Move) -> String {
+        move_to_string(mv, self.board.len())
+    }
+
+    // Parse a move in long algebraic notation and return it if it is legal in this position.
+    // Castling is also understood when written as the king taking its rook, or in Chess960 as the king's move.
+    pub fn parse_move(&self, s: &str) -> Option<Move> {
+        let legal_moves = self.legal_moves();
+        if let Some(mv) = legal_moves.iter().find(|mv| self.move_to_string(mv) == s) {
+            return Some(mv.clone());
+        }
+        legal_moves.into_iter().find(|mv| self.is_castling(mv) && self.castling_alternative(mv) == s)
+    }
+
+    // Parse a move in standard algebraic notation such as "Nf3", "exd5", "O-O", "e8=Q+" or "N@f3"
+    // and return it if it is legal in this position. In Duck chess the duck's square follows a
+    // comma, as in "Nf3,d5", and in S-Chess a gated piece follows a slash, as in "Nf3/H". Castling
+    // gates onto the king's square unless the rook's is given, as in "O-O/Hh1".
+    pub fn parse_san(&self, san: &str) -> Option<Move> {
+        let ranks = self.board.len();
+        let (san, duck) = match san.split_once(',') {
+            Some((san, square)) => (san, Some(parse_square(square, ranks)?)),
+            None => (san, None),
+        };
+        let (san, gating) = match san.split_once('/') {
+            Some((san, gate)) => {
+                let mut chars = gate.chars();
+                let letter = chars.next()?.to_ascii_uppercase();
+                let square = match chars.as_str() {
+                    "" => None,
+                    square => Some(parse_square(square, ranks)?),
+                };
+                (san, Some((letter, square)))
+            },
+            None => (san, None),
+        };
+        let san = san.trim_end_matches(['+', '#', '!', '?']);
+        let legal_moves: Vec<Move> = self.legal_moves().into_iter().filter(|mv| {
+            let gates_as_written = match (&mv.gating, gating) {
+                (None, None) => true,
+                (Some((piece_type, gate_square)), Some((letter, square))) => {
+                    piece_letter(piece_type) == letter && square.unwrap_or(mv.start) == *gate_square
+                },
+                _ => false,
+            };
+            mv.duck == duck && gates_as_written
+        }).collect();
+
+        // Drops name the piece (a pawn if left out) and the square it is put on
+        if let Some((piece, square)) = san.split_once('@') {
+            let letter = match piece {
+                "" => 'P',
+                _ if piece.len() == 1 => piece.chars().next()?.to_ascii_uppercase(),
+                _ => return None,
+            };
+            let end = parse_square(square, self.board.len())?;
+            return legal_moves.into_iter().find(|mv| mv.end == end && mv.drop.as_ref().map(piece_letter) == Some(letter));
+        }
+
+        // Castling is written with the letter O (or sometimes zero), with one more for the queenside
+        let kingside = match san {
+            "O-O" | "0-0" => Some(true),
+            "O-O-O" | "0-0-0" => Some(false),
+            _ => None,
+        };
+        if let Some(kingside) = kingside {
+            return legal_moves.into_iter().find(|mv| self.is_castling(mv) && (mv.end.1 > mv.start.1) == kingside);
+        }
+
+        // Split off the promotion piece, written as "e8=Q" or "e8Q". Pieces are compared by their
+        // letters, so those defined at runtime are understood too.
+        let mut chars: Vec<char> = san.chars().filter(|&c| c != '=').collect();
+        let promotion = match chars.last() {
+            Some(&c) if c.is_ascii_uppercase() && chars.len() > 2 => {
+                chars.pop();
+                Some(c)
+            },
+            _ => None,
+        };
+
+        // The moving piece is given by an uppercase letter, otherwise it is a pawn
+        let letter = match chars.first() {
+            Some(&c) if c.is_ascii_uppercase() => {
+                chars.remove(0);
+                c
+            },
+            _ => 'P',
+        };
+
+        // The last two characters are the destination, anything before them disambiguates
+        if chars.len() < 2 {
+            return None;
+        }
+        let end = parse_square(&chars[chars.len() - 2..].iter().collect::<String>(), ranks)?;
+        let mut from_file = None;
+        let mut from_rank = None;
+        for &c in &chars[..chars.len() - 2] {
+            match c {
+                'a'..='j' => from_file = Some(c as usize - 'a' as usize),
+                '1'..='9' => from_rank = Some(ranks.checked_sub(c.to_digit(10)? as usize)?),
+                'x' => {},
+                _ => return None,
+            }
+        }
+
+        legal_moves.into_iter().find(|mv| {
+            let moving = match self.board[mv.start.0][mv.start.1] {
+                Some(ref piece) => piece_letter(&piece.piece_type),
+                None => return false,
+            };
+            moving == letter
+                && mv.end == end
+                && mv.promotion.as_ref().map(piece_letter) == promotion
+                && from_file.is_none_or(|y| mv.start.1 == y)
+                && from_rank.is_none_or(|x| mv.start.0 == x)
+        })
+    }
+
+    // Play a move on the board, updating castling rights, en passant square and move counters.
+    // The move is assumed to be legal.
+    pub fn make_move(&mut self, mv: &Move) {
+        // A gated piece comes out of the reserve onto the square left empty
+        if let Some((ref piece_type, (gate_x, gate_y))) = mv.gating {
+            let color = self.side_to_move;
+            self.make_move(&Move { gating: None, ..mv.clone() });
+            let reserve = match color {
+                Color::White => &mut self.white_reserve,
+                Color::Black => &mut self.black_reserve,
+            };
+            if let Some(i) = reserve.iter().position(|reserved| reserved == piece_type) {
+                reserve.remove(i);
+            }
+            self.board[gate_x][gate_y] = Some(Piece { piece_type: piece_type.clone(), color });
+            return;
+        }
+
+        // The duck moves after the piece, possibly to the square the piece has just left
+        if let Some((duck_x, duck_y)) = mv.duck {
+            self.make_move(&Move { duck: None, ..mv.clone() });
+            for square in self.board.iter_mut().flatten() {
+                if matches!(square, Some(piece) if piece.piece_type == PieceType::Duck) {
+                    *square = None;
+                }
+            }
+            self.board[duck_x][duck_y] = piece_from_char('*');
+            return;
+        }
+
+        let (start_x, start_y) = mv.start;
+        let (end_x, end_y) = mv.end;
+
+        // In crazyhouse the capturing side keeps the captured piece
+        if self.variant.keeps_captures() {
+            if let Some(slot) = self.pocket_capture(mv).as_ref().and_then(pocket_slot) {
+                let color = self.side_to_move;
+                self.pocket_mut(color)[slot] += 1;
+            }
+        }
+
+        // A drop takes the piece out of the pocket and puts it on the empty square
+        if let Some(ref piece_type) = mv.drop {
+            let color = self.side_to_move;
+            if let Some(slot) = pocket_slot(piece_type) {
+                self.pocket_mut(color)[slot] -= 1;
+            }
+            self.board[end_x][end_y] = Some(Piece { piece_type: piece_type.clone(), color });
+            self.en_passant = None;
+            if *piece_type == PieceType::Pawn {
+                self.halfmove_clock = 0;
+            } else {
+                self.halfmove_clock += 1;
+            }
+            if color == Color::Black {
+                self.fullmove_number += 1;
+            }
+            self.side_to_move = opponent(color);
+            return;
+        }
+
+        let piece = match self.board[start_x][start_y] {
+            Some(ref piece) => piece.clone(),
+            None => return,
+        };
+        let is_castling = self.is_castling(mv);
+        let is_capture = !is_castling && self.board[end_x][end_y].is_some();
+        let is_pawn = piece.piece_type == PieceType::Pawn;
+
+        // An en passant capture removes the pawn beside the moving pawn
+        let is_en_passant = is_pawn && Some(mv.end) == self.en_passant && !is_capture && start_y != end_y;
+        if is_en_passant {
+            self.board[start_x][end_y] = None;
+        }
+
+        // Castling puts the king on the c or g file, or the i file on a 10x8 board, and the rook on the other side of it
+        if is_castling {
+            let kingside = end_y > start_y;
+            let (queenside_to, kingside_to) = castling_king_files(self.board[start_x].len());
+            let rook_from = match (self.chess960, kingside) {
+                (true, _) => end_y,
+                (false, true) => self.castling_rooks.1,
+                (false, false) => self.castling_rooks.0,
+            };
+            let (king_to, rook_to) = if kingside { (kingside_to, kingside_to - 1) } else { (queenside_to, queenside_to + 1) };
+            let rook = self.board[start_x][rook_from].take();
+            self.unmoved.retain(|&square| square != (start_x, rook_from));
+            self.board[start_x][start_y] = None;
+            self.board[start_x][king_to] = Some(piece.clone());
+            self.board[start_x][rook_to] = rook;
+        }
+
+        // Keep track of where the promoted pieces are, since a capture sends them to the pocket as pawns
+        if self.variant.has_drops() {
+            self.promoted.retain(|&square| square != mv.end);
+            if let Some(square) = self.promoted.iter_mut().find(|square| **square == mv.start) {
+                *square = mv.end;
+            }
+            if mv.promotion.is_some() {
+                self.promoted.push(mv.end);
+            }
+        }
+
+        match mv.promotion {
+            Some(ref promotion) => perform_pawn_promotion(&mut self.board, &piece, mv.start, mv.end, promotion.clone()),
+            None if is_castling => {},
+            None => {
+                self.board[end_x][end_y] = Some(piece.clone());
+                self.board[start_x][start_y] = None;
+            },
+        }
+
+        // A piece that moves or is captured can no longer have a piece gated behind it
+        self.unmoved.retain(|&square| square != mv.start && square != mv.end);
+
+        // In atomic chess a capture blows up the capturing piece and the pieces around it
+        let mut exploded = Vec::new();
+        if self.variant == Variant::Atomic && (is_capture || is_en_passant) {
+            exploded = perform_explosion(&mut self.board, mv.end);
+        }
+
+        // Moving the king or a rook, or capturing or blowing up a rook, loses the matching castling rights
+        if piece.piece_type == PieceType::King {
+            match piece.color {
+                Color::White => self.white_castling = (false, false),
+                Color::Black => self.black_castling = (false, false),
+            }
+        }
+        let (queenside_rook, kingside_rook) = self.castling_rooks;
+        let home_rank = self.board.len() - 1;
+        for (x, y) in [mv.start, mv.end].into_iter().chain(exploded) {
+            let rights = match x {
+                x if x == home_rank => &mut self.white_castling,
+                0 => &mut self.black_castling,
+                _ => continue,
+            };
+            if y == queenside_rook {
+                rights.0 = false;
+            }
+            if y == kingside_rook {
+                rights.1 = false;
+            }
+        }
+
+        // A double pawn step from the second rank allows an en passant capture on the skipped square,
+        // but the first rank double step of Horde does not
+        self.en_passant = if is_pawn && (start_x as i32 - end_x as i32).abs() == 2 && (start_x == 1 || start_x + 2 == self.board.len()) {
+            Some(((start_x + end_x) / 2, start_y))
+        } else {
+            None
+        };
+
+        if is_pawn || is_capture {
+            self.halfmove_clock = 0;
+        } else {
+            self.halfmove_clock += 1;
+        }
+        if self.side_to_move == Color::Black {
+            self.fullmove_number += 1;
+        }
+        self.side_to_move = opponent(self.side_to_move);
+
+        if self.variant.check_limit().is_some() && self.in_check() {
+            match self.side_to_move {
+                Color::White => self.black_checks += 1,
+                Color::Black => self.white_checks += 1,
+            }
+        }
+    }
+}
+
+impl Default for Position {
+    fn default() -> Self {
+        Position::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Count the move sequences of the given length from a position
+    fn perft(position: &Position, depth: u32) -> u64 {
+        if depth == 0 {
+            return 1;
+        }
+        let moves = position.legal_moves();
+        if depth == 1 {
+            return moves.len() as u64;
+        }
+        moves
+            .iter()
+            .map(|mv| {
+                let mut next = position.clone();
+                next.make_move(mv);
+                perft(&next, depth - 1)
+            })
+            .sum()
+    }
+
+    fn check_perft(position: &Position, counts: &[u64]) {
+        for (depth, &count) in counts.iter().enumerate() {
+            assert_eq!(perft(position, depth as u32 + 1), count, "perft {} of {}", depth + 1, position.to_fen());
+        }
+    }
+
+    #[test]
+    fn standard_perft() {
+        check_perft(&Position::new(), &[20, 400, 8902]);
+        // Castling, en passant and promotions, from the positions of the Chess Programming Wiki
+        check_perft(&Position::from_fen("r3k2r/p1ppqpb1/bn2pnp1/3PN3/1p2P3/2N2Q1p/PPPBBPPP/R3K2R w KQkq - 0 1", None).unwrap(), &[48, 2039]);
+        check_perft(&Position::from_fen("8/2p5/3p4/KP5r/1R3p1k/8/4P1P1/8 w - - 0 1", None).unwrap(), &[14, 191, 2812]);
+        check_perft(&Position::from_fen("r3k2r/Pppp1ppp/1b3nbN/nP6/BBP1P3/q4N2/Pp1P2PP/R2Q1RK1 w kq - 0 1", None).unwrap(), &[6, 264, 9467]);
+        check_perft(&Position::from_fen("rnbq1k1r/pp1Pbppp/2p5/8/2B5/8/PPP1NnPP/RNBQK2R w KQ - 1 8", None).unwrap(), &[44, 1486]);
+    }
+
+    #[test]
+    fn fen_round_trips() {
+        for fen in [
+            "rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KQkq - 0 1",
+            "rnbqkbnr/pp1ppppp/8/2p5/4P3/8/PPPP1PPP/RNBQKBNR w KQkq c6 0 2",
+            "r3k2r/8/8/8/8/8/8/R3K2R b Kq - 12 40",
+            "8/8/8/4k3/8/8/8/4K3 w - - 0 1",
+        ] {
+            assert_eq!(Position::from_fen(fen, None).unwrap().to_fen(), fen);
+        }
+        assert!(Position::from_fen("rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP w KQkq -", None).is_err());
+        assert_eq!(Position::new().to_fen(), "rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KQkq - 0 1");
+    }
+
+    #[test]
+    fn crazyhouse_drops_and_pockets() {
+        check_perft(&Position::with_variant(Variant::Crazyhouse), &[20, 400, 8902]);
+        // A knight can be dropped on any empty square, a pawn on none of the first and last ranks
+        check_perft(&Position::from_fen("4k3/8/8/8/8/8/8/4K3[N] w - - 0 1", None).unwrap(), &[67]);
+        check_perft(&Position::from_fen("4k3/8/8/8/8/8/8/4K3[P] w - - 0 1", None).unwrap(), &[53]);
+
+        for fen in [
+            "rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR[] w KQkq - 0 1",
+            "r1bqk2r/pppp1ppp/2n5/4p3/1bB1n3/2N2N2/PPPP1PPP/R1BQK2R[QNPbp] w KQkq - 0 6",
+            "4k3/8/8/8/8/8/8/Q~3K3[Nn] b - - 0 30",
+        ] {
+            assert_eq!(Position::from_fen(fen, None).unwrap().to_fen(), fen);
+        }
+        // The pocket may also be written as a ninth rank, and either way makes it crazyhouse
+        let position = Position::from_fen("4k3/8/8/8/8/8/8/4K3/Nn w - - 0 1", None).unwrap();
+        assert_eq!(position.variant, Variant::Crazyhouse);
+        assert_eq!(position.to_fen(), "4k3/8/8/8/8/8/8/4K3[Nn] w - - 0 1");
+        assert_eq!(Position::from_fen("4k3/8/8/8/8/8/8/4K3 w - - 0 1", None).unwrap().variant, Variant::Standard);
+
+        // A captured piece goes to the capturer's pocket, and a promoted one as a pawn
+        let mut position = Position::from_fen("4k3/8/8/3q~4/4P3/8/8/4K3[] w - - 0 1", None).unwrap();
+        let capture = position.parse_san("exd5").unwrap();
+        position.make_move(&capture);
+        assert_eq!(position.to_fen(), "4k3/8/8/3P4/8/8/8/4K3[P] b - - 0 1");
+        let mut position = Position::from_fen("4k3/8/8/3q4/4P3/8/8/4K3[] w - - 0 1", None).unwrap();
+        position.make_move(&capture);
+        assert_eq!(position.to_fen(), "4k3/8/8/3P4/8/8/8/4K3[Q] b - - 0 1");
+    }
+
+    #[test]
+    fn chess960_perft_and_castling_rights() {
+        // Number 518 is the standard arrangement
+        assert_eq!(Position::chess960(518).to_fen(), Position::new().to_fen());
+        let position = Position::from_fen("bqnb1rkr/pp3ppp/3ppn2/2p5/5P2/P2P4/NPP1P1PP/BQ1BNRKR w HFhf - 2 9", None).unwrap();
+        check_perft(&position, &[21, 528, 12189]);
+        // Rights to the outermost rooks are written as KQkq, and to any other rook by its file
+        assert_eq!(position.to_fen(), "bqnb1rkr/pp3ppp/3ppn2/2p5/5P2/P2P4/NPP1P1PP/BQ1BNRKR w KQkq - 2 9");
+        let fen = "r3k3/8/8/8/8/8/8/R3K1RR w Gq - 0 1";
+        assert_eq!(Position::from_fen(fen, None).unwrap().to_fen(), fen);
+    }
+
+    #[test]
+    fn antichess_perft() {
+        check_perft(&Position::with_variant(Variant::Antichess), &[20, 400, 8067]);
+        // Captures are compulsory, and the king is an ordinary piece that can be taken
+        check_perft(&Position::from_fen("4k3/8/8/3p4/4P3/8/8/4K3 w - - 0 1", Some(Variant::Antichess)).unwrap(), &[1]);
+        check_perft(&Position::from_fen("8/8/8/8/8/8/3k4/4K3 w - - 0 1", Some(Variant::Antichess)).unwrap(), &[1]);
+    }
+
+    #[test]
+    fn atomic_perft() {
+        check_perft(&Position::with_variant(Variant::Atomic), &[20, 400, 8902]);
+        // A king cannot capture, since it would blow itself up
+        check_perft(&Position::from_fen("4k3/8/8/8/8/8/4p3/4K3 w - - 0 1", Some(Variant::Atomic)).unwrap(), &[2]);
+    }
+
+    #[test]
+    fn horde_and_racing_kings_perft() {
+        check_perft(&Position::with_variant(Variant::Horde), &[8, 128, 1274]);
+        check_perft(&Position::with_variant(Variant::RacingKings), &[21, 421, 11264]);
+    }
+
+    #[test]
+    fn capablanca_perft() {
+        check_perft(&Position::with_variant(Variant::Capablanca), &[28, 784]);
+        let fen = "rnabqkbcnr/pppppppppp/10/10/4P5/10/PPPP1PPPPP/RNABQKBCNR b KQkq e3 0 1";
+        assert_eq!(Position::from_fen(fen, Some(Variant::Capablanca)).unwrap().to_fen(), fen);
+    }
+
+    #[test]
+    fn minichess_perft() {
+        // On five ranks a pawn's first step already meets the opponent's pawns
+        check_perft(&Position::with_variant(Variant::Gardner), &[7, 53]);
+        check_perft(&Position::with_variant(Variant::LosAlamos), &[10, 100]);
+    }
+
+    #[test]
+    fn duck_perft() {
+        // Each of the 20 moves is completed by putting the duck on one of the 32 empty squares
+        let mut position = Position::with_variant(Variant::Duck);
+        check_perft(&position, &[640]);
+        position.make_move(&position.parse_move("e2e4,d5").unwrap());
+        assert_eq!(position.to_fen(), "rnbqkbnr/pppppppp/8/3*4/4P3/8/PPPP1PPP/RNBQKBNR b KQkq e3 0 1");
+        // The duck blocks d7-d5 and has to move to another of the 31 empty squares
+        check_perft(&position, &[19 * 31]);
+    }
+
+    #[test]
+    fn seirawan_gating() {
+        let mut position = Position::with_variant(Variant::Seirawan);
+        // Each of the four knight moves may gate in the hawk, the elephant or nothing
+        check_perft(&position, &[28, 784]);
+        assert_eq!(position.to_fen(), "rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR[HEhe] w KQBCDFGkqbcdfg - 0 1");
+
+        let mv = position.parse_san("Nf3/H").unwrap();
+        assert_eq!(position.move_to_string(&mv), "g1f3h");
+        position.make_move(&mv);
+        assert_eq!(position.to_fen(), "rnbqkbnr/pppppppp/8/8/8/5N2/PPPPPPPP/RNBQKBHR[Ehe] b KQBCDFkqbcdfg - 1 1");
+        assert_eq!(Position::from_fen(&position.to_fen(), Some(Variant::Seirawan)).unwrap().to_fen(), position.to_fen());
+        // The hawk cannot be gated twice, nor behind a piece that has already moved
+        assert!(position.parse_san("Nc6/H").is_some());
+        position.make_move(&position.parse_san("Nc6").unwrap());
+        assert!(position.parse_san("Ne5/E").is_none());
+        assert!(position.parse_san("Ne5").is_some());
+        assert!(position.parse_san("Nc3/H").is_none());
+        assert!(position.parse_san("Nc3/E").is_some());
+    }
+}
@@ -2,9 +2,20 @@ use crate::PieceType;
 use crate::Color;
 use crate::Piece;
 use crate::Board;
+use crate::betza::FairyPiece;
 
 // Check if the move is valid for the piece
 pub fn is_valid_move(board: &Board, piece: &Piece, start: (usize, usize), end: (usize, usize)) -> bool {
+    // A piece has to leave its square
+    if start == end {
+        return false;
+    }
+
+    // The duck is moved separately from the pieces, and cannot be captured
+    if piece.piece_type == PieceType::Duck || matches!(board[end.0][end.1], Some(ref target) if target.piece_type == PieceType::Duck) {
+        return false;
+    }
+
     match piece.piece_type {
         PieceType::Pawn => is_valid_pawn_move(board, piece, start, end),
         PieceType::Rook => is_valid_rook_move(board, piece, start, end),
@@ -12,6 +23,10 @@ pub fn is_valid_move(board: &Board, piece: &Piece, start: (usize, usize), end: (
         PieceType::Bishop => is_valid_bishop_move(board, piece, start, end),
         PieceType::Queen => is_valid_queen_move(board, piece, start, end),
         PieceType::King => is_valid_king_move(board, piece, start, end),
+        PieceType::Archbishop => is_valid_archbishop_move(board, piece, start, end),
+        PieceType::Chancellor => is_valid_chancellor_move(board, piece, start, end),
+        PieceType::Fairy(ref fairy) => is_valid_fairy_move(board, piece, fairy, start, end),
+        PieceType::Duck => false,
     }
 }
 
@@ -19,16 +34,17 @@ pub fn is_valid_move(board: &Board, piece: &Piece, start: (usize, usize), end: (
 pub fn is_valid_pawn_move(board: &Board, piece: &Piece, start: (usize, usize), end: (usize, usize)) -> bool {
     let (start_x, start_y) = start;
     let (end_x, end_y) = end;
+    let ranks = board.len();
 
     match piece.color {
         Color::White => {
-            if start_x == 6 && end_x == 4 && start_y == end_y && board[end_x][end_y].is_none() {
+            if start_x == ranks - 2 && end_x + 4 == ranks && start_y == end_y && board[ranks - 3][end_y].is_none() && board[end_x][end_y].is_none() {
                 // Initial double step move
                 true
-            } else if end_x == start_x - 1 && start_y == end_y && board[end_x][end_y].is_none() {
+            } else if end_x + 1 == start_x && start_y == end_y && board[end_x][end_y].is_none() {
                 // Regular move
                 true
-            } else if end_x == start_x - 1 && (end_y == start_y - 1 || end_y == start_y + 1) {
+            } else if end_x + 1 == start_x && (end_y + 1 == start_y || end_y == start_y + 1) {
                 // Capture
                 if let Some(end_piece) = &board[end_x][end_y] {
                     end_piece.color == Color::Black
@@ -40,13 +56,13 @@ pub fn is_valid_pawn_move(board: &Board, piece: &Piece, start: (usize, usize), e
             }
         },
         Color::Black => {
-            if start_x == 1 && end_x == 3 && start_y == end_y && board[end_x][end_y].is_none() {
+            if start_x == 1 && end_x == 3 && start_y == end_y && board[2][end_y].is_none() && board[end_x][end_y].is_none() {
                 // Initial double step move
                 true
             } else if end_x == start_x + 1 && start_y == end_y && board[end_x][end_y].is_none() {
                 // Regular move
                 true
-            } else if end_x == start_x + 1 && (end_y == start_y - 1 || end_y == start_y + 1) {
+            } else if end_x == start_x + 1 && (end_y + 1 == start_y || end_y == start_y + 1) {
                 // Capture
                 if let Some(end_piece) = &board[end_x][end_y] {
                     end_piece.color == Color::White
@@ -60,6 +76,23 @@ pub fn is_valid_pawn_move(board: &Board, piece: &Piece, start: (usize, usize), e
     }
 }
 
+// Check if the move is a double step by a pawn from its first rank, which Horde allows
+pub fn is_valid_first_rank_double_step(board: &Board, piece: &Piece, start: (usize, usize), end: (usize, usize)) -> bool {
+    let (start_x, start_y) = start;
+    let (end_x, end_y) = end;
+    let ranks = board.len();
+    let (first_rank, over, to) = match piece.color {
+        Color::White => (ranks - 1, ranks - 2, ranks - 3),
+        Color::Black => (0, 1, 2),
+    };
+    piece.piece_type == PieceType::Pawn
+        && start_x == first_rank
+        && end_x == to
+        && start_y == end_y
+        && board[over][end_y].is_none()
+        && board[end_x][end_y].is_none()
+}
+
 // Check if the move is valid for a rook
 pub fn is_valid_rook_move(board: &Board, piece: &Piece, start: (usize, usize), end: (usize, usize)) -> bool {
     let (start_x, start_y) = start;
@@ -71,19 +104,15 @@ pub fn is_valid_rook_move(board: &Board, piece: &Piece, start: (usize, usize), e
         // Moving along a file
         let min_y = start_y.min(end_y);
         let max_y = start_y.max(end_y);
-        for y in min_y + 1..max_y {
-            if board[start_x][y].is_some() {
-                return false;
-            }
+        if (min_y + 1..max_y).any(|y| board[start_x][y].is_some()) {
+            return false;
         }
     } else if start_y == end_y {
         // Moving along a rank
         let min_x = start_x.min(end_x);
         let max_x = start_x.max(end_x);
-        for x in min_x + 1..max_x {
-            if board[x][start_y].is_some() {
-                return false;
-            }
+        if (min_x + 1..max_x).any(|x| board[x][start_y].is_some()) {
+            return false;
         }
     } else {
         // Not a valid rook move
@@ -167,6 +196,51 @@ pub fn is_valid_queen_move(board: &Board, piece: &Piece, start: (usize, usize),
     is_valid_rook_move(board, piece, start, end) || is_valid_bishop_move(board, piece, start, end)
 }
 
+// Check if the move is valid for an archbishop, which moves like a bishop or a knight
+pub fn is_valid_archbishop_move(board: &Board, piece: &Piece, start: (usize, usize), end: (usize, usize)) -> bool {
+    is_valid_bishop_move(board, piece, start, end) || is_valid_knight_move(board, piece, start, end)
+}
+
+// Check if the move is valid for a chancellor, which moves like a rook or a knight
+pub fn is_valid_chancellor_move(board: &Board, piece: &Piece, start: (usize, usize), end: (usize, usize)) -> bool {
+    is_valid_rook_move(board, piece, start, end) || is_valid_knight_move(board, piece, start, end)
+}
+
+// Check if the move is valid for a piece defined by Betza notation. Its steps are given from the
+// player's point of view, so Black's are turned around.
+pub fn is_valid_fairy_move(board: &Board, piece: &Piece, fairy: &FairyPiece, start: (usize, usize), end: (usize, usize)) -> bool {
+    let (start_x, start_y) = (start.0 as i32, start.1 as i32);
+    let (end_x, end_y) = (end.0 as i32, end.1 as i32);
+    let (forward, right) = match piece.color {
+        Color::White => (start_x - end_x, end_y - start_y),
+        Color::Black => (end_x - start_x, start_y - end_y),
+    };
+    let orientation = if piece.color == Color::White { 1 } else { -1 };
+
+    // Moving and capturing may be allowed by different steps
+    let capturing = match &board[end.0][end.1] {
+        Some(end_piece) if end_piece.color == piece.color => return false,
+        Some(_) => true,
+        None => false,
+    };
+    fairy.steps.iter().filter(|step| if capturing { step.captures } else { step.moves }).any(|step| {
+        // The destination must be a whole number of steps away along the step's line
+        let distance = if step.forward != 0 { forward / step.forward } else { right / step.right };
+        if distance < 1 || (step.forward * distance, step.right * distance) != (forward, right) {
+            return false;
+        }
+        if step.range != 0 && distance > step.range as i32 {
+            return false;
+        }
+        // A rider cannot jump over the squares on the way
+        (1..distance).all(|i| {
+            let x = start_x - orientation * step.forward * i;
+            let y = start_y + orientation * step.right * i;
+            board[x as usize][y as usize].is_none()
+        })
+    })
+}
+
 // Check if the move is valid for a king
 pub fn is_valid_king_move(board: &Board, piece: &Piece, start: (usize, usize), end: (usize, usize)) -> bool {
     let (start_x, start_y) = start;
@@ -200,22 +274,36 @@ pub fn is_valid_en_passant(board: &Board, piece: &Piece, start: (usize, usize),
     }
 
     // The pawn must be on its fifth rank
-    if (piece.color == Color::White && start_x != 3) || (piece.color == Color::Black && start_x != 4) {
+    if (piece.color == Color::White && start_x != 3) || (piece.color == Color::Black && start_x + 4 != board.len()) {
         return false;
     }
 
-    // The destination square must be the en passant square
-    if Some((end_x, end_y)) != en_passant_square {
+    // The destination square must be the en passant square, and empty, which it is unless the duck was put there
+    if Some((end_x, end_y)) != en_passant_square || board[end_x][end_y].is_some() {
         return false;
     }
 
-    true
+    // The pawn must move one square diagonally forward
+    let forward = if piece.color == Color::White { start_x - 1 } else { start_x + 1 };
+    if end_x != forward || (start_y as i32 - end_y as i32).abs() != 1 {
+        return false;
+    }
+
+    // The captured pawn must be beside the moving pawn
+    match &board[start_x][end_y] {
+        Some(captured) => captured.piece_type == PieceType::Pawn && captured.color != piece.color,
+        None => false,
+    }
 }
 
-// Check if the move is a valid castling move
-pub fn is_valid_castling(board: &Board, piece: &Piece, start: (usize, usize), end: (usize, usize), can_castle: (bool, bool)) -> bool {
+// Check if the move is a valid castling move. Check is decided by in_check, which differs between variants.
+// The king starts in the middle of the back rank and ends up on the third file from the corner,
+// which on a 10x8 board is a move of three squares.
+pub fn is_valid_castling(board: &Board, piece: &Piece, start: (usize, usize), end: (usize, usize), can_castle: (bool, bool), in_check: fn(&Board, Color) -> bool) -> bool {
     let (start_x, start_y) = start;
     let (end_x, end_y) = end;
+    let files = board[start_x].len();
+    let (queenside_to, kingside_to) = castling_king_files(files);
 
     // Castling can only be performed by a king
     if piece.piece_type != PieceType::King {
@@ -223,7 +311,7 @@ pub fn is_valid_castling(board: &Board, piece: &Piece, start: (usize, usize), en
     }
 
     // The king must not have moved before
-    if start_x != if piece.color == Color::White { 7 } else { 0 } || start_y != 4 {
+    if start_x != if piece.color == Color::White { board.len() - 1 } else { 0 } || start_y != files / 2 {
         return false;
     }
 
@@ -232,39 +320,114 @@ pub fn is_valid_castling(board: &Board, piece: &Piece, start: (usize, usize), en
         return false;
     }
 
-    // The king must move two squares towards the rook
-    if end_y != 2 && end_y != 6 {
+    // The king must move towards the rook
+    if end_y != queenside_to && end_y != kingside_to {
         return false;
     }
 
     // The corresponding rook must not have moved before
-    if end_y == 2 && !can_castle.0 || end_y == 6 && !can_castle.1 {
+    let queenside = end_y == queenside_to;
+    if queenside && !can_castle.0 || !queenside && !can_castle.1 {
         return false;
     }
 
+    // The rook must still be standing in its corner
+    let rook_y = if queenside { 0 } else { files - 1 };
+    match &board[start_x][rook_y] {
+        Some(rook) if rook.piece_type == PieceType::Rook && rook.color == piece.color => {},
+        _ => return false,
+    }
+
     // There must be no pieces between the king and the rook
-    let y_range = if end_y == 2 { 1..4 } else { 5..7 };
-    for y in y_range {
-        if board[start_x][y].is_some() {
+    let y_range = if queenside { 1..start_y } else { start_y + 1..files - 1 };
+    if y_range.into_iter().any(|y| board[start_x][y].is_some()) {
+        return false;
+    }
+
+    // The king must not be in check, pass through an attacked square or end up in check
+    if in_check(board, piece.color) {
+        return false;
+    }
+    let passed = if queenside { end_y..start_y } else { start_y + 1..end_y + 1 };
+    for y in passed {
+        let mut new_board = board.clone();
+        new_board[end_x][y] = Some(piece.clone());
+        new_board[start_x][start_y] = None;
+        if in_check(&new_board, piece.color) {
             return false;
         }
     }
 
-    // The king must not be in check in the final position
-    let mut new_board = board.clone();
-    new_board[end_x][end_y] = Some(piece.clone());
-    new_board[start_x][start_y] = None;
-    if is_in_check(&new_board, piece.color) {
+    true
+}
+
+// Get the files the king castles to on the (queenside, kingside), which are the c and g files on an
+// 8x8 board. The rook ends up beside the king on the inside.
+pub fn castling_king_files(files: usize) -> (usize, usize) {
+    (2, files - 2)
+}
+
+// Check if the move is a valid Chess960 castling move, given as the king taking its own rook.
+// The king may start on any file between the rooks, whose files are given as (queenside, kingside),
+// and ends up on the c or g file with the rook beside it on the d or f file like in standard chess.
+pub fn is_valid_chess960_castling(board: &Board, piece: &Piece, start: (usize, usize), end: (usize, usize), can_castle: (bool, bool), rook_files: (usize, usize), in_check: fn(&Board, Color) -> bool) -> bool {
+    let (start_x, start_y) = start;
+    let (end_x, end_y) = end;
+
+    // Castling can only be performed by a king on its initial rank
+    if piece.piece_type != PieceType::King || start_x != if piece.color == Color::White { board.len() - 1 } else { 0 } {
         return false;
     }
 
-    true
+    // The king must take one of its own rooks on the same rank
+    if end_x != start_x {
+        return false;
+    }
+    match &board[end_x][end_y] {
+        Some(rook) if rook.piece_type == PieceType::Rook && rook.color == piece.color => {},
+        _ => return false,
+    }
+
+    // The rook must be the unmoved one on that side of the king
+    let queenside = end_y < start_y;
+    let (has_right, rook_file) = if queenside { (can_castle.0, rook_files.0) } else { (can_castle.1, rook_files.1) };
+    if !has_right || end_y != rook_file {
+        return false;
+    }
+
+    // Every square the king and rook cross or land on must be empty, apart from the two of them
+    let (queenside_to, kingside_to) = castling_king_files(board[start_x].len());
+    let (king_to, rook_to) = if queenside { (queenside_to, queenside_to + 1) } else { (kingside_to, kingside_to - 1) };
+    let crossed = |from: usize, to: usize| from.min(to)..=from.max(to);
+    if crossed(start_y, king_to).chain(crossed(end_y, rook_to)).any(|y| y != start_y && y != end_y && board[start_x][y].is_some()) {
+        return false;
+    }
+
+    // The king must not be in check or pass through a square attacked as the pieces stand now
+    if in_check(board, piece.color) {
+        return false;
+    }
+    for y in crossed(start_y, king_to).filter(|&y| y != king_to) {
+        let mut new_board = board.clone();
+        new_board[start_x][start_y] = None;
+        new_board[start_x][y] = Some(piece.clone());
+        if in_check(&new_board, piece.color) {
+            return false;
+        }
+    }
+
+    // Nor may it end up in check once the rook has moved beside it
+    let mut new_board = board.clone();
+    let rook = new_board[end_x][end_y].take();
+    new_board[start_x][start_y] = None;
+    new_board[start_x][rook_to] = rook;
+    new_board[start_x][king_to] = Some(piece.clone());
+    !in_check(&new_board, piece.color)
 }
 
-// Check if the king is in check
-pub fn is_in_check(board: &Board, color: Color) -> bool {
-    // Find the king
-    let (king_x, king_y) = board.iter().enumerate().find_map(|(x, row)| {
+// Find the king of the given color
+pub fn find_king(board: &Board, color: Color) -> Option<(usize, usize)> {
+    board.iter().enumerate().find_map(|(x, row)| {
         row.iter().enumerate().find_map(|(y, square)| {
             if let Some(piece) = square {
                 if piece.piece_type == PieceType::King && piece.color == color {
@@ -276,7 +439,15 @@ pub fn is_in_check(board: &Board, color: Color) -> bool {
                 None
             }
         })
-    }).unwrap();
+    })
+}
+
+// Check if the king is in check
+pub fn is_in_check(board: &Board, color: Color) -> bool {
+    // Find the king, which may have been captured in variants where it is an ordinary piece
+    let Some((king_x, king_y)) = find_king(board, color) else {
+        return false;
+    };
 
     // Check if any of the opponent's pieces can capture the king
     for (x, row) in board.iter().enumerate() {
@@ -292,6 +463,40 @@ pub fn is_in_check(board: &Board, color: Color) -> bool {
     false
 }
 
+// Check if the king is in check in atomic chess. Kings standing next to each other cannot attack
+// one another, and a king next to the enemy king is never in check, since capturing it would blow
+// up the capturer's own king too.
+pub fn is_in_atomic_check(board: &Board, color: Color) -> bool {
+    let other = if color == Color::White { Color::Black } else { Color::White };
+    match (find_king(board, color), find_king(board, other)) {
+        (Some(king), Some(other_king)) => !are_adjacent(king, other_king) && is_in_check(board, color),
+        _ => false,
+    }
+}
+
+// Check if two squares touch, including diagonally
+fn are_adjacent(a: (usize, usize), b: (usize, usize)) -> bool {
+    a.0.abs_diff(b.0) <= 1 && a.1.abs_diff(b.1) <= 1
+}
+
+// Blow up the square of an atomic capture: the capturing piece and every piece other than a pawn
+// on the squares around it are removed. Returns the squares that were emptied.
+pub fn perform_explosion(board: &mut Board, square: (usize, usize)) -> Vec<(usize, usize)> {
+    let (x, y) = square;
+    board[x][y] = None;
+    let mut emptied = vec![square];
+    let (last_x, last_y) = (board.len() - 1, board[x].len() - 1);
+    let around = (x.saturating_sub(1)..=(x + 1).min(last_x))
+        .flat_map(|around_x| (y.saturating_sub(1)..=(y + 1).min(last_y)).map(move |around_y| (around_x, around_y)));
+    for (around_x, around_y) in around {
+        if matches!(board[around_x][around_y], Some(ref piece) if piece.piece_type != PieceType::Pawn) {
+            board[around_x][around_y] = None;
+            emptied.push((around_x, around_y));
+        }
+    }
+    emptied
+}
+
 // Check if the game is in checkmate
 pub fn is_checkmate(board: &Board, color: Color) -> bool {
     // The player must be in check
@@ -305,8 +510,8 @@ pub fn is_checkmate(board: &Board, color: Color) -> bool {
             if let Some(piece) = square {
                 if piece.color == color {
                     // Try all possible moves for this piece
-                    for dx in 0..8 {
-                        for dy in 0..8 {
+                    for dx in 0..board.len() {
+                        for dy in 0..row.len() {
                             if is_valid_move(board, piece, (x, y), (dx, dy)) {
                                 // Check if this move would get the player out of check
                                 let mut new_board = board.clone();
@@ -339,10 +544,15 @@ pub fn is_stalemate(board: &Board, color: Color) -> bool {
             if let Some(piece) = square {
                 if piece.color == color {
                     // Try all possible moves for this piece
-                    for dx in 0..8 {
-                        for dy in 0..8 {
+                    for dx in 0..board.len() {
+                        for dy in 0..row.len() {
                             if is_valid_move(board, piece, (x, y), (dx, dy)) {
-                                return false;
+                                let mut new_board = board.clone();
+                                new_board[dx][dy] = Some(piece.clone());
+                                new_board[x][y] = None;
+                                if !is_in_check(&new_board, color) {
+                                    return false;
+                                }
                             }
                         }
                     }
@@ -356,8 +566,8 @@ pub fn is_stalemate(board: &Board, color: Color) -> bool {
 
 // Check if the move is a valid pawn promotion
 pub fn is_valid_pawn_promotion(board: &Board, piece: &Piece, start: (usize, usize), end: (usize, usize), promotion: PieceType) -> bool {
-    let (start_x, start_y) = start;
-    let (end_x, end_y) = end;
+    let (start_x, _) = start;
+    let (end_x, _) = end;
 
     // Pawn promotion can only be performed by a pawn
     if piece.piece_type != PieceType::Pawn {
@@ -365,12 +575,13 @@ pub fn is_valid_pawn_promotion(board: &Board, piece: &Piece, start: (usize, usiz
     }
 
     // The pawn must be on its seventh rank
-    if (piece.color == Color::White && start_x != 1) || (piece.color == Color::Black && start_x != 6) {
+    let last_rank = board.len() - 1;
+    if (piece.color == Color::White && start_x != 1) || (piece.color == Color::Black && start_x + 1 != last_rank) {
         return false;
     }
 
     // The destination square must be on the opponent's side of the board
-    if (piece.color == Color::White && end_x != 0) || (piece.color == Color::Black && end_x != 7) {
+    if (piece.color == Color::White && end_x != 0) || (piece.color == Color::Black && end_x != last_rank) {
         return false;
     }
 
@@ -379,11 +590,8 @@ pub fn is_valid_pawn_promotion(board: &Board, piece: &Piece, start: (usize, usiz
         return false;
     }
 
-    // The promotion must be to a queen, rook, bishop, or knight
-    match promotion {
-        PieceType::Queen | PieceType::Rook | PieceType::Bishop | PieceType::Knight => true,
-        _ => false,
-    }
+    // The promotion must be to a piece other than a pawn or king; which ones a variant allows is up to it
+    !matches!(promotion, PieceType::Pawn | PieceType::King)
 }
 
 // Perform a pawn promotion
@@ -399,4 +607,17 @@ pub fn perform_pawn_promotion(board: &mut Board, piece: &Piece, start: (usize, u
         piece_type: promotion,
         color: piece.color,
     });
-}
\ No newline at end of file
+}
+
+// Check if a piece from the pocket can be dropped on the square
+pub fn is_valid_drop(board: &Board, piece: &Piece, square: (usize, usize)) -> bool {
+    let (x, y) = square;
+
+    // Pieces can only be dropped on empty squares
+    if board[x][y].is_some() {
+        return false;
+    }
+
+    // Pawns cannot be dropped on the first or last rank
+    piece.piece_type != PieceType::Pawn || (x != 0 && x != board.len() - 1)
+}
@@ -0,0 +1,508 @@
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::{Duration, Instant};
+
+use crate::endgame::{EndgameResult, EndgameTables};
+use crate::position::{Move, Position, POCKET_PIECES};
+use crate::syzygy::{SyzygyTables, Wdl};
+use crate::variant::{variant_outcome, Outcome, Variant};
+use crate::{Color, PieceType};
+
+// Score for delivering checkmate; mates closer to the root score higher
+pub const MATE: i32 = 100_000;
+const MAX_PLY: i32 = 100;
+// Endgame tables can see mates much further away than the search can
+const MAX_MATE_DISTANCE: i32 = 1000;
+// Tablebase wins score below every mate but above any evaluation
+const TABLEBASE_WIN: i32 = MATE - 2 * MAX_MATE_DISTANCE;
+
+// The limits a search is run under, as given by the "go" command
+#[derive(Debug, Clone, Default)]
+pub struct SearchLimits {
+    pub depth: Option<u32>,
+    pub movetime: Option<Duration>,
+    pub nodes: Option<u64>,
+    pub white_time: Option<Duration>,
+    pub black_time: Option<Duration>,
+    pub white_increment: Option<Duration>,
+    pub black_increment: Option<Duration>,
+    pub moves_to_go: Option<u32>,
+    pub infinite: bool,
+    // Time kept in reserve for communication delays
+    pub move_overhead: Duration,
+}
+
+// The result of one completed iteration of the search
+#[derive(Debug, Clone)]
+pub struct SearchInfo {
+    pub depth: u32,
+    pub score: i32,
+    pub nodes: u64,
+    pub elapsed: Duration,
+    pub pv: Vec<Move>,
+}
+
+// Convert a mate score into the number of moves until mate, negative when being mated.
+// Variants like antichess can also be lost straight after the side to move's own move.
+pub fn mate_in(score: i32) -> Option<i32> {
+    if score > MATE - MAX_MATE_DISTANCE {
+        Some((MATE - score + 1) / 2)
+    } else if score < -MATE + MAX_MATE_DISTANCE {
+        Some(-(MATE + score + 1) / 2)
+    } else {
+        None
+    }
+}
+
+// Material value of each piece type in centipawns
+pub fn piece_value(piece_type: &PieceType) -> i32 {
+    match piece_type {
+        PieceType::Pawn => 100,
+        PieceType::Knight => 320,
+        PieceType::Bishop => 330,
+        PieceType::Rook => 500,
+        PieceType::Queen => 900,
+        PieceType::King => 0,
+        PieceType::Archbishop => 825,
+        PieceType::Chancellor => 875,
+        PieceType::Fairy(ref fairy) => fairy.value,
+        PieceType::Duck => 0,
+    }
+}
+
+// Piece-square tables from White's point of view, with rank 8 in the first row like the board
+const PAWN_TABLE: [[i32; 8]; 8] = [
+    [0, 0, 0, 0, 0, 0, 0, 0],
+    [50, 50, 50, 50, 50, 50, 50, 50],
+    [10, 10, 20, 30, 30, 20, 10, 10],
+    [5, 5, 10, 25, 25, 10, 5, 5],
+    [0, 0, 0, 20, 20, 0, 0, 0],
+    [5, -5, -10, 0, 0, -10, -5, 5],
+    [5, 10, 10, -20, -20, 10, 10, 5],
+    [0, 0, 0, 0, 0, 0, 0, 0],
+];
+
+const KNIGHT_TABLE: [[i32; 8]; 8] = [
+    [-50, -40, -30, -30, -30, -30, -40, -50],
+    [-40, -20, 0, 0, 0, 0, -20, -40],
+    [-30, 0, 10, 15, 15, 10, 0, -30],
+    [-30, 5, 15, 20, 20, 15, 5, -30],
+    [-30, 0, 15, 20, 20, 15, 0, -30],
+    [-30, 5, 10, 15, 15, 10, 5, -30],
+    [-40, -20, 0, 5, 5, 0, -20, -40],
+    [-50, -40, -30, -30, -30, -30, -40, -50],
+];
+
+const BISHOP_TABLE: [[i32; 8]; 8] = [
+    [-20, -10, -10, -10, -10, -10, -10, -20],
+    [-10, 0, 0, 0, 0, 0, 0, -10],
+    [-10, 0, 5, 10, 10, 5, 0, -10],
+    [-10, 5, 5, 10, 10, 5, 5, -10],
+    [-10, 0, 10, 10, 10, 10, 0, -10],
+    [-10, 10, 10, 10, 10, 10, 10, -10],
+    [-10, 5, 0, 0, 0, 0, 5, -10],
+    [-20, -10, -10, -10, -10, -10, -10, -20],
+];
+
+const ROOK_TABLE: [[i32; 8]; 8] = [
+    [0, 0, 0, 0, 0, 0, 0, 0],
+    [5, 10, 10, 10, 10, 10, 10, 5],
+    [-5, 0, 0, 0, 0, 0, 0, -5],
+    [-5, 0, 0, 0, 0, 0, 0, -5],
+    [-5, 0, 0, 0, 0, 0, 0, -5],
+    [-5, 0, 0, 0, 0, 0, 0, -5],
+    [-5, 0, 0, 0, 0, 0, 0, -5],
+    [0, 0, 0, 5, 5, 0, 0, 0],
+];
+
+const QUEEN_TABLE: [[i32; 8]; 8] = [
+    [-20, -10, -10, -5, -5, -10, -10, -20],
+    [-10, 0, 0, 0, 0, 0, 0, -10],
+    [-10, 0, 5, 5, 5, 5, 0, -10],
+    [-5, 0, 5, 5, 5, 5, 0, -5],
+    [0, 0, 5, 5, 5, 5, 0, -5],
+    [-10, 5, 5, 5, 5, 5, 0, -10],
+    [-10, 0, 5, 0, 0, 0, 0, -10],
+    [-20, -10, -10, -5, -5, -10, -10, -20],
+];
+
+const KING_TABLE: [[i32; 8]; 8] = [
+    [-30, -40, -40, -50, -50, -40, -40, -30],
+    [-30, -40, -40, -50, -50, -40, -40, -30],
+    [-30, -40, -40, -50, -50, -40, -40, -30],
+    [-30, -40, -40, -50, -50, -40, -40, -30],
+    [-20, -30, -30, -40, -40, -30, -30, -20],
+    [-10, -20, -20, -20, -20, -20, -20, -10],
+    [20, 20, 0, 0, 0, 0, 20, 20],
+    [20, 30, 10, 0, 0, 10, 30, 20],
+];
+
+// Evaluate the position in centipawns from the point of view of the side to move
+pub fn evaluate(position: &Position) -> i32 {
+    if position.variant == Variant::Antichess {
+        return evaluate_antichess(position);
+    }
+
+    let mut score = 0;
+    for (x, row) in position.board.iter().enumerate() {
+        for (y, square) in row.iter().enumerate() {
+            if let Some(piece) = square.as_ref().filter(|piece| piece.piece_type != PieceType::Duck) {
+                let table = match piece.piece_type {
+                    PieceType::Pawn => &PAWN_TABLE,
+                    PieceType::Knight => &KNIGHT_TABLE,
+                    PieceType::Bishop => &BISHOP_TABLE,
+                    PieceType::Rook => &ROOK_TABLE,
+                    PieceType::Queen => &QUEEN_TABLE,
+                    PieceType::King => &KING_TABLE,
+                    // Fairy pieces are assumed to like the centre as much as a knight does
+                    PieceType::Archbishop | PieceType::Chancellor | PieceType::Fairy(_) | PieceType::Duck => &KNIGHT_TABLE,
+                };
+                // Black uses the same tables mirrored vertically, and boards of other sizes are scaled to fit them
+                let (rank, sign) = match piece.color {
+                    Color::White => (x, 1),
+                    Color::Black => (position.board.len() - 1 - x, -1),
+                };
+                score += sign * (piece_value(&piece.piece_type) + table[rank * 8 / position.board.len()][y * 8 / row.len()]);
+            }
+        }
+    }
+
+    // Pieces in hand count as material too
+    if position.variant.has_drops() {
+        for (piece_type, (&white, &black)) in POCKET_PIECES.iter().zip(position.white_pocket.iter().zip(&position.black_pocket)) {
+            score += (white as i32 - black as i32) * piece_value(piece_type);
+        }
+    }
+
+    // So do the pieces waiting to be gated in S-Chess
+    for piece_type in &position.white_reserve {
+        score += piece_value(piece_type);
+    }
+    for piece_type in &position.black_reserve {
+        score -= piece_value(piece_type);
+    }
+
+    // Every check brings a side closer to winning three-check
+    if position.variant.check_limit().is_some() {
+        score += (position.white_checks as i32 - position.black_checks as i32) * 200;
+    }
+
+    // In King of the Hill the kings are drawn towards the centre instead of hiding, and in Racing
+    // Kings both race towards the eighth rank
+    if matches!(position.variant, Variant::KingOfTheHill | Variant::RacingKings) {
+        for (x, row) in position.board.iter().enumerate() {
+            for (y, square) in row.iter().enumerate() {
+                if let Some(piece) = square.as_ref().filter(|piece| piece.piece_type == PieceType::King) {
+                    let sign = if piece.color == Color::White { 1 } else { -1 };
+                    score += sign * if position.variant == Variant::KingOfTheHill {
+                        let distance = (x as i32 * 2 - 7).abs().max((y as i32 * 2 - 7).abs()) / 2;
+                        (3 - distance) * 40
+                    } else {
+                        (7 - x as i32) * 60
+                    };
+                }
+            }
+        }
+    }
+
+    match position.side_to_move {
+        Color::White => score,
+        Color::Black => -score,
+    }
+}
+
+// Score a position where the variant's own goal has been reached, like a checkmate
+fn variant_score(position: &Position, ply: i32) -> Option<i32> {
+    match variant_outcome(position)? {
+        Outcome::Win(color, _) if color == position.side_to_move => Some(MATE - ply),
+        Outcome::Win(..) => Some(-MATE + ply),
+        Outcome::Draw(_) => Some(0),
+    }
+}
+
+// Evaluate an antichess position, where the side with fewer pieces left is closer to winning
+fn evaluate_antichess(position: &Position) -> i32 {
+    let mut score = 0;
+    for piece in position.board.iter().flatten().flatten() {
+        score += if piece.color == position.side_to_move { -100 } else { 100 };
+    }
+    score
+}
+
+// Score a position where the side to move has no legal move
+fn no_moves_score(position: &Position, ply: i32) -> i32 {
+    if position.variant.stalemate_wins() {
+        // Running out of moves wins antichess and Duck chess
+        MATE - ply
+    } else if position.in_check() {
+        -MATE + ply
+    } else {
+        0
+    }
+}
+
+// Check if a move captures a piece or promotes, which are the moves searched in quiescence
+fn is_tactical(position: &Position, mv: &Move) -> bool {
+    position.is_capture(mv) || mv.promotion.is_some()
+}
+
+// Order moves so the previous best move comes first, then captures of valuable pieces by cheap ones
+fn order_moves(position: &Position, moves: &mut [Move], best: Option<&Move>) {
+    moves.sort_by_cached_key(|mv| {
+        if Some(mv) == best {
+            return i32::MIN;
+        }
+        let mut key = 0;
+        if let Some(ref victim) = position.board[mv.end.0][mv.end.1] {
+            // In Chess960 the king takes its own rook to castle, which is no capture
+            if victim.color == position.side_to_move {
+                return key;
+            }
+            key -= 10 * piece_value(&victim.piece_type);
+            if let Some(ref attacker) = position.board[mv.start.0][mv.start.1] {
+                key += piece_value(&attacker.piece_type) / 10;
+            }
+        }
+        if let Some(ref promotion) = mv.promotion {
+            key -= piece_value(promotion);
+        }
+        key
+    });
+}
+
+// Work out how long to think for, if there is a time limit at all
+fn time_budget(position: &Position, limits: &SearchLimits) -> Option<Duration> {
+    if limits.infinite {
+        return None;
+    }
+    if let Some(movetime) = limits.movetime {
+        return Some(movetime.saturating_sub(limits.move_overhead));
+    }
+    let (time, increment) = match position.side_to_move {
+        Color::White => (limits.white_time, limits.white_increment),
+        Color::Black => (limits.black_time, limits.black_increment),
+    };
+    let time = time?;
+    let moves_to_go = limits.moves_to_go.unwrap_or(30).max(1);
+    let budget = time / moves_to_go + increment.unwrap_or_default() * 3 / 4;
+    Some(budget.min(time / 2).saturating_sub(limits.move_overhead))
+}
+
+// Alpha-beta searcher shared by the protocol front-ends
+pub struct Searcher<'a> {
+    stop: &'a AtomicBool,
+    pondering: &'a AtomicBool,
+    start: Instant,
+    budget: Option<Duration>,
+    node_limit: Option<u64>,
+    nodes: u64,
+    aborted: bool,
+    endgames: Option<&'a EndgameTables>,
+    syzygy: Option<&'a SyzygyTables>,
+    // The moves searched at the root, which the tablebases can narrow down
+    root_moves: Vec<Move>,
+}
+
+impl<'a> Searcher<'a> {
+    // Create a searcher that stops when the stop flag is set or the limits run out.
+    // While the pondering flag is set the time budget is not enforced.
+    pub fn new(position: &Position, limits: &SearchLimits, stop: &'a AtomicBool, pondering: &'a AtomicBool) -> Searcher<'a> {
+        Searcher {
+            stop,
+            pondering,
+            start: Instant::now(),
+            budget: time_budget(position, limits),
+            node_limit: limits.nodes,
+            nodes: 0,
+            aborted: false,
+            endgames: None,
+            syzygy: None,
+            root_moves: Vec::new(),
+        }
+    }
+
+    // Score positions covered by the endgame tables exactly instead of searching them
+    pub fn set_endgames(&mut self, endgames: &'a EndgameTables) {
+        self.endgames = Some(endgames);
+    }
+
+    // Only play root moves that keep the tablebase result, and score positions the tablebases
+    // cover by their win/draw/loss value
+    pub fn set_syzygy(&mut self, syzygy: &'a SyzygyTables) {
+        self.syzygy = Some(syzygy);
+    }
+
+    // Check whether the search has to stop now
+    fn should_stop(&mut self) -> bool {
+        if self.aborted {
+            return true;
+        }
+        if self.stop.load(Ordering::Relaxed) || self.node_limit.is_some_and(|limit| self.nodes >= limit) {
+            self.aborted = true;
+        } else if self.nodes.is_multiple_of(256) && !self.pondering.load(Ordering::Relaxed) {
+            if let Some(budget) = self.budget {
+                self.aborted = self.start.elapsed() >= budget;
+            }
+        }
+        self.aborted
+    }
+
+    // Run an iterative deepening search, reporting each completed depth.
+    // Returns the last completed iteration, or None if there are no legal moves.
+    pub fn search(&mut self, position: &Position, max_depth: Option<u32>, mut report: impl FnMut(&SearchInfo)) -> Option<SearchInfo> {
+        let root_moves = position.legal_moves();
+        if root_moves.is_empty() {
+            return None;
+        }
+        self.root_moves = self.syzygy.and_then(|syzygy| syzygy.root_moves(position)).unwrap_or(root_moves);
+
+        let mut best: Option<SearchInfo> = None;
+        // Always finish at least one iteration, so a position with legal moves gets a best move
+        let max_depth = max_depth.unwrap_or(MAX_PLY as u32 - 1).clamp(1, MAX_PLY as u32 - 1);
+        for depth in 1..=max_depth {
+            let mut pv = Vec::new();
+            let previous_pv = best.as_ref().map(|info| info.pv.clone()).unwrap_or_default();
+            let score = self.negamax(position, depth as i32, 0, -MATE - 1, MATE + 1, &previous_pv, &mut pv);
+
+            // An interrupted iteration is only trusted if nothing better has been found yet
+            if self.aborted && best.is_some() {
+                break;
+            }
+            if pv.is_empty() {
+                pv.push(self.root_moves[0].clone());
+            }
+            let info = SearchInfo {
+                depth,
+                score,
+                nodes: self.nodes,
+                elapsed: self.start.elapsed(),
+                pv,
+            };
+            report(&info);
+            best = Some(info);
+
+            if self.aborted || mate_in(score).is_some() {
+                break;
+            }
+            // Another iteration would take longer than the time that is left
+            if let Some(budget) = self.budget {
+                if !self.pondering.load(Ordering::Relaxed) && self.start.elapsed() * 2 > budget {
+                    break;
+                }
+            }
+        }
+
+        best
+    }
+
+    // Negamax alpha-beta search, filling in the principal variation
+    #[allow(clippy::too_many_arguments)]
+    fn negamax(&mut self, position: &Position, depth: i32, ply: i32, mut alpha: i32, beta: i32, previous_pv: &[Move], pv: &mut Vec<Move>) -> i32 {
+        self.nodes += 1;
+        if ply > 0 && self.should_stop() {
+            return 0;
+        }
+        if ply > 0 && position.halfmove_clock >= 100 {
+            return 0;
+        }
+        if ply > 0 {
+            match self.endgames.and_then(|endgames| endgames.probe(position)) {
+                Some(EndgameResult::Win(plies)) => return MATE - ply - plies as i32,
+                Some(EndgameResult::Loss(plies)) => return -MATE + ply + plies as i32,
+                Some(EndgameResult::Draw) => return 0,
+                None => {},
+            }
+        }
+        // The win/draw/loss tables only hold under the fifty-move rule right after a capture or pawn move
+        if ply > 0 && position.halfmove_clock == 0 {
+            match self.syzygy.and_then(|syzygy| syzygy.probe_wdl(position)) {
+                Some(Wdl::Win) => return TABLEBASE_WIN - ply,
+                Some(Wdl::Loss) => return -TABLEBASE_WIN + ply,
+                Some(Wdl::CursedWin) => return 1,
+                Some(Wdl::BlessedLoss) => return -1,
+                Some(Wdl::Draw) => return 0,
+                None => {},
+            }
+        }
+
+        if let Some(score) = variant_score(position, ply) {
+            return score;
+        }
+        let mut moves = if ply == 0 { self.root_moves.clone() } else { position.legal_moves() };
+        if moves.is_empty() {
+            return no_moves_score(position, ply);
+        }
+        if depth <= 0 || ply >= MAX_PLY - 1 {
+            return self.quiescence(position, ply, alpha, beta);
+        }
+
+        order_moves(position, &mut moves, previous_pv.first());
+        let mut best_score = -MATE - 1;
+        for mv in moves {
+            let mut next = position.clone();
+            next.make_move(&mv);
+            let mut child_pv = Vec::new();
+            let child_hint = if previous_pv.first() == Some(&mv) { &previous_pv[1..] } else { &[] };
+            let score = -self.negamax(&next, depth - 1, ply + 1, -beta, -alpha, child_hint, &mut child_pv);
+            if self.aborted {
+                return 0;
+            }
+            if score > best_score {
+                best_score = score;
+                if score > alpha {
+                    alpha = score;
+                    pv.clear();
+                    pv.push(mv);
+                    pv.extend(child_pv);
+                }
+            }
+            if alpha >= beta {
+                break;
+            }
+        }
+
+        best_score
+    }
+
+    // Search captures and promotions until the position is quiet
+    fn quiescence(&mut self, position: &Position, ply: i32, mut alpha: i32, beta: i32) -> i32 {
+        self.nodes += 1;
+        if self.should_stop() {
+            return 0;
+        }
+        if let Some(score) = variant_score(position, ply) {
+            return score;
+        }
+
+        // When captures are compulsory the side to move cannot stand pat while it has one to make
+        let mut moves = position.legal_moves();
+        let forced = position.variant.captures_compulsory() && moves.iter().any(|mv| position.is_capture(mv));
+        if position.variant.captures_compulsory() && moves.is_empty() {
+            return no_moves_score(position, ply);
+        }
+
+        let stand_pat = evaluate(position);
+        if ply >= MAX_PLY - 1 || !forced && stand_pat >= beta {
+            return stand_pat;
+        }
+        if !forced {
+            alpha = alpha.max(stand_pat);
+        }
+
+        moves.retain(|mv| forced || is_tactical(position, mv));
+        order_moves(position, &mut moves, None);
+        for mv in moves {
+            let mut next = position.clone();
+            next.make_move(&mv);
+            let score = -self.quiescence(&next, ply + 1, -beta, -alpha);
+            if self.aborted {
+                return 0;
+            }
+            if score >= beta {
+                return score;
+            }
+            alpha = alpha.max(score);
+        }
+
+        alpha
+    }
+}
@@ -0,0 +1,949 @@
+use std::collections::HashMap;
+use std::io::{self, BufRead, BufReader, Write};
+use std::net::{Shutdown, TcpListener, TcpStream};
+use std::sync::mpsc::{self, Receiver, RecvTimeoutError, Sender};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use crate::book::random_u64;
+use crate::broadcast::{self, Spectate};
+use crate::matchmaking::{
+    parse_seek_terms, rating_allowed, seek_terms_text, time_control_text, update_ratings, valid_name, SeekTerms,
+    TimeControl, INITIAL_RATING,
+};
+use crate::position::{opponent, Position};
+use crate::variant::{outcome, parse_player_move, Outcome, Variant};
+use crate::{board_to_string, Color};
+
+// A server for playing over the network, where every move is checked by the same rules as at the
+// terminal. Each connection and each game runs in its own thread, and a connection passes what its
+// player does on to their game over a channel. The protocol is plain lines of text, so a client can
+// be as thin as netcat.
+//
+// The client sends:
+//
+// - name <user>: take a name, which is needed to seek, challenge and be rated
+// - seek [terms]: offer a game to anyone, on terms written in any order as the variant, the time
+//   control as minutes+increment or "untimed", the color to play or "random", and the range of
+//   ratings an opponent may have, e.g. "seek atomic 3+2 white 1400-1800"
+// - challenge <user> [terms]: offer a game to one player
+// - accept <seek>: accept a seek or a challenge, which starts the game at once
+// - decline <seek>: turn down a challenge, or take back a seek or challenge of your own
+// - new [variant]: start an untimed game, as White, and wait for an opponent
+// - list: list the games waiting for an opponent, the open seeks and the games being played
+// - join <game>: join a waiting game as Black
+// - watch <game>: watch a game, from its first move, and unwatch to stop
+// - rejoin <game> <key>: sit down again at a game after losing the connection
+// - move <move>: play a move, written as at the terminal, e.g. "e4", "Nf3" or "g1f3"
+// - board: show the game's position again
+// - resign
+// - quit
+//
+// The server answers with lines starting with a keyword:
+//
+// - info <text> and error <text>: messages for the player
+// - game <game> <variant> <color> <key>: the player has sat down at a game, and can come back to it
+//   with the key
+// - waiting <game> <variant>: a game in the list of waiting games
+// - seek <seek> <user> <rating> <variant> <time> <color> <ratings>: an open seek, as it is posted
+//   and in the list
+// - challenge <seek> <user> <rating> <to> <variant> <time> <color> <ratings>: a challenge, sent to
+//   the player challenged and listed for both players
+// - playing <game> <variant> <time> <white> <black>: a game in the list of games being played
+// - watching <game> <variant> <time> <white> <black> <delay>: the player watches a game, and is
+//   sent its moves, clocks, positions and result the given number of seconds after they happen
+// - start: both players are there and the game begins
+// - moved <color> <move>: a move was played, in long algebraic notation
+// - clock <white> <black>: the milliseconds each player has left in a timed game
+// - position <fen>: the position, followed by the board drawn as at the terminal
+// - end <result>: the game is over
+//
+// A player whose connection drops keeps their seat, and the game goes on once they rejoin. A game
+// both players have left waits ten minutes for one of them to come back before it is given up, and
+// one is given up at once if the first player leaves before anyone has joined. Clocks keep running
+// meanwhile, and a player whose time runs out loses. Games between two named players are rated,
+// with Elo ratings that last as long as the server runs. Any number of players who are not playing
+// can watch a game, held back by the server's spectator delay if it has one. Someone who starts
+// watching a game under way first gets every move played so far.
+
+// The address the server listens on when none is given
+pub const DEFAULT_ADDRESS: &str = "127.0.0.1:7878";
+
+// How long a write to a player may take before their connection is given up as stuck
+const WRITE_TIMEOUT: Duration = Duration::from_secs(10);
+
+// How long a game both players have left waits for one of them to rejoin
+const ABANDON_TIMEOUT: Duration = Duration::from_secs(10 * 60);
+
+// What a connection passes on to the game its player sits at, with the player's color and number.
+// The number tells the connection a player last sat down from apart from one they left behind,
+// which may only notice it has been closed after they have rejoined.
+enum Event {
+    Sit(Color, u32, TcpStream),
+    Move(Color, u32, String),
+    Board(Color, u32),
+    Resign(Color, u32),
+    Leave(Color, u32),
+}
+
+// A game as the lobby knows it: where to send its events and what its spectators see, whether
+// Black's seat is still free, the keys the players can rejoin it with, and the names of the players
+// who have one
+struct GameEntry {
+    events: Sender<Event>,
+    spectate: Sender<Spectate>,
+    variant: Variant,
+    time_control: Option<TimeControl>,
+    waiting: bool,
+    keys: Vec<(Color, String)>,
+    names: Vec<(Color, String)>,
+}
+
+impl GameEntry {
+    fn name(&self, color: Color) -> &str {
+        self.names.iter().find(|(seated, _)| *seated == color).map_or("anonymous", |(_, name)| name.as_str())
+    }
+}
+
+// Where a player sits: the game's number and variant, their color and key, and where to send events
+#[derive(Clone)]
+struct Seat {
+    id: u32,
+    variant: Variant,
+    color: Color,
+    key: String,
+    events: Sender<Event>,
+}
+
+// A player connected to the server, with the name they took, the game they sit at and the one they watch
+struct Player {
+    name: Option<String>,
+    writer: TcpStream,
+    seat: Option<Seat>,
+    watching: Option<u32>,
+}
+
+// An open seek, or a challenge when it is made to one player
+struct Seek {
+    id: u32,
+    player: u32,
+    name: String,
+    terms: SeekTerms,
+    target: Option<String>,
+}
+
+// The games being played or waiting for an opponent, the connected players and their seeks, all by
+// number, and the ratings of every name that has played
+struct Lobby {
+    games: HashMap<u32, GameEntry>,
+    next_id: u32,
+    players: HashMap<u32, Player>,
+    next_player: u32,
+    seeks: Vec<Seek>,
+    next_seek: u32,
+    ratings: HashMap<String, i32>,
+    spectator_delay: Duration,
+}
+
+impl Lobby {
+    fn rating(&self, name: &str) -> i32 {
+        self.ratings.get(name).copied().unwrap_or(INITIAL_RATING)
+    }
+
+    fn find_player(&self, name: &str) -> Option<u32> {
+        self.players.iter().find(|(_, player)| player.name.as_deref() == Some(name)).map(|(&id, _)| id)
+    }
+
+    // Stop sending a player the game they watch
+    fn unwatch(&mut self, player: u32) {
+        let watching = self.players.get_mut(&player).and_then(|player| player.watching.take());
+        if let Some(entry) = watching.and_then(|id| self.games.get(&id)) {
+            let _ = entry.spectate.send(Spectate::Unwatch(player));
+        }
+    }
+
+    // Describe a seek or challenge as the server lists it
+    fn seek_text(&self, seek: &Seek) -> String {
+        match seek.target {
+            Some(ref target) => format!("challenge {} {} {} {} {}", seek.id, seek.name, self.rating(&seek.name), target, seek_terms_text(&seek.terms)),
+            None => format!("seek {} {} {} {}", seek.id, seek.name, self.rating(&seek.name), seek_terms_text(&seek.terms)),
+        }
+    }
+}
+
+type SharedLobby = Arc<Mutex<Lobby>>;
+
+// The time each player has left in a timed game, where the side to move's clock has been running
+// since the last move
+struct Clock {
+    white: Duration,
+    black: Duration,
+    increment: Duration,
+    since: Instant,
+}
+
+impl Clock {
+    fn left(&self, color: Color, to_move: Color) -> Duration {
+        let stored = match color {
+            Color::White => self.white,
+            Color::Black => self.black,
+        };
+        if color == to_move {
+            stored.saturating_sub(self.since.elapsed())
+        } else {
+            stored
+        }
+    }
+
+    // Stop the mover's clock, adding the increment, and start the opponent's
+    fn press(&mut self, mover: Color) {
+        let left = self.left(mover, mover) + self.increment;
+        match mover {
+            Color::White => self.white = left,
+            Color::Black => self.black = left,
+        }
+        self.since = Instant::now();
+    }
+}
+
+// Write lines to a player, ignoring a connection that has gone away since its thread notices that.
+// The game and the player's connection both write to them, so each message goes out in one piece
+// to keep their lines from running together.
+fn send(stream: &mut TcpStream, text: &str) {
+    let _ = send_line(stream, text);
+}
+
+// Write lines to a spectator, for the broadcast to notice when they have gone away. A connection
+// that fails, or stops reading until a write times out, is shut down so its thread notices too.
+fn send_line(stream: &mut TcpStream, text: &str) -> io::Result<()> {
+    let written = stream.write_all(format!("{}\n", text).as_bytes());
+    if written.is_err() {
+        let _ = stream.shutdown(Shutdown::Both);
+    }
+    written
+}
+
+// Make up the key a player can rejoin their game with
+fn new_key() -> String {
+    format!("{:016x}", random_u64())
+}
+
+// Describe the result of a game
+fn result_text(result: &Outcome) -> String {
+    match result {
+        Outcome::Win(color, reason) => format!("{:?} wins: {}", color, reason),
+        Outcome::Draw(reason) => format!("Draw: {}", reason),
+    }
+}
+
+// Describe the position as FEN and as the board drawn for the terminal, after the clocks if the game has them
+fn position_text(position: &Position, clock: &Option<Clock>) -> String {
+    let position_lines = format!("position {}\n{}", position.to_fen(), board_to_string(&position.board).trim_end());
+    match clock {
+        Some(clock) => {
+            let left = |color| clock.left(color, position.side_to_move).as_millis();
+            format!("clock {} {}\n{}", left(Color::White), left(Color::Black), position_lines)
+        },
+        None => position_lines,
+    }
+}
+
+// Rate a game between two named players, and describe the new ratings
+fn rate_game(lobby: &mut Lobby, names: &[(Color, String)], result: &Outcome) -> Option<String> {
+    let name = |color| names.iter().find(|(seated, _)| *seated == color).map(|(_, name)| name.clone());
+    let (white, black) = (name(Color::White)?, name(Color::Black)?);
+    if white == black {
+        return None;
+    }
+    let score = match result {
+        Outcome::Win(Color::White, _) => 1.0,
+        Outcome::Win(Color::Black, _) => 0.0,
+        Outcome::Draw(_) => 0.5,
+    };
+    let (old_white, old_black) = (lobby.rating(&white), lobby.rating(&black));
+    let (new_white, new_black) = update_ratings(old_white, old_black, score);
+    lobby.ratings.insert(white.clone(), new_white);
+    lobby.ratings.insert(black.clone(), new_black);
+    Some(format!(
+        "info Ratings: {} {} ({:+}), {} {} ({:+})",
+        white, new_white, new_white - old_white, black, new_black, new_black - old_black,
+    ))
+}
+
+// Play one game, taking the players' moves in the order they arrive and checking each against the rules
+fn run_game(id: u32, variant: Variant, time_control: Option<TimeControl>, events: Receiver<Event>, spectate: Sender<Spectate>, lobby: SharedLobby) {
+    let mut position = Position::with_variant(variant);
+    let mut players: Vec<(Color, u32, TcpStream)> = Vec::new();
+    let mut started = false;
+    let mut clock = time_control.map(|time_control| Clock {
+        white: time_control.initial(),
+        black: time_control.initial(),
+        increment: time_control.increment(),
+        since: Instant::now(),
+    });
+    let mut result: Option<Outcome> = None;
+    let broadcast = |players: &mut Vec<(Color, u32, TcpStream)>, text: &str| {
+        for (_, _, stream) in players.iter_mut() {
+            send(stream, text);
+        }
+    };
+    let send_to = |players: &mut Vec<(Color, u32, TcpStream)>, color: Color, text: &str| {
+        if let Some((_, _, stream)) = players.iter_mut().find(|(seated, _, _)| *seated == color) {
+            send(stream, text);
+        }
+    };
+    // Only the connection a player last sat down from speaks for them
+    let holds = |players: &Vec<(Color, u32, TcpStream)>, color: Color, player: u32| {
+        players.iter().any(|&(seated, holder, _)| seated == color && holder == player)
+    };
+
+    loop {
+        // Wait for the players, but no longer than the side to move has left on the clock, nor
+        // than a game nobody sits at any more waits for them
+        let time_left = clock.as_ref().filter(|_| started).map(|clock| clock.left(position.side_to_move, position.side_to_move));
+        let wait = match started && players.is_empty() {
+            true => Some(time_left.map_or(ABANDON_TIMEOUT, |left| left.min(ABANDON_TIMEOUT))),
+            false => time_left,
+        };
+        let received = match wait {
+            Some(wait) => events.recv_timeout(wait),
+            None => events.recv().map_err(|_| RecvTimeoutError::Disconnected),
+        };
+        let event = match received {
+            Ok(event) => event,
+            Err(RecvTimeoutError::Timeout) if wait == time_left => {
+                let flagged = position.side_to_move;
+                result = Some(Outcome::Win(opponent(flagged), format!("{:?} ran out of time", flagged)));
+                break;
+            },
+            Err(RecvTimeoutError::Timeout) => {
+                let _ = spectate.send(Spectate::Record("end Abandoned: both players left".to_string()));
+                break;
+            },
+            Err(RecvTimeoutError::Disconnected) => break,
+        };
+
+        match event {
+            Event::Sit(color, player, stream) => {
+                players.retain(|(seated, _, _)| *seated != color);
+                players.push((color, player, stream));
+                if started {
+                    broadcast(&mut players, &format!("info {:?} is back", color));
+                    send_to(&mut players, color, "start");
+                    send_to(&mut players, color, &position_text(&position, &clock));
+                } else if players.len() == 2 {
+                    started = true;
+                    if let Some(ref mut clock) = clock {
+                        clock.since = Instant::now();
+                    }
+                    broadcast(&mut players, "start");
+                    broadcast(&mut players, &position_text(&position, &clock));
+                    let _ = spectate.send(Spectate::State(position_text(&position, &clock)));
+                }
+            },
+            Event::Move(color, player, text) if holds(&players, color, player) => {
+                if !started {
+                    send_to(&mut players, color, "error The game has not started yet");
+                    continue;
+                }
+                if color != position.side_to_move {
+                    send_to(&mut players, color, "error It is not your turn");
+                    continue;
+                }
+                let mv = match parse_player_move(&position, &text) {
+                    Some(mv) => mv,
+                    None => {
+                        send_to(&mut players, color, &format!("error Illegal move '{}'", text));
+                        continue;
+                    },
+                };
+                let played = position.move_to_string(&mv);
+                position.make_move(&mv);
+                if let Some(ref mut clock) = clock {
+                    clock.press(color);
+                }
+                broadcast(&mut players, &format!("moved {:?} {}", color, played));
+                broadcast(&mut players, &position_text(&position, &clock));
+                let _ = spectate.send(Spectate::Record(format!("moved {:?} {}", color, played)));
+                let _ = spectate.send(Spectate::State(position_text(&position, &clock)));
+                if let Some(game_result) = outcome(&position) {
+                    result = Some(game_result);
+                    break;
+                }
+            },
+            Event::Board(color, player) if holds(&players, color, player) => send_to(&mut players, color, &position_text(&position, &clock)),
+            Event::Resign(color, player) if holds(&players, color, player) => {
+                result = Some(Outcome::Win(opponent(color), format!("{:?} resigned", color)));
+                break;
+            },
+            Event::Leave(color, player) if holds(&players, color, player) => {
+                players.retain(|(seated, _, _)| *seated != color);
+                if !started {
+                    break;
+                }
+                broadcast(&mut players, &format!("info {:?} lost the connection, the game goes on when they rejoin", color));
+            },
+            // A connection the player has left behind has no say in the game any more
+            _ => {},
+        }
+    }
+
+    // Take the game out of the lobby, freeing its players' seats, before they hear it is over
+    let ratings = {
+        let mut locked = lobby.lock().unwrap();
+        let entry = locked.games.remove(&id);
+        for player in locked.players.values_mut() {
+            if player.seat.as_ref().is_some_and(|seat| seat.id == id) {
+                player.seat = None;
+            }
+        }
+        match (entry, &result) {
+            (Some(entry), Some(result)) => rate_game(&mut locked, &entry.names, result),
+            _ => None,
+        }
+    };
+    if let Some(result) = result {
+        broadcast(&mut players, &format!("end {}", result_text(&result)));
+        let _ = spectate.send(Spectate::Record(format!("end {}", result_text(&result))));
+        if let Some(ratings) = ratings {
+            broadcast(&mut players, &ratings);
+            let _ = spectate.send(Spectate::Record(ratings));
+        }
+    }
+}
+
+// Give a player a seat at a game, with a new key, and note their name for the rating
+fn take_seat(id: u32, entry: &mut GameEntry, color: Color, name: Option<String>) -> Seat {
+    let key = new_key();
+    entry.keys.push((color, key.clone()));
+    if let Some(name) = name {
+        entry.names.push((color, name));
+    }
+    Seat { id, variant: entry.variant, color, key, events: entry.events.clone() }
+}
+
+// Start a new game in the lobby, in its own thread, and seat the given players. With only one of
+// them the game waits for an opponent.
+fn create_game(lobby: &SharedLobby, variant: Variant, time_control: Option<TimeControl>, seated: Vec<(Color, Option<String>)>) -> Vec<Seat> {
+    let (sender, receiver) = mpsc::channel();
+    let mut locked = lobby.lock().unwrap();
+    let id = locked.next_id;
+    locked.next_id += 1;
+    let spectate = broadcast::spawn(locked.spectator_delay, send_line);
+    let mut entry = GameEntry {
+        events: sender,
+        spectate: spectate.clone(),
+        variant,
+        time_control,
+        waiting: seated.len() < 2,
+        keys: Vec::new(),
+        names: Vec::new(),
+    };
+    let seats = seated.into_iter().map(|(color, name)| take_seat(id, &mut entry, color, name)).collect();
+    locked.games.insert(id, entry);
+    let game_lobby = Arc::clone(lobby);
+    thread::spawn(move || run_game(id, variant, time_control, receiver, spectate, game_lobby));
+    seats
+}
+
+// Take Black's seat at a waiting game
+fn join_game(lobby: &SharedLobby, id: u32, name: Option<String>) -> Result<Seat, String> {
+    let mut locked = lobby.lock().unwrap();
+    match locked.games.get_mut(&id) {
+        Some(entry) if entry.waiting => {
+            entry.waiting = false;
+            Ok(take_seat(id, entry, Color::Black, name))
+        },
+        Some(_) => Err(format!("Game {} already has two players", id)),
+        None => Err(format!("There is no game {}", id)),
+    }
+}
+
+// Take back the seat the key belongs to at a game
+fn rejoin_game(lobby: &SharedLobby, id: u32, key: &str) -> Result<Seat, String> {
+    let locked = lobby.lock().unwrap();
+    let entry = locked.games.get(&id).ok_or(format!("There is no game {}", id))?;
+    match entry.keys.iter().find(|(_, seat_key)| seat_key == key) {
+        Some(&(color, _)) => Ok(Seat { id, variant: entry.variant, color, key: key.to_string(), events: entry.events.clone() }),
+        None => Err(format!("Wrong key for game {}", id)),
+    }
+}
+
+// Seat a connected player at a game, telling them which one and the key to come back to it with.
+// Their seeks are withdrawn and they stop watching, since they can only play one game at a time.
+fn seat_player(lobby: &SharedLobby, player: u32, seat: Seat) {
+    let (writer, left_behind) = {
+        let mut locked = lobby.lock().unwrap();
+        locked.seeks.retain(|seek| seek.player != player);
+        locked.unwatch(player);
+        // A connection the seat was rejoined from gives it up, so it cannot play for the player any more
+        let left_behind: Vec<TcpStream> = locked.players.iter_mut()
+            .filter(|(&other, holder)| other != player && holder.seat.as_ref().is_some_and(|held| held.id == seat.id && held.color == seat.color))
+            .filter_map(|(_, holder)| {
+                holder.seat = None;
+                holder.writer.try_clone().ok()
+            })
+            .collect();
+        let writer = locked.players.get_mut(&player).and_then(|seated| {
+            seated.seat = Some(seat.clone());
+            seated.writer.try_clone().ok()
+        });
+        (writer, left_behind)
+    };
+    for mut stream in left_behind {
+        send(&mut stream, &format!("info You sat down at game {} from another connection", seat.id));
+    }
+    // The player hears where they sit before the game sends them anything
+    if let Some(mut writer) = writer {
+        send(&mut writer, &format!("game {} {} {:?} {}", seat.id, seat.variant.name(), seat.color, seat.key));
+        let _ = seat.events.send(Event::Sit(seat.color, player, writer));
+    }
+}
+
+// Post a seek, or a challenge to the named player, and announce it
+fn post_seek(lobby: &SharedLobby, player: u32, name: String, argument: &str, challenge: bool) -> Result<String, String> {
+    let mut words: Vec<&str> = argument.split_whitespace().collect();
+    let mut locked = lobby.lock().unwrap();
+    let target = match challenge {
+        true if words.is_empty() => return Err("Give the name of the player to challenge".to_string()),
+        true => Some(words.remove(0).to_string()),
+        false => None,
+    };
+    let terms = parse_seek_terms(&words)?;
+    let target_player = match target {
+        Some(ref target) if *target == name => return Err("You cannot challenge yourself".to_string()),
+        Some(ref target) => Some(locked.find_player(target).ok_or(format!("{} is not connected", target))?),
+        None => None,
+    };
+
+    let id = locked.next_seek;
+    locked.next_seek += 1;
+    let seek = Seek { id, player, name, terms, target };
+    let text = locked.seek_text(&seek);
+    locked.seeks.push(seek);
+
+    // Everyone free to play hears of a seek, but only the player challenged of a challenge
+    let interested: Vec<TcpStream> = locked.players.iter()
+        .filter(|&(&id, other)| match target_player {
+            Some(target_player) => id == target_player,
+            None => id != player && other.seat.is_none(),
+        })
+        .filter_map(|(_, other)| other.writer.try_clone().ok())
+        .collect();
+    drop(locked);
+    for mut writer in interested {
+        send(&mut writer, &text);
+    }
+    Ok(text)
+}
+
+// Accept a seek or a challenge and start its game, with the seeker's color or a random one
+fn accept_seek(lobby: &SharedLobby, player: u32, name: String, argument: &str) -> Result<(), String> {
+    let id = argument.parse::<u32>().map_err(|_| "Give the number of a seek".to_string())?;
+    let seek = {
+        let mut locked = lobby.lock().unwrap();
+        let index = locked.seeks.iter().position(|seek| seek.id == id).ok_or(format!("There is no seek {}", id))?;
+        let seek = &locked.seeks[index];
+        if seek.player == player {
+            return Err("You cannot accept your own seek".to_string());
+        }
+        if seek.target.as_ref().is_some_and(|target| *target != name) {
+            return Err(format!("Challenge {} is for {}", id, seek.target.as_deref().unwrap_or("")));
+        }
+        if seek.target.is_none() && !rating_allowed(&seek.terms, locked.rating(&name)) {
+            return Err(format!("Your rating of {} is outside the range of seek {}", locked.rating(&name), id));
+        }
+        locked.seeks.remove(index)
+    };
+
+    let seeker_color = seek.terms.color.unwrap_or(if random_u64().is_multiple_of(2) { Color::White } else { Color::Black });
+    let seated = vec![(seeker_color, Some(seek.name)), (opponent(seeker_color), Some(name))];
+    let seats = create_game(lobby, seek.terms.variant, seek.terms.time_control, seated);
+    for (seat, seated_player) in seats.into_iter().zip([seek.player, player]) {
+        seat_player(lobby, seated_player, seat);
+    }
+    Ok(())
+}
+
+// Turn down a challenge, or take back one's own seek or challenge
+fn decline_seek(lobby: &SharedLobby, player: u32, name: Option<String>, argument: &str) -> Result<String, String> {
+    let id = argument.parse::<u32>().map_err(|_| "Give the number of a seek".to_string())?;
+    let mut locked = lobby.lock().unwrap();
+    let index = locked.seeks.iter().position(|seek| seek.id == id).ok_or(format!("There is no seek {}", id))?;
+    let seek = &locked.seeks[index];
+    if seek.player == player {
+        locked.seeks.remove(index);
+        return Ok(format!("Seek {} withdrawn", id));
+    }
+    if name.is_none() || seek.target != name {
+        return Err(format!("Seek {} is not yours to decline", id));
+    }
+    let seek = locked.seeks.remove(index);
+    let challenger = locked.players.get(&seek.player).and_then(|challenger| challenger.writer.try_clone().ok());
+    drop(locked);
+    if let Some(mut challenger) = challenger {
+        send(&mut challenger, &format!("info {} declined challenge {}", name.unwrap_or_default(), id));
+    }
+    Ok(format!("Challenge {} declined", id))
+}
+
+// List the games waiting for an opponent, the seeks open to the player and the games being played
+fn list_lobby(lobby: &SharedLobby, player: u32, name: Option<String>) -> Vec<String> {
+    let locked = lobby.lock().unwrap();
+    let mut lines = Vec::new();
+    let mut ids: Vec<&u32> = locked.games.keys().collect();
+    ids.sort();
+
+    let waiting: Vec<&&u32> = ids.iter().filter(|id| locked.games[id].waiting).collect();
+    for id in &waiting {
+        lines.push(format!("waiting {} {}", id, locked.games[id].variant.name()));
+    }
+    let seeks: Vec<&Seek> = locked.seeks.iter()
+        .filter(|seek| seek.target.is_none() || seek.player == player || seek.target == name)
+        .collect();
+    for seek in &seeks {
+        lines.push(locked.seek_text(seek));
+    }
+    let playing: Vec<&&u32> = ids.iter().filter(|id| !locked.games[id].waiting).collect();
+    for id in &playing {
+        let entry = &locked.games[id];
+        lines.push(format!("playing {} {} {} {} {}", id, entry.variant.name(), time_control_text(entry.time_control), entry.name(Color::White), entry.name(Color::Black)));
+    }
+    lines.push(format!("info {} game(s) waiting, {} seek(s) open, {} game(s) being played", waiting.len(), seeks.len(), playing.len()));
+    lines
+}
+
+// Start sending a player a game, with its history so far
+fn watch_game(lobby: &SharedLobby, player: u32, argument: &str) -> Result<(), String> {
+    let id = argument.parse::<u32>().map_err(|_| "Give the number of a game".to_string())?;
+    let mut locked = lobby.lock().unwrap();
+    if !locked.games.contains_key(&id) {
+        return Err(format!("There is no game {}", id));
+    }
+    locked.unwatch(player);
+    let delay = locked.spectator_delay.as_secs();
+    let entry = &locked.games[&id];
+    let header = format!(
+        "watching {} {} {} {} {} {}",
+        id, entry.variant.name(), time_control_text(entry.time_control), entry.name(Color::White), entry.name(Color::Black), delay,
+    );
+    let spectate = entry.spectate.clone();
+    let watcher = locked.players.get_mut(&player).ok_or("You are not connected")?;
+    let mut stream = watcher.writer.try_clone().map_err(|error| error.to_string())?;
+    watcher.watching = Some(id);
+    drop(locked);
+    // The header comes before anything the broadcast sends
+    send(&mut stream, &header);
+    let _ = spectate.send(Spectate::Watch(player, stream));
+    Ok(())
+}
+
+// Serve one player: read their commands and pass what concerns their game on to it
+fn handle_connection(stream: TcpStream, lobby: SharedLobby) {
+    let mut writer = match stream.try_clone() {
+        Ok(writer) => writer,
+        Err(_) => return,
+    };
+    let player_writer = match writer.try_clone() {
+        Ok(player_writer) => player_writer,
+        Err(_) => return,
+    };
+    let me = {
+        let mut locked = lobby.lock().unwrap();
+        let me = locked.next_player;
+        locked.next_player += 1;
+        locked.players.insert(me, Player { name: None, writer: player_writer, seat: None, watching: None });
+        me
+    };
+    send(&mut writer, "info Welcome to Rust Chess! Commands: name <user>, seek [terms], challenge <user> [terms], accept <seek>, decline <seek>, new [variant], list, join <game>, watch <game>, unwatch, rejoin <game> <key>, move <move>, board, resign, quit");
+
+    for line in BufReader::new(stream).lines() {
+        let line = match line {
+            Ok(line) => line,
+            Err(_) => break,
+        };
+        let (command, argument) = line.trim().split_once(' ').unwrap_or((line.trim(), ""));
+        let argument = argument.trim();
+        let (name, seat) = {
+            let locked = lobby.lock().unwrap();
+            let player = &locked.players[&me];
+            (player.name.clone(), player.seat.clone())
+        };
+
+        match command {
+            "new" | "join" | "rejoin" | "seek" | "challenge" | "accept" | "watch" if seat.is_some() => {
+                send(&mut writer, "error You are already playing a game")
+            },
+            "name" => {
+                if seat.is_some() {
+                    send(&mut writer, "error You cannot change your name during a game");
+                    continue;
+                }
+                if !valid_name(argument) {
+                    send(&mut writer, "error A name is up to 20 letters, digits, '_' and '-'");
+                    continue;
+                }
+                let reply = {
+                    let mut locked = lobby.lock().unwrap();
+                    if locked.find_player(argument).is_some_and(|other| other != me) {
+                        format!("error The name {} is taken", argument)
+                    } else {
+                        locked.seeks.retain(|seek| seek.player != me);
+                        locked.players.get_mut(&me).unwrap().name = Some(argument.to_string());
+                        format!("info You are {}, rated {}", argument, locked.rating(argument))
+                    }
+                };
+                send(&mut writer, &reply);
+            },
+            "seek" | "challenge" | "accept" if name.is_none() => send(&mut writer, "error Take a name first, with: name <user>"),
+            "seek" | "challenge" => match post_seek(&lobby, me, name.unwrap_or_default(), argument, command == "challenge") {
+                Ok(text) => send(&mut writer, &text),
+                Err(error) => send(&mut writer, &format!("error {}", error)),
+            },
+            "accept" => {
+                if let Err(error) = accept_seek(&lobby, me, name.unwrap_or_default(), argument) {
+                    send(&mut writer, &format!("error {}", error));
+                }
+            },
+            "decline" => match decline_seek(&lobby, me, name, argument) {
+                Ok(text) => send(&mut writer, &format!("info {}", text)),
+                Err(error) => send(&mut writer, &format!("error {}", error)),
+            },
+            "new" => {
+                let variant = match argument {
+                    "" => Some(Variant::Standard),
+                    name => Variant::from_name(name).filter(|&variant| variant != Variant::Bughouse),
+                };
+                let variant = match variant {
+                    Some(variant) => variant,
+                    None => {
+                        send(&mut writer, &format!("error Unknown variant '{}'", argument));
+                        continue;
+                    },
+                };
+                for seat in create_game(&lobby, variant, None, vec![(Color::White, name)]) {
+                    seat_player(&lobby, me, seat);
+                }
+                send(&mut writer, "info Waiting for an opponent");
+            },
+            "join" | "rejoin" => {
+                let (id, key) = argument.split_once(' ').unwrap_or((argument, ""));
+                let joined = id.parse::<u32>().map_err(|_| "Give the number of a game".to_string()).and_then(|id| match command {
+                    "join" => join_game(&lobby, id, name),
+                    _ => rejoin_game(&lobby, id, key.trim()),
+                });
+                match joined {
+                    Ok(joined) => seat_player(&lobby, me, joined),
+                    Err(error) => send(&mut writer, &format!("error {}", error)),
+                }
+            },
+            "watch" => {
+                if let Err(error) = watch_game(&lobby, me, argument) {
+                    send(&mut writer, &format!("error {}", error));
+                }
+            },
+            "unwatch" => {
+                lobby.lock().unwrap().unwatch(me);
+                send(&mut writer, "info You stopped watching");
+            },
+            "list" => {
+                for line in list_lobby(&lobby, me, name) {
+                    send(&mut writer, &line);
+                }
+            },
+            "move" | "board" | "resign" => {
+                let sitting = match seat {
+                    Some(ref sitting) => sitting,
+                    None => {
+                        send(&mut writer, "error You are not playing a game");
+                        continue;
+                    },
+                };
+                let event = match command {
+                    "move" => Event::Move(sitting.color, me, argument.to_string()),
+                    "board" => Event::Board(sitting.color, me),
+                    _ => Event::Resign(sitting.color, me),
+                };
+                if sitting.events.send(event).is_err() {
+                    send(&mut writer, "error The game is over");
+                }
+            },
+            "quit" => break,
+            "" => {},
+            _ => send(&mut writer, &format!("error Unknown command '{}'", command)),
+        }
+    }
+
+    let mut locked = lobby.lock().unwrap();
+    locked.seeks.retain(|seek| seek.player != me);
+    locked.unwatch(me);
+    if let Some(Player { seat: Some(seat), .. }) = locked.players.remove(&me) {
+        let _ = seat.events.send(Event::Leave(seat.color, me));
+    }
+}
+
+// Accept players on the given address until the program is stopped, showing games to spectators
+// after the given delay
+pub fn run(address: &str, spectator_delay: Duration) -> io::Result<()> {
+    let listener = TcpListener::bind(address)?;
+    println!("Listening on {}", listener.local_addr()?);
+    serve(listener, spectator_delay);
+    Ok(())
+}
+
+// Accept players on a listener that is already bound
+fn serve(listener: TcpListener, spectator_delay: Duration) {
+    let lobby = Arc::new(Mutex::new(Lobby {
+        games: HashMap::new(),
+        next_id: 1,
+        players: HashMap::new(),
+        next_player: 1,
+        seeks: Vec::new(),
+        next_seek: 1,
+        ratings: HashMap::new(),
+        spectator_delay,
+    }));
+    for stream in listener.incoming().flatten() {
+        // Nothing is written while the lobby is locked, but a player who stops reading must still
+        // not hold up their game or the spectators of another
+        if stream.set_write_timeout(Some(WRITE_TIMEOUT)).is_err() {
+            continue;
+        }
+        let lobby = Arc::clone(&lobby);
+        thread::spawn(move || handle_connection(stream, lobby));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Start a server on a free port of the local machine and get its address
+    fn start_server() -> String {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = listener.local_addr().unwrap().to_string();
+        thread::spawn(move || serve(listener, Duration::ZERO));
+        address
+    }
+
+    // A player connected to the test server
+    struct TestClient {
+        writer: TcpStream,
+        reader: BufReader<TcpStream>,
+    }
+
+    impl TestClient {
+        fn connect(address: &str) -> TestClient {
+            let stream = TcpStream::connect(address).unwrap();
+            stream.set_read_timeout(Some(Duration::from_secs(5))).unwrap();
+            TestClient { writer: stream.try_clone().unwrap(), reader: BufReader::new(stream) }
+        }
+
+        fn send(&mut self, line: &str) {
+            writeln!(self.writer, "{}", line).unwrap();
+        }
+
+        // Read lines until one starts with the keyword, and get it
+        fn expect(&mut self, keyword: &str) -> String {
+            loop {
+                let mut line = String::new();
+                assert!(self.reader.read_line(&mut line).unwrap() > 0, "connection closed before '{}'", keyword);
+                if line.starts_with(keyword) {
+                    return line.trim_end().to_string();
+                }
+            }
+        }
+    }
+
+    // Sit two players down at a new game, White first
+    fn start_game(address: &str) -> (TestClient, TestClient) {
+        let mut white = TestClient::connect(address);
+        let mut black = TestClient::connect(address);
+        white.send("new");
+        let id = white.expect("game ").split_whitespace().nth(1).unwrap().to_string();
+        black.send(&format!("join {}", id));
+        assert!(black.expect("game ").contains("Black"));
+        white.expect("start");
+        black.expect("start");
+        (white, black)
+    }
+
+    #[test]
+    fn two_players_finish_a_game() {
+        let address = start_server();
+        let (white, black) = start_game(&address);
+        let mut players = [white, black];
+        for (turn, (played, long)) in [("f3", "f2f3"), ("e5", "e7e5"), ("g4", "g2g4"), ("Qh4", "d8h4")].into_iter().enumerate() {
+            let color = if turn % 2 == 0 { Color::White } else { Color::Black };
+            players[turn % 2].send(&format!("move {}", played));
+            for player in players.iter_mut() {
+                assert_eq!(player.expect("moved"), format!("moved {:?} {}", color, long));
+            }
+        }
+        let [mut white, mut black] = players;
+        assert_eq!(white.expect("end"), "end Black wins: Black mates");
+        assert_eq!(black.expect("end"), "end Black wins: Black mates");
+    }
+
+    #[test]
+    fn illegal_and_untimely_moves_are_rejected() {
+        let address = start_server();
+        let (mut white, mut black) = start_game(&address);
+        white.send("move e5");
+        assert_eq!(white.expect("error"), "error Illegal move 'e5'");
+        black.send("move e5");
+        assert_eq!(black.expect("error"), "error It is not your turn");
+
+        // The game goes on as if nothing had been tried
+        white.send("move e4");
+        assert_eq!(black.expect("moved"), "moved White e2e4");
+        assert!(black.expect("position").contains(" b KQkq e3 "));
+    }
+    // Connect a player and take a name
+    fn named(address: &str, name: &str) -> TestClient {
+        let mut client = TestClient::connect(address);
+        client.send(&format!("name {}", name));
+        assert_eq!(client.expect("info You are"), format!("info You are {}, rated 1500", name));
+        client
+    }
+
+    #[test]
+    fn seeks_and_challenges_start_rated_games() {
+        let address = start_server();
+        let mut alice = named(&address, "alice");
+        let mut bob = named(&address, "bob");
+
+        // A seek is announced to everyone free to play, and accepting it starts the game
+        alice.send("seek white 5+0");
+        assert_eq!(alice.expect("seek"), "seek 1 alice 1500 chess 5+0 white any");
+        assert_eq!(bob.expect("seek"), "seek 1 alice 1500 chess 5+0 white any");
+        bob.send("accept 1");
+        assert!(alice.expect("game ").contains(" White "));
+        assert!(bob.expect("game ").contains(" Black "));
+        alice.expect("start");
+        // White's clock has started, but Black's has the full five minutes
+        assert!(bob.expect("clock").ends_with(" 300000"));
+        bob.send("resign");
+        assert_eq!(alice.expect("end"), "end White wins: Black resigned");
+        assert_eq!(alice.expect("info Ratings"), "info Ratings: alice 1516 (+16), bob 1484 (-16)");
+
+        // A challenge only goes to the player challenged, who may turn it down
+        bob.send("challenge alice 3+2 1500-1600");
+        assert_eq!(bob.expect("challenge"), "challenge 2 bob 1484 alice chess 3+2 random 1500-1600");
+        assert_eq!(alice.expect("challenge"), "challenge 2 bob 1484 alice chess 3+2 random 1500-1600");
+        bob.send("accept 2");
+        assert_eq!(bob.expect("error"), "error You cannot accept your own seek");
+        alice.send("decline 2");
+        assert_eq!(alice.expect("info Challenge"), "info Challenge 2 declined");
+        assert_eq!(bob.expect("info alice"), "info alice declined challenge 2");
+        alice.send("accept 2");
+        assert_eq!(alice.expect("error"), "error There is no seek 2");
+
+        // A seek outside the player's rating cannot be accepted
+        alice.send("seek 1600-2000");
+        alice.expect("seek 3");
+        bob.send("accept 3");
+        assert_eq!(bob.expect("error"), "error Your rating of 1484 is outside the range of seek 3");
+    }
+}
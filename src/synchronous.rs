@@ -0,0 +1,308 @@
+use std::io::{self, Write};
+
+use crate::position::{opponent, square_to_string};
+use crate::rules::is_valid_move;
+use crate::{print_board, read_move, starting_position, Board, Color, Piece, PieceType};
+
+// In synchronous chess both players choose a move for the same position without seeing the
+// other's, and the referee then plays both at once. The moves are resolved like this:
+//
+// 1. Both moving pieces leave their squares first, so a piece that moves away from a square the
+//    other player attacks escapes, and the attacker just lands on the empty square.
+// 2. If both pieces land on the same square they collide and both are removed, unless exactly one
+//    of them is a king, which then takes the other piece.
+// 3. Otherwise each piece lands on its square and captures whatever stands there.
+// 4. A pawn reaching the last rank becomes a queen.
+//
+// There is no check: a player wins by capturing the enemy king, and if both kings are captured
+// in the same turn or a player has no move left the game is drawn.
+
+// A move sealed by one player until the referee reveals both
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct SealedMove {
+    pub start: (usize, usize),
+    pub end: (usize, usize),
+}
+
+// How a synchronous game ended
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum SynchronousResult {
+    Win(Color),
+    Draw,
+}
+
+// What the referee announces after playing both moves of a turn
+#[derive(Debug, Clone)]
+pub struct Resolution {
+    pub white_move: SealedMove,
+    pub black_move: SealedMove,
+    pub events: Vec<String>,
+}
+
+// Get a piece's name for the referee's announcements
+fn piece_name(piece: &Piece) -> String {
+    let piece_type = format!("{:?}", piece.piece_type);
+    format!("{:?} {}", piece.color, piece_type.to_lowercase())
+}
+
+// Check if a piece can make a move in synchronous chess, where check does not exist
+pub fn is_valid_synchronous_move(board: &Board, color: Color, mv: SealedMove) -> bool {
+    match board[mv.start.0][mv.start.1] {
+        Some(ref piece) if piece.color == color => is_valid_move(board, piece, mv.start, mv.end),
+        _ => false,
+    }
+}
+
+// Check if a player has any move at all
+fn has_moves(board: &Board, color: Color) -> bool {
+    let (ranks, files) = (board.len(), board[0].len());
+    let squares = || (0..ranks).flat_map(move |x| (0..files).map(move |y| (x, y)));
+    squares().any(|start| squares().any(|end| is_valid_synchronous_move(board, color, SealedMove { start, end })))
+}
+
+// Check if a player still has their king
+fn has_king(board: &Board, color: Color) -> bool {
+    board.iter().flatten().flatten().any(|piece| piece.piece_type == PieceType::King && piece.color == color)
+}
+
+// Put a piece on its destination square, capturing what stands there and promoting pawns on the last rank
+fn land(board: &mut Board, mut piece: Piece, end: (usize, usize), events: &mut Vec<String>) {
+    let (end_x, end_y) = end;
+    if let Some(captured) = board[end_x][end_y].take() {
+        events.push(format!("{} captures {} on {}", piece_name(&piece), piece_name(&captured), square_to_string(end, board.len())));
+    }
+    let last_rank = if piece.color == Color::White { 0 } else { board.len() - 1 };
+    if piece.piece_type == PieceType::Pawn && end_x == last_rank {
+        piece.piece_type = PieceType::Queen;
+        events.push(format!("{:?} pawn promotes to a queen on {}", piece.color, square_to_string(end, board.len())));
+    }
+    board[end_x][end_y] = Some(piece);
+}
+
+// Play both moves of a turn at once following the resolution rules above
+pub fn resolve(board: &mut Board, white_move: SealedMove, black_move: SealedMove) -> Resolution {
+    let mut events = Vec::new();
+
+    // Both pieces leave their squares before either lands
+    let white_piece = board[white_move.start.0][white_move.start.1].take().unwrap();
+    let black_piece = board[black_move.start.0][black_move.start.1].take().unwrap();
+    for (mv, piece) in [(white_move, &white_piece), (black_move, &black_piece)] {
+        let other = if piece.color == Color::White { black_move } else { white_move };
+        if other.end == mv.start {
+            events.push(format!("{} escapes from {}", piece_name(piece), square_to_string(mv.start, board.len())));
+        }
+    }
+
+    if white_move.end == black_move.end {
+        let square = square_to_string(white_move.end, board.len());
+        match (white_piece.piece_type == PieceType::King, black_piece.piece_type == PieceType::King) {
+            (true, false) => {
+                events.push(format!("Collision on {}: the White king takes the {}", square, piece_name(&black_piece)));
+                land(board, white_piece, white_move.end, &mut events);
+            },
+            (false, true) => {
+                events.push(format!("Collision on {}: the Black king takes the {}", square, piece_name(&white_piece)));
+                land(board, black_piece, black_move.end, &mut events);
+            },
+            _ => events.push(format!("Collision on {}: the {} and the {} are both removed", square, piece_name(&white_piece), piece_name(&black_piece))),
+        }
+    } else {
+        land(board, white_piece, white_move.end, &mut events);
+        land(board, black_piece, black_move.end, &mut events);
+    }
+
+    Resolution { white_move, black_move, events }
+}
+
+// Decide if the game is over after a turn
+pub fn game_result(board: &Board) -> Option<SynchronousResult> {
+    match (has_king(board, Color::White), has_king(board, Color::Black)) {
+        (true, true) => {},
+        (true, false) => return Some(SynchronousResult::Win(Color::White)),
+        (false, true) => return Some(SynchronousResult::Win(Color::Black)),
+        (false, false) => return Some(SynchronousResult::Draw),
+    }
+    if !has_moves(board, Color::White) || !has_moves(board, Color::Black) {
+        return Some(SynchronousResult::Draw);
+    }
+    None
+}
+
+// The referee keeps the board and the sealed moves, and plays the turn once both players have moved
+pub struct Referee {
+    pub board: Board,
+    white_move: Option<SealedMove>,
+    black_move: Option<SealedMove>,
+}
+
+impl Referee {
+    pub fn new() -> Referee {
+        Referee { board: starting_position(), white_move: None, black_move: None }
+    }
+
+    // Seal a player's move for this turn, rejecting it if the piece cannot move there
+    pub fn submit(&mut self, color: Color, mv: SealedMove) -> Result<(), String> {
+        if !is_valid_synchronous_move(&self.board, color, mv) {
+            return Err(format!("{} to {} is not a valid move for {:?}", square_to_string(mv.start, self.board.len()), square_to_string(mv.end, self.board.len()), color));
+        }
+        match color {
+            Color::White => self.white_move = Some(mv),
+            Color::Black => self.black_move = Some(mv),
+        }
+        Ok(())
+    }
+
+    // Check if a player still has to send their move for this turn
+    pub fn waiting_for(&self, color: Color) -> bool {
+        match color {
+            Color::White => self.white_move.is_none(),
+            Color::Black => self.black_move.is_none(),
+        }
+    }
+
+    // Reveal and play both moves once they are in
+    pub fn reveal(&mut self) -> Option<Resolution> {
+        let white_move = self.white_move?;
+        let black_move = self.black_move?;
+        self.white_move = None;
+        self.black_move = None;
+        Some(resolve(&mut self.board, white_move, black_move))
+    }
+}
+
+impl Default for Referee {
+    fn default() -> Self {
+        Referee::new()
+    }
+}
+
+// Play a hot-seat game of synchronous chess, each player entering a move in turn without seeing the other's
+pub fn play() {
+    let mut referee = Referee::new();
+    println!("Both players move at the same time: enter your move, then let your opponent enter theirs.");
+
+    loop {
+        print_board(&referee.board);
+        for color in [Color::White, Color::Black] {
+            while referee.waiting_for(color) {
+                print!("{:?}, enter your move: ", color);
+                io::stdout().flush().unwrap();
+                let mut input = String::new();
+                if io::stdin().read_line(&mut input).unwrap_or(0) == 0 {
+                    return;
+                }
+                let (start_x, start_y, end_x, end_y) = match read_move(&input, &referee.board) {
+                    Ok(coordinates) => coordinates,
+                    Err(_) => {
+                        println!("Invalid input");
+                        continue;
+                    }
+                };
+                if let Err(error) = referee.submit(color, SealedMove { start: (start_x, start_y), end: (end_x, end_y) }) {
+                    println!("{}", error);
+                }
+            }
+        }
+
+        let resolution = referee.reveal().unwrap();
+        for (color, mv) in [(Color::White, resolution.white_move), (Color::Black, resolution.black_move)] {
+            println!("{:?} played {} to {}", color, square_to_string(mv.start, referee.board.len()), square_to_string(mv.end, referee.board.len()));
+        }
+        for event in &resolution.events {
+            println!("{}", event);
+        }
+
+        match game_result(&referee.board) {
+            Some(SynchronousResult::Win(color)) => {
+                print_board(&referee.board);
+                println!("{:?} captured the {:?} king and wins!", color, opponent(color));
+                return;
+            },
+            Some(SynchronousResult::Draw) => {
+                print_board(&referee.board);
+                println!("The game is a draw.");
+                return;
+            },
+            None => {},
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::position::{parse_square, piece_to_char, Position};
+
+    // Set up a board from the placement of a FEN
+    fn board_from_fen(fen: &str) -> Board {
+        Position::from_fen(fen, None).unwrap().board
+    }
+
+    // Seal a move written as its two squares, e.g. "e2e4"
+    fn sealed(text: &str) -> SealedMove {
+        SealedMove { start: parse_square(&text[..2], 8).unwrap(), end: parse_square(&text[2..], 8).unwrap() }
+    }
+
+    // Play one turn and get what the referee announces
+    fn play_turn(board: &mut Board, white: &str, black: &str) -> Vec<String> {
+        resolve(board, sealed(white), sealed(black)).events
+    }
+
+    // Get the piece on a square as its FEN letter
+    fn letter_at(board: &Board, square: &str) -> Option<char> {
+        let (x, y) = parse_square(square, 8).unwrap();
+        board[x][y].as_ref().map(piece_to_char)
+    }
+
+    #[test]
+    fn pieces_moving_away_escape() {
+        // The pawn takes on b5 just as the bishop leaves it
+        let mut board = board_from_fen("4k3/8/8/1b6/P7/8/8/4K3 w - - 0 1");
+        let events = play_turn(&mut board, "a4b5", "b5e2");
+        assert_eq!(events, ["Black bishop escapes from b5"]);
+        assert_eq!((letter_at(&board, "b5"), letter_at(&board, "e2")), (Some('P'), Some('b')));
+    }
+
+    #[test]
+    fn collisions_remove_both_pieces_unless_one_is_a_king() {
+        let mut board = board_from_fen("4k3/8/8/8/8/2N1n3/8/4K3 w - - 0 1");
+        let events = play_turn(&mut board, "c3d5", "e3d5");
+        assert_eq!(events, ["Collision on d5: the White knight and the Black knight are both removed"]);
+        assert_eq!(letter_at(&board, "d5"), None);
+
+        let mut board = board_from_fen("8/8/8/8/8/2N5/3k4/4K3 w - - 0 1");
+        let events = play_turn(&mut board, "c3e2", "d2e2");
+        assert_eq!(events, ["Collision on e2: the Black king takes the White knight"]);
+        assert_eq!(letter_at(&board, "e2"), Some('k'));
+    }
+
+    #[test]
+    fn capturing_the_king_wins() {
+        let mut board = board_from_fen("4k3/8/8/8/8/8/8/R3K2r w - - 0 1");
+        assert_eq!(game_result(&board), None);
+        let events = play_turn(&mut board, "a1a8", "h1e1");
+        assert_eq!(events, ["Black rook captures White king on e1"]);
+        assert_eq!(game_result(&board), Some(SynchronousResult::Win(Color::Black)));
+
+        // Both kings taken in the same turn is a draw
+        let mut board = board_from_fen("4k3/8/8/8/8/8/8/R3K2r w - - 0 1");
+        play_turn(&mut board, "a1a7", "h1h2");
+        play_turn(&mut board, "a7e7", "h2e2");
+        assert_eq!(play_turn(&mut board, "e7e8", "e2e1").len(), 2);
+        assert_eq!(game_result(&board), Some(SynchronousResult::Draw));
+    }
+
+    #[test]
+    fn the_referee_waits_for_both_moves() {
+        let mut referee = Referee::new();
+        assert!(referee.submit(Color::White, sealed("e2e5")).is_err());
+        assert!(referee.submit(Color::White, sealed("e7e5")).is_err());
+        referee.submit(Color::White, sealed("e2e4")).unwrap();
+        assert!(referee.reveal().is_none());
+        assert!(!referee.waiting_for(Color::White) && referee.waiting_for(Color::Black));
+        referee.submit(Color::Black, sealed("e7e5")).unwrap();
+        assert!(referee.reveal().is_some());
+        assert!(referee.waiting_for(Color::White));
+        assert_eq!((letter_at(&referee.board, "e4"), letter_at(&referee.board, "e5")), (Some('P'), Some('p')));
+    }
+}
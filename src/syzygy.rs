@@ -0,0 +1,1182 @@
+use std::collections::HashMap;
+use std::env;
+use std::fs;
+use std::io;
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex, OnceLock};
+
+use crate::position::{Move, Position};
+use crate::variant::Variant;
+use crate::{Color, PieceType};
+
+// Syzygy files start with these bytes: .rtbw files hold win/draw/loss results and
+// .rtbz files the distance to the next capture or pawn move
+const WDL_MAGIC: [u8; 4] = [0x71, 0xe8, 0x23, 0x5d];
+const DTZ_MAGIC: [u8; 4] = [0xd7, 0x66, 0x0c, 0xa5];
+
+// Flag in the first byte of a file
+const HAS_PAWNS: u8 = 2;
+
+// Flags of each compressed part of a table
+const BLACK_TO_MOVE: u8 = 1;
+const MAPPED: u8 = 2;
+const WIN_PLIES: u8 = 4;
+const LOSS_PLIES: u8 = 8;
+const WIDE: u8 = 16;
+const SINGLE_VALUE: u8 = 128;
+
+// The largest tables published cover seven pieces
+const MAX_PIECES: usize = 7;
+
+// Root moves are ranked on this scale, with certain wins at the top
+const MAX_DTZ: i32 = 1 << 18;
+
+// The theoretical result of a position for the side to move. Cursed wins and blessed losses
+// would be decided, but the fifty-move rule comes first and makes them draws.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Wdl {
+    Loss,
+    BlessedLoss,
+    Draw,
+    CursedWin,
+    Win,
+}
+
+impl Wdl {
+    // Results are handled as numbers from -2 for a loss to 2 for a win, as the tables store them
+    fn from_value(value: i32) -> Wdl {
+        match value {
+            -2 => Wdl::Loss,
+            -1 => Wdl::BlessedLoss,
+            1 => Wdl::CursedWin,
+            2 => Wdl::Win,
+            _ => Wdl::Draw,
+        }
+    }
+}
+
+// Describe a result in words, with the distance to zeroing in half-moves when it is known
+pub fn describe_wdl(wdl: Wdl, dtz: Option<i32>) -> String {
+    let result = match wdl {
+        Wdl::Win => "win",
+        Wdl::CursedWin => "win, but drawn by the fifty-move rule",
+        Wdl::Draw => "draw",
+        Wdl::BlessedLoss => "loss, but drawn by the fifty-move rule",
+        Wdl::Loss => "loss",
+    };
+    match dtz {
+        Some(dtz) if dtz != 0 => format!("{} (DTZ {})", result, dtz),
+        _ => result.to_string(),
+    }
+}
+
+// Squares are numbered as in the tables: a1 is 0, h1 is 7 and h8 is 63
+fn rank_of(square: usize) -> usize {
+    square / 8
+}
+
+fn file_of(square: usize) -> usize {
+    square % 8
+}
+
+// Which side of the a1-h8 diagonal a square is on: negative below it, positive above it
+fn off_diagonal(square: usize) -> i32 {
+    rank_of(square) as i32 - file_of(square) as i32
+}
+
+// The tables that turn piece placements into positions within a table, the same for all files
+struct Indices {
+    // binomial[k][n] is the number of ways to choose k squares out of n
+    binomial: [[u64; 64]; 6],
+    // The squares a2-h7 numbered so that the pawn with the highest number leads
+    map_pawns: [usize; 64],
+    lead_pawn_idx: [[u64; 64]; 6],
+    lead_pawns_size: [[u64; 4]; 6],
+    // The squares below the a1-h8 diagonal numbered 0-27
+    map_b1h1h7: [u64; 64],
+    // The squares of the a1-d1-d4 triangle numbered 0-9, those on the diagonal last
+    map_a1d1d4: [u64; 64],
+    // The 462 ways to place two kings with the first one in the triangle
+    map_kk: [[u64; 64]; 10],
+}
+
+fn indices() -> &'static Indices {
+    static INDICES: OnceLock<Indices> = OnceLock::new();
+    INDICES.get_or_init(|| {
+        let mut indices = Indices {
+            binomial: [[0; 64]; 6],
+            map_pawns: [0; 64],
+            lead_pawn_idx: [[0; 64]; 6],
+            lead_pawns_size: [[0; 4]; 6],
+            map_b1h1h7: [0; 64],
+            map_a1d1d4: [0; 64],
+            map_kk: [[0; 64]; 10],
+        };
+
+        let mut code = 0;
+        for square in 0..64 {
+            if off_diagonal(square) < 0 {
+                indices.map_b1h1h7[square] = code;
+                code += 1;
+            }
+        }
+
+        let triangle = (0..=27).filter(|&square| file_of(square) <= 3);
+        let below = triangle.clone().filter(|&square| off_diagonal(square) < 0);
+        for (code, square) in below.chain(triangle.filter(|&square| off_diagonal(square) == 0)).enumerate() {
+            indices.map_a1d1d4[square] = code as u64;
+        }
+
+        // Kings next to each other cannot be placed, and when the first king is on the diagonal
+        // the second one is kept below it. Kings both on the diagonal are numbered last.
+        let mut code = 0;
+        let mut both_on_diagonal = Vec::new();
+        for idx in 0..10 {
+            for first in 0..=27 {
+                if file_of(first) > 3 || off_diagonal(first) > 0 || indices.map_a1d1d4[first] != idx as u64 {
+                    continue;
+                }
+                for second in 0..64 {
+                    let touching = file_of(first).abs_diff(file_of(second)) <= 1 && rank_of(first).abs_diff(rank_of(second)) <= 1;
+                    if touching || off_diagonal(first) == 0 && off_diagonal(second) > 0 {
+                        continue;
+                    }
+                    if off_diagonal(first) == 0 && off_diagonal(second) == 0 {
+                        both_on_diagonal.push((idx, second));
+                    } else {
+                        indices.map_kk[idx][second] = code;
+                        code += 1;
+                    }
+                }
+            }
+        }
+        for (idx, second) in both_on_diagonal {
+            indices.map_kk[idx][second] = code;
+            code += 1;
+        }
+
+        indices.binomial[0][0] = 1;
+        for n in 1..64 {
+            for k in 0..6.min(n + 1) {
+                indices.binomial[k][n] = if k > 0 { indices.binomial[k - 1][n - 1] } else { 0 } + if k < n { indices.binomial[k][n - 1] } else { 0 };
+            }
+        }
+
+        // The leading pawn is the one nearest the edge and, on the same file, the furthest back.
+        // Tables with pawns are split by the file of the leading pawn, mirrored onto files a-d.
+        let mut available = 48;
+        for lead_pawns in 1..=5 {
+            for file in 0..4 {
+                let mut idx = 0;
+                for rank in 1..7 {
+                    let square = rank * 8 + file;
+                    if lead_pawns == 1 {
+                        indices.map_pawns[square] = available - 1;
+                        indices.map_pawns[square ^ 7] = available - 2;
+                        available -= 2;
+                    }
+                    indices.lead_pawn_idx[lead_pawns][square] = idx;
+                    idx += indices.binomial[lead_pawns - 1][indices.map_pawns[square]];
+                }
+                indices.lead_pawns_size[lead_pawns][file] = idx;
+            }
+        }
+
+        indices
+    })
+}
+
+// Little-endian and big-endian numbers in a table file, None past its end
+fn read_u8(bytes: &[u8], at: usize) -> Option<u8> {
+    bytes.get(at).copied()
+}
+
+fn read_u16(bytes: &[u8], at: usize) -> Option<u16> {
+    Some(u16::from_le_bytes(bytes.get(at..at + 2)?.try_into().ok()?))
+}
+
+fn read_u32(bytes: &[u8], at: usize) -> Option<u32> {
+    Some(u32::from_le_bytes(bytes.get(at..at + 4)?.try_into().ok()?))
+}
+
+fn read_u32_be(bytes: &[u8], at: usize) -> Option<u32> {
+    Some(u32::from_be_bytes(bytes.get(at..at + 4)?.try_into().ok()?))
+}
+
+fn read_u64_be(bytes: &[u8], at: usize) -> Option<u64> {
+    Some(u64::from_be_bytes(bytes.get(at..at + 8)?.try_into().ok()?))
+}
+
+// One compressed part of a table: a side to move, and with pawns a file of the leading pawn.
+// Positions are numbered by placing groups of pieces, and their values are stored as Huffman
+// coded symbols that each stand for a run of values built up by pairing smaller symbols.
+#[derive(Debug, Clone, Default)]
+struct PairsData {
+    flags: u8,
+    min_sym_len: u8,
+    block_size: usize,
+    span: u64,
+    num_blocks: usize,
+    block_length_size: usize,
+    sparse_index_size: usize,
+    // Offsets of the parts of the table in the file
+    lowest_sym: usize,
+    btree: usize,
+    sparse_index: usize,
+    block_length: usize,
+    data: usize,
+    // base64[l] is the lowest code of length l + min_sym_len, padded to 64 bits
+    base64: Vec<u64>,
+    // How many values, less one, each symbol stands for
+    symlen: Vec<u8>,
+    // The pieces in the order they are placed, grouped by group_len, and the factor each
+    // group's placement is multiplied by
+    pieces: [u8; MAX_PIECES],
+    group_len: [usize; MAX_PIECES + 1],
+    group_idx: [u64; MAX_PIECES + 1],
+    // Where the values of wins, losses, cursed wins and blessed losses are remapped in DTZ tables
+    map_idx: [usize; 4],
+}
+
+impl PairsData {
+    // The symbols a symbol pairs up, 12 bits each
+    fn left(&self, bytes: &[u8], sym: usize) -> usize {
+        let at = self.btree + sym * 3;
+        ((read_u8(bytes, at + 1).unwrap_or(0) as usize & 0xf) << 8) | read_u8(bytes, at).unwrap_or(0) as usize
+    }
+
+    fn right(&self, bytes: &[u8], sym: usize) -> usize {
+        let at = self.btree + sym * 3;
+        ((read_u8(bytes, at + 2).unwrap_or(0) as usize) << 4) | (read_u8(bytes, at + 1).unwrap_or(0) as usize >> 4)
+    }
+
+    // Count the values a symbol stands for by expanding its pairs
+    fn set_symlen(&mut self, bytes: &[u8], sym: usize, visited: &mut [bool]) -> u8 {
+        visited[sym] = true;
+        let right = self.right(bytes, sym);
+        if right == 0xfff {
+            return 0;
+        }
+        let left = self.left(bytes, sym);
+        for child in [left, right] {
+            if child < visited.len() && !visited[child] {
+                self.symlen[child] = self.set_symlen(bytes, child, visited);
+            }
+        }
+        let length = |child: usize| self.symlen.get(child).copied().unwrap_or(0);
+        length(left).wrapping_add(length(right)).wrapping_add(1)
+    }
+
+    // Read the sizes and the Huffman code of the part, returning the offset after them
+    fn set_sizes(&mut self, bytes: &[u8], mut at: usize) -> Option<usize> {
+        self.flags = read_u8(bytes, at)?;
+        at += 1;
+        if self.flags & SINGLE_VALUE != 0 {
+            // Every position has the same value, stored in place of the code
+            self.min_sym_len = read_u8(bytes, at)?;
+            return Some(at + 1);
+        }
+
+        let groups = self.group_len.iter().position(|&len| len == 0)?;
+        let size = self.group_idx[groups];
+        self.block_size = 1usize.checked_shl(read_u8(bytes, at)? as u32)?;
+        self.span = 1u64.checked_shl(read_u8(bytes, at + 1)? as u32)?;
+        self.sparse_index_size = size.div_ceil(self.span) as usize;
+        let padding = read_u8(bytes, at + 2)? as usize;
+        self.num_blocks = read_u32(bytes, at + 3)? as usize;
+        self.block_length_size = self.num_blocks + padding;
+        let max_sym_len = read_u8(bytes, at + 7)?;
+        self.min_sym_len = read_u8(bytes, at + 8)?;
+        at += 9;
+        if max_sym_len < self.min_sym_len {
+            return None;
+        }
+
+        // Longer codes have lower values, so base64 decreases with the code length
+        self.lowest_sym = at;
+        let lengths = (max_sym_len - self.min_sym_len) as usize + 1;
+        self.base64 = vec![0; lengths];
+        for i in (0..lengths - 1).rev() {
+            let lowest = read_u16(bytes, at + i * 2)? as u64;
+            let next_lowest = read_u16(bytes, at + i * 2 + 2)? as u64;
+            self.base64[i] = self.base64[i + 1].wrapping_add(lowest).wrapping_sub(next_lowest) / 2;
+        }
+        for (i, base) in self.base64.iter_mut().enumerate() {
+            *base = base.checked_shl(64 - i as u32 - self.min_sym_len as u32).unwrap_or(0);
+        }
+        at += lengths * 2;
+
+        let symbols = read_u16(bytes, at)? as usize;
+        at += 2;
+        self.btree = at;
+        self.symlen = vec![0; symbols];
+        let mut visited = vec![false; symbols];
+        for sym in 0..symbols {
+            if !visited[sym] {
+                self.symlen[sym] = self.set_symlen(bytes, sym, &mut visited);
+            }
+        }
+        Some(at + symbols * 3 + (symbols & 1))
+    }
+
+    // Find the value stored for a position number
+    fn decompress(&self, bytes: &[u8], idx: u64) -> Option<i32> {
+        if self.flags & SINGLE_VALUE != 0 {
+            return Some(self.min_sym_len as i32);
+        }
+
+        // The sparse index gives the block and offset of every span-th value, counted from the
+        // middle of the span, and the blocks around it are walked from there
+        let entry = self.sparse_index + (idx / self.span) as usize * 6;
+        let mut block = read_u32(bytes, entry)? as usize;
+        let mut offset = read_u16(bytes, entry + 4)? as i64 + (idx % self.span) as i64 - (self.span / 2) as i64;
+        let block_length = |block: usize| read_u16(bytes, self.block_length + block * 2).map(|length| length as i64);
+        while offset < 0 {
+            block = block.checked_sub(1)?;
+            offset += block_length(block)? + 1;
+        }
+        while offset > block_length(block)? {
+            offset -= block_length(block)? + 1;
+            block += 1;
+        }
+
+        // Read codes from the start of the block until reaching the one that covers the offset
+        let mut at = self.data + block * self.block_size;
+        let mut buffer = read_u64_be(bytes, at)?;
+        let mut buffer_size = 64;
+        at += 8;
+        let symlen = |sym: usize| self.symlen.get(sym).map(|&length| length as i64);
+        let mut sym;
+        loop {
+            let mut len = 0;
+            while buffer < *self.base64.get(len)? {
+                len += 1;
+            }
+            let shift = 64 - len as u32 - self.min_sym_len as u32;
+            sym = (buffer - self.base64[len]).checked_shr(shift).unwrap_or(0) as u16;
+            sym = sym.wrapping_add(read_u16(bytes, self.lowest_sym + len * 2)?);
+            if offset < symlen(sym as usize)? + 1 {
+                break;
+            }
+            offset -= symlen(sym as usize)? + 1;
+            let consumed = len as u32 + self.min_sym_len as u32;
+            buffer = buffer.checked_shl(consumed).unwrap_or(0);
+            buffer_size -= consumed;
+            if buffer_size <= 32 {
+                buffer_size += 32;
+                buffer |= (read_u32_be(bytes, at).unwrap_or(0) as u64) << (64 - buffer_size);
+                at += 4;
+            }
+        }
+
+        // Expand the symbol's pairs down to the single value at the offset
+        let mut sym = sym as usize;
+        while symlen(sym)? != 0 {
+            let left = self.left(bytes, sym);
+            if offset < symlen(left)? + 1 {
+                sym = left;
+            } else {
+                offset -= symlen(left)? + 1;
+                sym = self.right(bytes, sym);
+            }
+        }
+        Some(self.left(bytes, sym) as i32)
+    }
+}
+
+// Why a table could not give a value
+enum ProbeError {
+    // The table is not there or could not be read
+    Missing,
+    // DTZ tables only store one side to move, and this is the other one
+    OtherSide,
+}
+
+// A table file read into memory, with the layout of its parts
+struct Table {
+    bytes: Vec<u8>,
+    dtz: bool,
+    piece_count: usize,
+    has_pawns: bool,
+    // At least one side has a piece other than the king that it has only one of
+    has_unique_pieces: bool,
+    // Both sides have the same pieces, so only White to move is stored
+    symmetric: bool,
+    // The pawns of the side whose pawns lead, then the other side's
+    pawn_count: [usize; 2],
+    // By side to move, then by file of the leading pawn
+    parts: Vec<Vec<PairsData>>,
+}
+
+impl Table {
+    // Read the layout of a table named like "KRPvKR" from the contents of its file
+    fn parse(name: &str, bytes: Vec<u8>, dtz: bool) -> Option<Table> {
+        let (white, black) = name.split_once('v')?;
+        let count = |side: &str, letter: char| side.chars().filter(|&c| c == letter).count();
+        let (white_pawns, black_pawns) = (count(white, 'P'), count(black, 'P'));
+        // The side with fewer pawns leads, as that compresses better
+        let white_leads = black_pawns == 0 || white_pawns > 0 && black_pawns >= white_pawns;
+        let mut table = Table {
+            piece_count: white.len() + black.len(),
+            has_pawns: white_pawns + black_pawns > 0,
+            has_unique_pieces: [white, black].iter().any(|side| "PNBRQ".chars().any(|letter| count(side, letter) == 1)),
+            symmetric: white == black,
+            pawn_count: if white_leads { [white_pawns, black_pawns] } else { [black_pawns, white_pawns] },
+            parts: Vec::new(),
+            bytes: Vec::new(),
+            dtz,
+        };
+
+        let magic = if dtz { DTZ_MAGIC } else { WDL_MAGIC };
+        if bytes.get(..4)? != magic || (read_u8(&bytes, 4)? & HAS_PAWNS != 0) != table.has_pawns {
+            return None;
+        }
+        let mut at = 5;
+
+        let sides = if !dtz && !table.symmetric { 2 } else { 1 };
+        let files = if table.has_pawns { 4 } else { 1 };
+        let both_have_pawns = table.has_pawns && table.pawn_count[1] > 0;
+        let mut parts = vec![vec![PairsData::default(); files]; sides];
+        for file in 0..files {
+            // The order the groups are numbered in, for each side to move in one byte
+            let order = read_u8(&bytes, at)?;
+            let pawn_order = if both_have_pawns { read_u8(&bytes, at + 1)? } else { 0xff };
+            at += 1 + both_have_pawns as usize;
+            for k in 0..table.piece_count {
+                let pieces = read_u8(&bytes, at + k)?;
+                for (side, part) in parts.iter_mut().enumerate() {
+                    part[file].pieces[k] = if side == 0 { pieces & 0xf } else { pieces >> 4 };
+                }
+            }
+            at += table.piece_count;
+            for (side, part) in parts.iter_mut().enumerate() {
+                let nibble = |byte: u8| if side == 0 { byte & 0xf } else { byte >> 4 };
+                table.set_groups(&mut part[file], [nibble(order) as usize, nibble(pawn_order) as usize], file);
+            }
+        }
+        at += at & 1;
+
+        for file in 0..files {
+            for part in parts.iter_mut() {
+                at = part[file].set_sizes(&bytes, at)?;
+            }
+        }
+
+        // DTZ tables list their values by frequency for each result, with a map back to distances
+        if dtz {
+            for part in parts[0].iter_mut() {
+                if part.flags & MAPPED == 0 {
+                    continue;
+                }
+                if part.flags & WIDE != 0 {
+                    at += at & 1;
+                    for i in 0..4 {
+                        part.map_idx[i] = at + 2;
+                        at += 2 * read_u16(&bytes, at)? as usize + 2;
+                    }
+                } else {
+                    for i in 0..4 {
+                        part.map_idx[i] = at + 1;
+                        at += read_u8(&bytes, at)? as usize + 1;
+                    }
+                }
+            }
+            at += at & 1;
+        }
+
+        for file in 0..files {
+            for part in parts.iter_mut() {
+                part[file].sparse_index = at;
+                at += part[file].sparse_index_size * 6;
+            }
+        }
+        for file in 0..files {
+            for part in parts.iter_mut() {
+                part[file].block_length = at;
+                at += part[file].block_length_size * 2;
+            }
+        }
+        for file in 0..files {
+            for part in parts.iter_mut().filter(|part| part[file].num_blocks > 0) {
+                // Blocks start on 64 byte boundaries
+                at = (at + 0x3f) & !0x3f;
+                part[file].data = at;
+                at += part[file].num_blocks * part[file].block_size;
+            }
+        }
+        if at > bytes.len() {
+            return None;
+        }
+
+        table.parts = parts;
+        table.bytes = bytes;
+        Some(table)
+    }
+
+    // Split the pieces into the groups placed together and work out the factor of each group.
+    // The first group is the leading pawns, or without pawns three unique pieces or the two kings,
+    // and every other group is pieces of one type and color.
+    fn set_groups(&self, part: &mut PairsData, order: [usize; 2], file: usize) {
+        let indices = indices();
+        let mut n = 0;
+        let mut first_len = if self.has_pawns { 0 } else if self.has_unique_pieces { 3 } else { 2 };
+        part.group_len[0] = 1;
+        for i in 1..self.piece_count {
+            first_len -= 1;
+            if first_len > 0 || part.pieces[i] == part.pieces[i - 1] {
+                part.group_len[n] += 1;
+            } else {
+                n += 1;
+                part.group_len[n] = 1;
+            }
+        }
+        n += 1;
+        part.group_len[n] = 0;
+
+        // The other side's pawns, if any, come second in the pieces but can be numbered in any order
+        let both_have_pawns = self.has_pawns && self.pawn_count[1] > 0;
+        let mut next = if both_have_pawns { 2 } else { 1 };
+        let mut free_squares = 64 - part.group_len[0] - if both_have_pawns { part.group_len[1] } else { 0 };
+        let mut idx = 1;
+        let mut k = 0;
+        while next < n || k == order[0] || k == order[1] {
+            if k == order[0] {
+                part.group_idx[0] = idx;
+                idx *= if self.has_pawns {
+                    indices.lead_pawns_size[part.group_len[0]][file]
+                } else if self.has_unique_pieces {
+                    31332
+                } else {
+                    462
+                };
+            } else if k == order[1] {
+                part.group_idx[1] = idx;
+                idx *= indices.binomial[part.group_len[1]][48 - part.group_len[0]];
+            } else {
+                part.group_idx[next] = idx;
+                idx *= indices.binomial[part.group_len[next]][free_squares];
+                free_squares -= part.group_len[next];
+                next += 1;
+            }
+            k += 1;
+        }
+        part.group_idx[n] = idx;
+    }
+
+    fn part(&self, side: usize, file: usize) -> &PairsData {
+        &self.parts[side % self.parts.len()][if self.has_pawns { file } else { 0 }]
+    }
+
+    // Turn a stored value into a result from -2 to 2, or for DTZ into half-moves
+    fn map_score(&self, file: usize, value: i32, wdl: i32) -> i32 {
+        if !self.dtz {
+            return value - 2;
+        }
+        let part = self.part(0, file);
+        let mut value = value;
+        if part.flags & MAPPED != 0 {
+            let map = part.map_idx[[1, 3, 0, 2, 0][(wdl + 2) as usize]];
+            value = if part.flags & WIDE != 0 {
+                read_u16(&self.bytes, map + value as usize * 2).unwrap_or(0) as i32
+            } else {
+                read_u8(&self.bytes, map + value as usize).unwrap_or(0) as i32
+            };
+        }
+        // Distances are stored in full moves unless the table says they are in half-moves
+        if wdl == 2 && part.flags & WIN_PLIES == 0 || wdl == -2 && part.flags & LOSS_PLIES == 0 || wdl == 1 || wdl == -1 {
+            value *= 2;
+        }
+        value + 1
+    }
+
+    // Look up a position given as piece codes by square. The colors are swapped and the board
+    // mirrored top to bottom when Black is the side the table calls White.
+    fn probe(&self, pieces: &[(usize, u8)], black_to_move: bool, mirrored: bool, wdl: i32) -> Result<i32, ProbeError> {
+        let indices = indices();
+        let flip_color = if mirrored { 8 } else { 0 };
+        let flip_squares = if mirrored { 56 } else { 0 };
+        let side = (mirrored != black_to_move) as usize;
+
+        let mut squares = Vec::new();
+        let mut codes = Vec::new();
+        let mut lead_pawns = 0;
+        let mut file = 0;
+        let mut lead_pawn = None;
+        if self.has_pawns {
+            // Every part starts with a pawn of the leading color
+            let code = self.parts[0][0].pieces[0] ^ flip_color;
+            lead_pawn = Some(code);
+            for &(square, _) in pieces.iter().filter(|piece| piece.1 == code) {
+                squares.push(square ^ flip_squares);
+                codes.push(code ^ flip_color);
+            }
+            lead_pawns = squares.len();
+            let lead = (0..lead_pawns).rev().max_by_key(|&i| indices.map_pawns[squares[i]]).ok_or(ProbeError::Missing)?;
+            squares.swap(0, lead);
+            file = file_of(squares[0]).min(7 - file_of(squares[0]));
+        }
+
+        let stored_side = (self.part(side, file).flags & BLACK_TO_MOVE) as usize;
+        // Symmetric tables without pawns look the same from either side
+        if self.dtz && stored_side != side && (self.has_pawns || !self.symmetric) {
+            return Err(ProbeError::OtherSide);
+        }
+
+        for &(square, code) in pieces.iter().filter(|piece| Some(piece.1) != lead_pawn) {
+            squares.push(square ^ flip_squares);
+            codes.push(code ^ flip_color);
+        }
+        let size = squares.len();
+        let part = self.part(side, file);
+
+        // Put the pieces in the order the table places them
+        for i in lead_pawns..size.saturating_sub(1) {
+            if let Some(j) = (i + 1..size).find(|&j| codes[j] == part.pieces[i]) {
+                codes.swap(i, j);
+                squares.swap(i, j);
+            }
+        }
+
+        // Mirror the board so the first piece is on files a-d
+        if file_of(squares[0]) > 3 {
+            for square in squares.iter_mut() {
+                *square ^= 7;
+            }
+        }
+
+        let mut idx;
+        if self.has_pawns {
+            idx = indices.lead_pawn_idx[lead_pawns][squares[0]];
+            squares[1..lead_pawns].sort_by_key(|&square| indices.map_pawns[square]);
+            for (i, &square) in squares.iter().enumerate().take(lead_pawns).skip(1) {
+                idx += indices.binomial[i][indices.map_pawns[square]];
+            }
+        } else {
+            // Without pawns the board is also mirrored so the first piece is on ranks 1-4
+            if rank_of(squares[0]) > 3 {
+                for square in squares.iter_mut() {
+                    *square ^= 56;
+                }
+            }
+            // and along the a1-h8 diagonal so the first piece of the group off it is below it
+            for i in 0..part.group_len[0] {
+                if off_diagonal(squares[i]) == 0 {
+                    continue;
+                }
+                if off_diagonal(squares[i]) > 0 {
+                    for square in squares[i..].iter_mut() {
+                        *square = ((*square >> 3) | (*square << 3)) & 63;
+                    }
+                }
+                break;
+            }
+
+            idx = if self.has_unique_pieces {
+                let (first, second, third) = (squares[0], squares[1], squares[2]);
+                let adjust1 = (second > first) as u64;
+                let adjust2 = (third > first) as u64 + (third > second) as u64;
+                let rank = |square: usize| rank_of(square) as u64;
+                if off_diagonal(first) != 0 {
+                    (indices.map_a1d1d4[first] * 63 + (second as u64 - adjust1)) * 62 + third as u64 - adjust2
+                } else if off_diagonal(second) != 0 {
+                    (6 * 63 + rank(first) * 28 + indices.map_b1h1h7[second]) * 62 + third as u64 - adjust2
+                } else if off_diagonal(third) != 0 {
+                    6 * 63 * 62 + 4 * 28 * 62 + rank(first) * 7 * 28 + (rank(second) - adjust1) * 28 + indices.map_b1h1h7[third]
+                } else {
+                    6 * 63 * 62 + 4 * 28 * 62 + 4 * 7 * 28 + rank(first) * 7 * 6 + (rank(second) - adjust1) * 6 + (rank(third) - adjust2)
+                }
+            } else {
+                indices.map_kk[indices.map_a1d1d4[squares[0]] as usize][squares[1]]
+            };
+        }
+
+        // Place the remaining groups on the squares left free by the earlier ones
+        idx *= part.group_idx[0];
+        let mut start = part.group_len[0];
+        let mut remaining_pawns = self.has_pawns && self.pawn_count[1] > 0;
+        let mut next = 1;
+        while part.group_len[next] != 0 {
+            let len = part.group_len[next];
+            squares[start..start + len].sort();
+            let mut n = 0;
+            for i in 0..len {
+                let square = squares[start + i];
+                let adjust = squares[..start].iter().filter(|&&earlier| square > earlier).count();
+                // Pawns are never on the first rank
+                n += indices.binomial[i + 1][square - adjust - if remaining_pawns { 8 } else { 0 }];
+            }
+            remaining_pawns = false;
+            idx += n * part.group_idx[next];
+            start += len;
+            next += 1;
+        }
+
+        let value = part.decompress(&self.bytes, idx).ok_or(ProbeError::Missing)?;
+        Ok(self.map_score(file, value, wdl))
+    }
+}
+
+// Name a side's pieces the way table files are named, e.g. "KRP"
+fn side_name(pieces: &[(Color, PieceType)], color: Color) -> String {
+    let mut name = String::new();
+    for (piece_type, letter) in [(PieceType::King, 'K'), (PieceType::Queen, 'Q'), (PieceType::Rook, 'R'), (PieceType::Bishop, 'B'), (PieceType::Knight, 'N'), (PieceType::Pawn, 'P')] {
+        for _ in pieces.iter().filter(|piece| piece.0 == color && piece.1 == piece_type) {
+            name.push(letter);
+        }
+    }
+    name
+}
+
+// Check that a file name's stem is a table name such as "KRPvKR"
+fn is_table_name(name: &str) -> bool {
+    match name.split_once('v') {
+        Some((white, black)) => [white, black].iter().all(|side| side.starts_with('K') && side[1..].chars().all(|c| "QRBNP".contains(c))),
+        None => false,
+    }
+}
+
+// Win/draw/loss values after a move that resets the fifty-move counter, as a distance to zeroing
+fn dtz_before_zeroing(wdl: i32) -> i32 {
+    match wdl {
+        2 => 1,
+        1 => 101,
+        -1 => -101,
+        -2 => -1,
+        _ => 0,
+    }
+}
+
+// Syzygy tablebases found in one or more directories. Tables are read into memory the first
+// time a position needs them.
+#[derive(Default)]
+pub struct SyzygyTables {
+    // The file of every table found, by file name such as "KRvK.rtbw"
+    files: HashMap<String, PathBuf>,
+    max_pieces: usize,
+    loaded: Mutex<HashMap<String, Option<Arc<Table>>>>,
+}
+
+impl SyzygyTables {
+    pub fn new() -> SyzygyTables {
+        SyzygyTables::default()
+    }
+
+    // Find the tables in a list of directories separated like the PATH variable
+    pub fn load_dirs(dirs: &str) -> io::Result<SyzygyTables> {
+        let mut tables = SyzygyTables::new();
+        for dir in env::split_paths(dirs) {
+            for entry in fs::read_dir(&dir)? {
+                let path = entry?.path();
+                let (Some(stem), Some(extension)) = (path.file_stem().and_then(|stem| stem.to_str()), path.extension()) else {
+                    continue;
+                };
+                if (extension == "rtbw" || extension == "rtbz") && is_table_name(stem) {
+                    tables.max_pieces = tables.max_pieces.max(stem.len() - 1).min(MAX_PIECES);
+                    let name = path.file_name().unwrap().to_string_lossy().to_string();
+                    tables.files.entry(name).or_insert(path);
+                }
+            }
+        }
+        Ok(tables)
+    }
+
+    // The most pieces on the board the tables found can cover
+    pub fn max_pieces(&self) -> usize {
+        self.max_pieces
+    }
+
+    // Read a table file the first time it is needed, remembering files that cannot be used
+    fn table(&self, file_name: &str) -> Option<Arc<Table>> {
+        let mut loaded = self.loaded.lock().unwrap();
+        if let Some(table) = loaded.get(file_name) {
+            return table.clone();
+        }
+        let (name, extension) = file_name.split_once('.')?;
+        let table = self
+            .files
+            .get(file_name)
+            .and_then(|path| fs::read(path).ok())
+            .and_then(|bytes| Table::parse(name, bytes, extension == "rtbz"))
+            .map(Arc::new);
+        loaded.insert(file_name.to_string(), table.clone());
+        table
+    }
+
+    // Probe the table for a position's material, either win/draw/loss or DTZ
+    fn probe_table(&self, position: &Position, dtz: bool, wdl: i32) -> Result<i32, ProbeError> {
+        let mut pieces = Vec::new();
+        let mut material = Vec::new();
+        for (x, row) in position.board.iter().enumerate() {
+            for (y, square) in row.iter().enumerate() {
+                if let Some(piece) = square {
+                    let code = match piece.piece_type {
+                        PieceType::Pawn => 1,
+                        PieceType::Knight => 2,
+                        PieceType::Bishop => 3,
+                        PieceType::Rook => 4,
+                        PieceType::Queen => 5,
+                        PieceType::King => 6,
+                        _ => return Err(ProbeError::Missing),
+                    };
+                    pieces.push(((7 - x) * 8 + y, code | if piece.color == Color::Black { 8 } else { 0 }));
+                    material.push((piece.color, piece.piece_type.clone()));
+                }
+            }
+        }
+        // Two bare kings are always a draw and have no table
+        if pieces.len() == 2 {
+            return Ok(0);
+        }
+        pieces.sort();
+
+        // Tables are named with the stronger side first and stored as if it were White
+        let (white, black) = (side_name(&material, Color::White), side_name(&material, Color::Black));
+        let extension = if dtz { "rtbz" } else { "rtbw" };
+        let black_to_move = position.side_to_move == Color::Black;
+        let straight = format!("{}v{}.{}", white, black, extension);
+        let (table, mirrored) = match self.table(&straight) {
+            // With the same pieces on both sides only White to move is stored
+            Some(table) => (table, white == black && black_to_move),
+            None => (self.table(&format!("{}v{}.{}", black, white, extension)).ok_or(ProbeError::Missing)?, true),
+        };
+        table.probe(&pieces, black_to_move, mirrored, wdl)
+    }
+
+    // Work out the win/draw/loss value of a position, trying the captures first since the
+    // tables may store any value for positions where a capture is best. With zeroing set pawn
+    // moves are tried as well, as DTZ tables do not store positions where one is best.
+    // Returns the value and whether a capture or pawn move achieves it.
+    fn search(&self, position: &Position, zeroing: bool) -> Option<(i32, bool)> {
+        let moves = position.legal_moves();
+        let mut best = -2;
+        let mut searched = 0;
+        for mv in &moves {
+            if !(is_capture(position, mv) || zeroing && is_pawn_move(position, mv)) {
+                continue;
+            }
+            searched += 1;
+            let mut next = position.clone();
+            next.make_move(mv);
+            let value = -self.search(&next, false)?.0;
+            if value > best {
+                best = value;
+                if value >= 2 {
+                    return Some((value, true));
+                }
+            }
+        }
+
+        // The table does not know about en passant, so when every move has been tried trust them instead
+        let tried_all = searched > 0 && searched == moves.len();
+        let value = if tried_all {
+            best
+        } else {
+            self.probe_table(position, false, 0).ok()?
+        };
+        if best >= value {
+            return Some((best, best > 0 || tried_all));
+        }
+        Some((value, false))
+    }
+
+    // Distance to zeroing in half-moves from the side to move's point of view, positive when winning
+    fn dtz(&self, position: &Position) -> Option<i32> {
+        let (wdl, zeroing) = self.search(position, true)?;
+        if wdl == 0 {
+            return Some(0);
+        }
+        if zeroing {
+            return Some(dtz_before_zeroing(wdl));
+        }
+        match self.probe_table(position, true, wdl) {
+            Ok(dtz) => return Some((dtz + if wdl.abs() == 1 { 100 } else { 0 }) * wdl.signum()),
+            Err(ProbeError::Missing) => return None,
+            Err(ProbeError::OtherSide) => {},
+        }
+
+        // The table stores the other side to move, so look one move ahead for the best distance
+        let mut min_dtz = i32::MAX;
+        for mv in position.legal_moves() {
+            let zeroing = is_capture(position, &mv) || is_pawn_move(position, &mv);
+            let mut next = position.clone();
+            next.make_move(&mv);
+            let mut dtz = if zeroing { -dtz_before_zeroing(self.search(&next, false)?.0) } else { -self.dtz(&next)? };
+            if dtz == 1 && is_checkmate(&next) {
+                min_dtz = 1;
+            }
+            if !zeroing {
+                dtz += dtz.signum();
+            }
+            if dtz < min_dtz && dtz.signum() == wdl.signum() {
+                min_dtz = dtz;
+            }
+        }
+        Some(if min_dtz == i32::MAX { -1 } else { min_dtz })
+    }
+
+    // Check whether the tables can cover a position at all
+    fn covers(&self, position: &Position) -> bool {
+        let pieces = position.board.iter().flatten().filter(|square| square.is_some()).count();
+        position.variant == Variant::Standard
+            && position.board.len() == 8
+            && position.board[0].len() == 8
+            && position.white_castling == (false, false)
+            && position.black_castling == (false, false)
+            && pieces <= self.max_pieces
+    }
+
+    // Find the win/draw/loss value of a position if the tables cover it
+    pub fn probe_wdl(&self, position: &Position) -> Option<Wdl> {
+        if !self.covers(position) {
+            return None;
+        }
+        self.search(position, false).map(|(wdl, _)| Wdl::from_value(wdl))
+    }
+
+    // Find the distance to zeroing of a position if the tables cover it. It is counted in half-moves
+    // to the next capture or pawn move, positive when the side to move wins and negative when it
+    // loses, beyond 100 either way when the fifty-move rule saves the loser, and 0 for a draw.
+    // The distance can be one more than given when the table stores it in full moves.
+    pub fn probe_dtz(&self, position: &Position) -> Option<i32> {
+        if !self.covers(position) {
+            return None;
+        }
+        self.dtz(position)
+    }
+
+    // Keep the moves that hold the best result at the root, so the search only chooses among them.
+    // With DTZ tables, wins that are safe from the fifty-move rule count the same and riskier wins
+    // count less the longer they take; without them the win/draw/loss values are used.
+    pub fn root_moves(&self, position: &Position) -> Option<Vec<Move>> {
+        if !self.covers(position) {
+            return None;
+        }
+        let moves = position.legal_moves();
+        let ranks = self.dtz_ranks(position, &moves).or_else(|| self.wdl_ranks(position, &moves))?;
+        let best = *ranks.iter().max()?;
+        Some(moves.into_iter().zip(ranks).filter(|&(_, rank)| rank == best).map(|(mv, _)| mv).collect())
+    }
+
+    fn dtz_ranks(&self, position: &Position, moves: &[Move]) -> Option<Vec<i32>> {
+        let clock = position.halfmove_clock as i32;
+        let mut ranks = Vec::new();
+        for mv in moves {
+            let mut next = position.clone();
+            next.make_move(mv);
+            let mut dtz = if next.halfmove_clock == 0 {
+                dtz_before_zeroing(-self.search(&next, false)?.0)
+            } else {
+                let dtz = -self.dtz(&next)?;
+                dtz + dtz.signum()
+            };
+            // A mating move is always the shortest way
+            if dtz == 2 && is_checkmate(&next) {
+                dtz = 1;
+            }
+            ranks.push(if dtz > 0 {
+                if dtz + clock <= 99 { MAX_DTZ } else { MAX_DTZ - (dtz + clock) }
+            } else if dtz < 0 {
+                if -dtz * 2 + clock < 100 { -MAX_DTZ } else { -MAX_DTZ + (-dtz + clock) }
+            } else {
+                0
+            });
+        }
+        Some(ranks)
+    }
+
+    fn wdl_ranks(&self, position: &Position, moves: &[Move]) -> Option<Vec<i32>> {
+        let mut ranks = Vec::new();
+        for mv in moves {
+            let mut next = position.clone();
+            next.make_move(mv);
+            let wdl = -self.search(&next, false)?.0;
+            ranks.push([-MAX_DTZ, -MAX_DTZ + 101, 0, MAX_DTZ - 101, MAX_DTZ][(wdl + 2) as usize]);
+        }
+        Some(ranks)
+    }
+}
+
+// Check whether a move takes a piece, including en passant
+fn is_capture(position: &Position, mv: &Move) -> bool {
+    position.board[mv.end.0][mv.end.1].is_some() || Some(mv.end) == position.en_passant && is_pawn_move(position, mv)
+}
+
+// Check whether the side to move has been mated
+fn is_checkmate(position: &Position) -> bool {
+    position.in_check() && position.legal_moves().is_empty()
+}
+
+// Check whether a move is made by a pawn, which resets the fifty-move counter
+fn is_pawn_move(position: &Position, mv: &Move) -> bool {
+    matches!(position.board[mv.start.0][mv.start.1], Some(ref piece) if piece.piece_type == PieceType::Pawn)
+}
+
+// Find the tables for an engine option, where an empty path means no tables
+pub fn load_syzygy(path: &str) -> Result<SyzygyTables, String> {
+    if path.is_empty() || path == "<empty>" {
+        return Ok(SyzygyTables::new());
+    }
+    SyzygyTables::load_dirs(path).map_err(|error| format!("Could not load Syzygy tables from {}: {}", path, error))
+}
+
+// Describe what the tables say about a position, for the "tb" command
+pub fn describe_position(tables: &SyzygyTables, position: &Position) -> String {
+    match tables.probe_wdl(position) {
+        Some(wdl) => describe_wdl(wdl, tables.probe_dtz(position)),
+        None if tables.max_pieces() == 0 => "no tablebases loaded".to_string(),
+        None => "not in the tablebases".to_string(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashSet;
+
+    use super::*;
+
+    #[test]
+    fn index_tables() {
+        let indices = indices();
+        // Two kings can be placed in 462 ways that are not mirror images of each other
+        let codes: HashSet<u64> = (0..10).flat_map(|idx| (0..64).map(move |square| (idx, square))).map(|(idx, square)| indices.map_kk[idx][square]).collect();
+        assert_eq!(codes.len(), 462);
+        assert_eq!(codes.iter().max(), Some(&461));
+        assert_eq!(indices.binomial[3][10], 120);
+        assert_eq!(indices.binomial[5][63], 7028847);
+        // a2 leads over h2, and b2 comes after every square on the a and h files
+        assert_eq!(indices.map_pawns[8], 47);
+        assert_eq!(indices.map_pawns[15], 46);
+        assert_eq!(indices.map_pawns[9], 35);
+        assert_eq!(indices.lead_pawns_size[1].iter().sum::<u64>(), 24);
+    }
+
+    #[test]
+    fn table_names() {
+        assert!(is_table_name("KRPvKR"));
+        assert!(is_table_name("KvK"));
+        assert!(!is_table_name("KRvRK"));
+        assert!(!is_table_name("KQK"));
+        let material = [(Color::White, PieceType::Pawn), (Color::White, PieceType::King), (Color::Black, PieceType::King), (Color::White, PieceType::Rook)];
+        assert_eq!(side_name(&material, Color::White), "KRP");
+        assert_eq!(side_name(&material, Color::Black), "K");
+    }
+
+    // A KRvK table that stores a single value for each side to move: win for White, loss for Black
+    fn single_value_table() -> Vec<u8> {
+        let mut bytes = WDL_MAGIC.to_vec();
+        // Split by side to move, the order of the groups, the pieces (white king, rook, black king)
+        // and a byte of padding
+        bytes.extend([1, 0x00, 0xe6, 0x44, 0xe6, 0]);
+        bytes.extend([SINGLE_VALUE, 4, SINGLE_VALUE, 0]);
+        bytes
+    }
+
+    #[test]
+    fn single_value_tables() {
+        let table = Table::parse("KRvK", single_value_table(), false).unwrap();
+        assert!(!table.has_pawns && table.has_unique_pieces && !table.symmetric);
+        assert_eq!(table.parts.len(), 2);
+        assert_eq!(table.parts[0][0].group_len[..2], [3, 0]);
+        assert_eq!(table.parts[0][0].group_idx[1], 31332);
+        assert!(Table::parse("KRvK", DTZ_MAGIC.to_vec(), false).is_none());
+
+        let dir = env::temp_dir().join(format!("syzygy-test-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        fs::write(dir.join("KRvK.rtbw"), single_value_table()).unwrap();
+        let tables = load_syzygy(dir.to_str().unwrap()).unwrap();
+        assert_eq!(tables.max_pieces(), 3);
+
+        let probe = |fen: &str| tables.probe_wdl(&Position::from_fen(fen, None).unwrap());
+        assert_eq!(probe("8/8/8/4k3/8/8/8/R3K3 w - - 0 1"), Some(Wdl::Win));
+        assert_eq!(probe("8/8/8/4k3/8/8/8/R3K3 b - - 0 1"), Some(Wdl::Loss));
+        // Black's rook is looked up with the colors swapped
+        assert_eq!(probe("r3k3/8/8/8/4K3/8/8/8 b - - 0 1"), Some(Wdl::Win));
+        // Taking the rook leaves two bare kings
+        assert_eq!(probe("8/8/8/8/8/8/6k1/4K2R b - - 0 1"), Some(Wdl::Draw));
+        // Missing tables, castling rights and other variants are not covered
+        assert_eq!(probe("8/8/8/4k3/8/8/8/Q3K3 w - - 0 1"), None);
+        assert_eq!(probe("8/8/8/4k3/8/8/8/4K2R w K - 0 1"), None);
+        assert_eq!(probe("8/8/8/4k3/8/8/8/R3K3[] w - - 0 1"), None);
+        assert_eq!(describe_position(&tables, &Position::new()), "not in the tablebases");
+        assert_eq!(describe_position(&SyzygyTables::new(), &Position::new()), "no tablebases loaded");
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn huffman_coded_tables() {
+        // KRvK with White to move coded as one bit per position in two blocks of 2048 bytes:
+        // symbol 0 is a win and symbol 1 a draw, drawn every third position
+        let mut bytes = WDL_MAGIC.to_vec();
+        bytes.extend([1, 0x00, 0xe6, 0x44, 0xe6, 0]);
+        bytes.extend([0, 11, 14, 0, 2, 0, 0, 0, 1, 1, 0, 0, 2, 0]);
+        bytes.extend([4, 0xf0, 0xff, 2, 0xf0, 0xff]);
+        bytes.extend([SINGLE_VALUE, 0]);
+        // The sparse index points into the middle of each span of 16384 positions
+        bytes.extend([0, 0, 0, 0, 0, 0x20, 1, 0, 0, 0, 0, 0x20]);
+        bytes.extend((16383u16).to_le_bytes());
+        bytes.extend((31331u16 - 16384).to_le_bytes());
+        bytes.resize(bytes.len().div_ceil(64) * 64, 0);
+        let data = bytes.len();
+        bytes.resize(data + 4096, 0);
+        for idx in (0..31332).step_by(3) {
+            let (block, offset) = (idx / 16384, idx % 16384);
+            bytes[data + block * 2048 + offset / 8] |= 0x80 >> (offset % 8);
+        }
+
+        // A block size of 2^64 bytes cannot be right
+        let mut broken = bytes.clone();
+        broken[11] = 64;
+        assert!(Table::parse("KRvK", broken, false).is_none());
+
+        let table = Table::parse("KRvK", bytes, false).unwrap();
+        let part = table.part(0, 0);
+        assert_eq!((part.sparse_index_size, part.num_blocks, part.data), (2, 2, data));
+        for idx in [0, 1, 2, 3, 100, 8191, 8192, 16383, 16384, 16385, 24576, 31329, 31331] {
+            assert_eq!(part.decompress(&table.bytes, idx), Some(if idx % 3 == 0 { 2 } else { 4 }), "position {}", idx);
+        }
+        assert_eq!(table.part(1, 0).decompress(&table.bytes, 5), Some(0));
+    }
+
+    // Probes the real KQvK and KRvK tables from the directories in SYZYGY_PATH, when it is set
+    #[test]
+    fn real_tables() {
+        let path = match env::var("SYZYGY_PATH") {
+            Ok(path) => path,
+            Err(_) => return,
+        };
+        let tables = load_syzygy(&path).unwrap();
+        let position = |fen: &str| Position::from_fen(fen, None).unwrap();
+
+        if tables.files.contains_key("KQvK.rtbw") && tables.files.contains_key("KQvK.rtbz") {
+            // Qc8 mates at once, Qc7 would be stalemate
+            let mate_in_one = position("k7/8/1K6/8/8/8/8/2Q5 w - - 0 1");
+            assert_eq!(tables.probe_wdl(&mate_in_one), Some(Wdl::Win));
+            assert_eq!(tables.probe_dtz(&mate_in_one), Some(1));
+            assert_eq!(describe_position(&tables, &mate_in_one), "win (DTZ 1)");
+            let moves = tables.root_moves(&mate_in_one).unwrap();
+            assert!(moves.contains(&mate_in_one.parse_move("c1c8").unwrap()));
+            assert!(!moves.contains(&mate_in_one.parse_move("c1c7").unwrap()));
+
+            assert_eq!(tables.probe_wdl(&position("k7/8/1K6/8/8/8/8/2Q5 b - - 0 1")), Some(Wdl::Loss));
+            // The black king takes the undefended queen, or is stalemated
+            assert_eq!(tables.probe_wdl(&position("8/8/8/8/8/8/2k5/1Q2K3 b - - 0 1")), Some(Wdl::Draw));
+            assert_eq!(tables.probe_dtz(&position("8/8/8/8/8/8/2k5/1Q2K3 b - - 0 1")), Some(0));
+            assert_eq!(tables.probe_wdl(&position("k7/2Q5/1K6/8/8/8/8/8 b - - 0 1")), Some(Wdl::Draw));
+            // Colors swapped
+            assert_eq!(tables.probe_wdl(&position("2q5/8/8/8/8/1k6/8/K7 b - - 0 1")), Some(Wdl::Win));
+        }
+
+        if tables.files.contains_key("KRvK.rtbw") && tables.files.contains_key("KRvK.rtbz") {
+            // Ra8 mates at once
+            let mate_in_one = position("7k/8/6K1/8/8/8/8/R7 w - - 0 1");
+            assert_eq!(tables.probe_dtz(&mate_in_one), Some(1));
+            assert_eq!(tables.probe_wdl(&position("7k/8/6K1/8/8/8/8/R7 b - - 0 1")), Some(Wdl::Loss));
+            // The longest KRvK win takes 16 moves to mate, so no position is further from zeroing
+            let far = position("8/8/8/3k4/8/8/8/R3K3 w - - 0 1");
+            let dtz = tables.probe_dtz(&far).unwrap();
+            assert!(dtz > 1 && dtz <= 32, "DTZ {}", dtz);
+            // Black takes the rook
+            assert_eq!(tables.probe_wdl(&position("8/8/8/8/8/8/6k1/4K2R b - - 0 1")), Some(Wdl::Draw));
+        }
+    }
+}
@@ -0,0 +1,243 @@
+use std::io::{self, BufRead};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::thread::{self, JoinHandle};
+use std::time::Duration;
+
+use crate::book::{BookSelection, BookSettings};
+use crate::endgame::{load_endgames, EndgameTables};
+use crate::position::Position;
+use crate::search::{mate_in, SearchInfo, SearchLimits, Searcher};
+use crate::syzygy::{describe_position, load_syzygy, SyzygyTables};
+use crate::variant::{Variant, ENGINE_VARIANTS};
+use crate::print_board;
+
+// Format a search score for an "info" line, either in centipawns or as moves to mate
+pub fn format_score(score: i32) -> String {
+    match mate_in(score) {
+        Some(moves) => format!("mate {}", moves),
+        None => format!("cp {}", score),
+    }
+}
+
+// Format one completed search iteration from the position as a UCI "info" line
+pub fn format_info(position: &Position, info: &SearchInfo) -> String {
+    let millis = info.elapsed.as_millis() as u64;
+    let pv: Vec<String> = info.pv.iter().map(|mv| position.move_to_string(mv)).collect();
+    format!(
+        "info depth {} score {} nodes {} nps {} time {} pv {}",
+        info.depth,
+        format_score(info.score),
+        info.nodes,
+        info.nodes * 1000 / millis.max(1),
+        millis,
+        pv.join(" "),
+    )
+}
+
+// Set up a position of the variant from the arguments of the "position" command
+pub fn parse_position(args: &[&str], variant: Variant, chess960: bool) -> Result<Position, String> {
+    let moves_index = args.iter().position(|&arg| arg == "moves").unwrap_or(args.len());
+    let mut position = match args.first() {
+        Some(&"startpos") => Position::with_variant(variant),
+        // Under standard chess the FEN itself can still say it is crazyhouse or three-check
+        Some(&"fen") => Position::from_fen(&args[1..moves_index].join(" "), Some(variant).filter(|&variant| variant != Variant::Standard))?,
+        _ => return Err("Expected 'startpos' or 'fen'".to_string()),
+    };
+    // Castling moves are then sent as the king taking its own rook
+    position.chess960 |= chess960;
+
+    for text in args.iter().skip(moves_index + 1) {
+        let mv = position.parse_move(text).ok_or(format!("Illegal move '{}'", text))?;
+        position.make_move(&mv);
+    }
+
+    Ok(position)
+}
+
+// Parse the arguments of the "go" command into search limits
+pub fn parse_go(args: &[&str], move_overhead: Duration) -> (SearchLimits, bool) {
+    let mut limits = SearchLimits { move_overhead, ..SearchLimits::default() };
+    let mut ponder = false;
+    let mut iter = args.iter();
+    while let Some(&arg) = iter.next() {
+        let mut number = || iter.next().and_then(|value| value.parse::<u64>().ok());
+        match arg {
+            "depth" => limits.depth = number().map(|depth| depth as u32),
+            "movetime" => limits.movetime = number().map(Duration::from_millis),
+            "nodes" => limits.nodes = number(),
+            "wtime" => limits.white_time = number().map(Duration::from_millis),
+            "btime" => limits.black_time = number().map(Duration::from_millis),
+            "winc" => limits.white_increment = number().map(Duration::from_millis),
+            "binc" => limits.black_increment = number().map(Duration::from_millis),
+            "movestogo" => limits.moves_to_go = number().map(|moves| moves as u32),
+            "infinite" => limits.infinite = true,
+            "ponder" => ponder = true,
+            _ => {},
+        }
+    }
+    (limits, ponder)
+}
+
+// Start searching on a separate thread so "stop" and "ponderhit" can still be read
+fn start_search(position: Position, limits: SearchLimits, endgames: Arc<EndgameTables>, syzygy: Arc<SyzygyTables>, stop: Arc<AtomicBool>, pondering: Arc<AtomicBool>) -> JoinHandle<()> {
+    thread::spawn(move || {
+        let mut searcher = Searcher::new(&position, &limits, &stop, &pondering);
+        searcher.set_endgames(&endgames);
+        searcher.set_syzygy(&syzygy);
+        let result = searcher.search(&position, limits.depth, |info| println!("{}", format_info(&position, info)));
+
+        // When pondering or searching infinitely the best move may only be sent once told to
+        while (limits.infinite || pondering.load(Ordering::Relaxed)) && !stop.load(Ordering::Relaxed) {
+            thread::sleep(Duration::from_millis(5));
+        }
+
+        match result {
+            Some(info) => match info.pv.get(1) {
+                Some(ponder_move) => println!("bestmove {} ponder {}", position.move_to_string(&info.pv[0]), position.move_to_string(ponder_move)),
+                None => println!("bestmove {}", position.move_to_string(&info.pv[0])),
+            },
+            None => println!("bestmove 0000"),
+        }
+    })
+}
+
+// Wait for a running search to finish and send its best move
+fn wait_for_search(search: &mut Option<JoinHandle<()>>) {
+    if let Some(handle) = search.take() {
+        handle.join().unwrap();
+    }
+}
+
+// Stop a running search and wait for it to send its best move
+fn stop_search(search: &mut Option<JoinHandle<()>>, stop: &AtomicBool) {
+    stop.store(true, Ordering::Relaxed);
+    wait_for_search(search);
+}
+
+// Run the engine under the Universal Chess Interface protocol on stdin and stdout
+pub fn run() {
+    let mut position = Position::new();
+    let stop = Arc::new(AtomicBool::new(false));
+    let pondering = Arc::new(AtomicBool::new(false));
+    let mut search: Option<JoinHandle<()>> = None;
+    let mut move_overhead = Duration::from_millis(10);
+    let mut book = BookSettings::new();
+    let mut endgames = Arc::new(EndgameTables::new());
+    let mut syzygy = Arc::new(SyzygyTables::new());
+    let mut variant = Variant::Standard;
+    let mut chess960 = false;
+
+    for line in io::stdin().lock().lines() {
+        let line = match line {
+            Ok(line) => line,
+            Err(_) => break,
+        };
+        let tokens: Vec<&str> = line.split_whitespace().collect();
+        let (command, args) = match tokens.split_first() {
+            Some((command, args)) => (*command, args),
+            None => continue,
+        };
+
+        match command {
+            "uci" => {
+                println!("id name ConcurrentChess");
+                println!("id author ConcurrentChess contributors");
+                println!("option name Move Overhead type spin default 10 min 0 max 5000");
+                println!("option name Ponder type check default false");
+                println!("option name OwnBook type check default false");
+                println!("option name Book File type string default <empty>");
+                println!("option name Best Book Move type check default false");
+                println!("option name Endgame Path type string default <empty>");
+                println!("option name SyzygyPath type string default <empty>");
+                let variants: Vec<String> = ENGINE_VARIANTS.iter().map(|variant| format!("var {}", variant.name())).collect();
+                println!("option name UCI_Variant type combo default chess {}", variants.join(" "));
+                println!("option name UCI_Chess960 type check default false");
+                println!("uciok");
+            },
+            "isready" => println!("readyok"),
+            "ucinewgame" => {
+                wait_for_search(&mut search);
+                position = Position::with_variant(variant);
+            },
+            "position" => {
+                wait_for_search(&mut search);
+                match parse_position(args, variant, chess960) {
+                    Ok(new_position) => position = new_position,
+                    Err(error) => println!("info string {}", error),
+                }
+            },
+            "go" => {
+                wait_for_search(&mut search);
+                let (limits, ponder) = parse_go(args, move_overhead);
+                // Known opening theory is played straight from the book without thinking
+                if !ponder && !limits.infinite {
+                    if let Some(mv) = book.probe(&position) {
+                        println!("info string book move");
+                        println!("bestmove {}", position.move_to_string(&mv));
+                        continue;
+                    }
+                }
+                stop.store(false, Ordering::Relaxed);
+                pondering.store(ponder, Ordering::Relaxed);
+                search = Some(start_search(position.clone(), limits, endgames.clone(), syzygy.clone(), stop.clone(), pondering.clone()));
+            },
+            "stop" => stop_search(&mut search, &stop),
+            "ponderhit" => pondering.store(false, Ordering::Relaxed),
+            "setoption" => {
+                let name_index = args.iter().position(|&arg| arg == "name").map(|i| i + 1).unwrap_or(0);
+                let value_index = args.iter().position(|&arg| arg == "value").unwrap_or(args.len());
+                let name = args[name_index.min(value_index)..value_index].join(" ");
+                let value = args.get(value_index + 1..).unwrap_or_default().join(" ");
+                match name.to_lowercase().as_str() {
+                    "move overhead" => match value.parse() {
+                        Ok(millis) => move_overhead = Duration::from_millis(millis),
+                        Err(_) => println!("info string Invalid value '{}' for Move Overhead", value),
+                    },
+                    // Pondering is driven by "go ponder", so there is nothing to set up
+                    "ponder" => {},
+                    "ownbook" => book.enabled = value == "true",
+                    "book file" => {
+                        if let Err(error) = book.load(&value) {
+                            println!("info string {}", error);
+                        }
+                    },
+                    "best book move" => {
+                        book.selection = if value == "true" { BookSelection::Best } else { BookSelection::WeightedRandom };
+                    },
+                    "endgame path" => match load_endgames(&value) {
+                        Ok(tables) => endgames = Arc::new(tables),
+                        Err(error) => println!("info string {}", error),
+                    },
+                    "syzygypath" => match load_syzygy(&value) {
+                        Ok(tables) => {
+                            println!("info string Found Syzygy tables for up to {} pieces", tables.max_pieces());
+                            syzygy = Arc::new(tables);
+                        },
+                        Err(error) => println!("info string {}", error),
+                    },
+                    "uci_variant" => match Variant::from_name(&value) {
+                        Some(new_variant) if new_variant.is_engine_variant() => {
+                            variant = new_variant;
+                            position = Position::with_variant(variant);
+                        },
+                        _ => println!("info string Unsupported variant '{}'", value),
+                    },
+                    "uci_chess960" => chess960 = value == "true",
+                    _ => println!("info string Unknown option '{}'", name),
+                }
+            },
+            // Non-standard but widely supported: show the current position
+            "d" => {
+                print_board(&position.board);
+                println!("Fen: {}", position.to_fen());
+            },
+            // Also non-standard: the theoretical result of the current position from the tablebases
+            "tb" => println!("Tablebase: {}", describe_position(&syzygy, &position)),
+            "quit" => break,
+            _ => println!("info string Unknown command '{}'", command),
+        }
+    }
+
+    stop_search(&mut search, &stop);
+}
@@ -0,0 +1,612 @@
+use std::io::{self, Write};
+use std::sync::Arc;
+
+use crate::betza::FairyPiece;
+use crate::book::random_u64;
+use crate::position::{opponent, pocket_to_string, reserve_to_string, Move, Position};
+use crate::rules::find_king;
+use crate::variant_file::{custom_outcome, custom_variants, CustomVariant};
+use crate::{capablanca_position, gardner_position, gothic_position, horde_position, los_alamos_position, print_board, racing_kings_position, starting_position, Board, Color, PieceType};
+
+// The rule sets a position can be played under
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Variant {
+    Standard,
+    Crazyhouse,
+    Bughouse,
+    ThreeCheck,
+    KingOfTheHill,
+    Antichess,
+    Atomic,
+    Horde,
+    RacingKings,
+    Capablanca,
+    Gothic,
+    Gardner,
+    LosAlamos,
+    Duck,
+    Seirawan,
+    // A variant described in a variant file
+    Custom(&'static CustomVariant),
+}
+
+// The variants that can be played from the variant menu
+pub const MENU_VARIANTS: [Variant; 13] = [
+    Variant::Crazyhouse,
+    Variant::ThreeCheck,
+    Variant::KingOfTheHill,
+    Variant::Antichess,
+    Variant::Atomic,
+    Variant::Horde,
+    Variant::RacingKings,
+    Variant::Capablanca,
+    Variant::Gothic,
+    Variant::Gardner,
+    Variant::LosAlamos,
+    Variant::Duck,
+    Variant::Seirawan,
+];
+
+// The variants the engine can play over UCI and xboard
+pub const ENGINE_VARIANTS: [Variant; 13] = [
+    Variant::Standard,
+    Variant::Crazyhouse,
+    Variant::ThreeCheck,
+    Variant::KingOfTheHill,
+    Variant::Antichess,
+    Variant::Atomic,
+    Variant::Horde,
+    Variant::RacingKings,
+    Variant::Capablanca,
+    Variant::Gothic,
+    Variant::Gardner,
+    Variant::LosAlamos,
+    Variant::Seirawan,
+];
+
+// The starting position of S-Chess, with the hawk and elephant of each side waiting in the brackets
+// and the pieces that have not moved yet given by their files after the castling rights
+const SEIRAWAN_FEN: &str = "rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR[HEhe] w KQBCDFGkqbcdfg - 0 1";
+
+// The four centre squares a king has to reach in King of the Hill
+const HILL: [(usize, usize); 4] = [(3, 3), (3, 4), (4, 3), (4, 4)];
+
+// How a game ended, with the reason to show the players
+#[derive(Debug, Clone, PartialEq)]
+pub enum Outcome {
+    Win(Color, String),
+    Draw(String),
+}
+
+impl Variant {
+    // Get the name of the variant as used by UCI_Variant and xboard's "variant" command
+    pub fn name(self) -> &'static str {
+        match self {
+            Variant::Standard => "chess",
+            Variant::Crazyhouse => "crazyhouse",
+            Variant::Bughouse => "bughouse",
+            Variant::ThreeCheck => "3check",
+            Variant::KingOfTheHill => "kingofthehill",
+            Variant::Antichess => "antichess",
+            Variant::Atomic => "atomic",
+            Variant::Horde => "horde",
+            Variant::RacingKings => "racingkings",
+            Variant::Capablanca => "capablanca",
+            Variant::Gothic => "gothic",
+            Variant::Gardner => "gardner",
+            Variant::LosAlamos => "losalamos",
+            Variant::Duck => "duck",
+            Variant::Seirawan => "seirawan",
+            Variant::Custom(custom) => &custom.name,
+        }
+    }
+
+    // Get the name of the variant as shown to players
+    pub fn title(self) -> &'static str {
+        match self {
+            Variant::Standard => "Chess",
+            Variant::Crazyhouse => "Crazyhouse",
+            Variant::Bughouse => "Bughouse",
+            Variant::ThreeCheck => "Three-check",
+            Variant::KingOfTheHill => "King of the Hill",
+            Variant::Antichess => "Antichess",
+            Variant::Atomic => "Atomic",
+            Variant::Horde => "Horde",
+            Variant::RacingKings => "Racing Kings",
+            Variant::Capablanca => "Capablanca chess",
+            Variant::Gothic => "Gothic chess",
+            Variant::Gardner => "Gardner minichess",
+            Variant::LosAlamos => "Los Alamos chess",
+            Variant::Duck => "Duck chess",
+            Variant::Seirawan => "S-Chess",
+            Variant::Custom(custom) => &custom.title,
+        }
+    }
+
+    // Find a variant by name, accepting the names both protocols use for standard chess
+    pub fn from_name(name: &str) -> Option<Variant> {
+        match name.to_lowercase().as_str() {
+            "chess" | "normal" | "standard" => Some(Variant::Standard),
+            "crazyhouse" => Some(Variant::Crazyhouse),
+            "bughouse" => Some(Variant::Bughouse),
+            "3check" | "threecheck" => Some(Variant::ThreeCheck),
+            "kingofthehill" | "koth" => Some(Variant::KingOfTheHill),
+            "antichess" | "losing" => Some(Variant::Antichess),
+            "atomic" => Some(Variant::Atomic),
+            "horde" => Some(Variant::Horde),
+            "racingkings" => Some(Variant::RacingKings),
+            "capablanca" => Some(Variant::Capablanca),
+            "gothic" => Some(Variant::Gothic),
+            "gardner" => Some(Variant::Gardner),
+            "losalamos" => Some(Variant::LosAlamos),
+            "duck" => Some(Variant::Duck),
+            "seirawan" | "schess" => Some(Variant::Seirawan),
+            _ => None,
+        }
+    }
+
+    // Check if pieces can be dropped from a pocket
+    pub fn has_drops(self) -> bool {
+        match self {
+            Variant::Crazyhouse | Variant::Bughouse => true,
+            Variant::Custom(custom) => custom.drops,
+            _ => false,
+        }
+    }
+
+    // Check if captured pieces go to the capturer's own pocket rather than to a partner's
+    pub fn keeps_captures(self) -> bool {
+        match self {
+            Variant::Crazyhouse => true,
+            Variant::Custom(custom) => custom.drops,
+            _ => false,
+        }
+    }
+
+    // Check if the king is royal, so that it can be checked and mated and must not be left in check.
+    // In antichess it is an ordinary piece that can be captured, and in Duck chess capturing it wins.
+    pub fn has_royal_king(self) -> bool {
+        match self {
+            Variant::Antichess | Variant::Duck => false,
+            Variant::Custom(custom) => custom.checkmate,
+            _ => true,
+        }
+    }
+
+    // Check if a player must have exactly one king: in antichess kings are ordinary pieces, and the
+    // White horde has none
+    pub fn needs_king(self, color: Color) -> bool {
+        match self {
+            Variant::Antichess => false,
+            Variant::Horde => color == Color::Black,
+            Variant::Custom(custom) => custom.checkmate,
+            _ => true,
+        }
+    }
+
+    // Check if a player who can capture has to
+    pub fn captures_compulsory(self) -> bool {
+        self == Variant::Antichess
+    }
+
+    // Check if pieces held in reserve can be gated onto the square a piece leaves on the back rank
+    pub fn has_gating(self) -> bool {
+        self == Variant::Seirawan
+    }
+
+    // Check if a player who cannot move wins, rather than being mated or stalemated
+    pub fn stalemate_wins(self) -> bool {
+        matches!(self, Variant::Antichess | Variant::Duck)
+    }
+
+    // Check if the players can castle
+    pub fn has_castling(self) -> bool {
+        match self {
+            Variant::Antichess | Variant::RacingKings | Variant::Gardner | Variant::LosAlamos => false,
+            Variant::Custom(custom) => custom.castling,
+            _ => true,
+        }
+    }
+
+    // Check if pawns can make a double step from their starting rank, which the small boards of
+    // minichess leave no room for
+    pub fn has_double_step(self) -> bool {
+        match self {
+            Variant::Gardner | Variant::LosAlamos => false,
+            Variant::Custom(custom) => custom.double_step,
+            _ => true,
+        }
+    }
+
+    // Get the number of checks that wins the game, in three-check and the variant files counting them
+    pub fn check_limit(self) -> Option<u32> {
+        match self {
+            Variant::ThreeCheck => Some(3),
+            Variant::Custom(custom) => custom.checks,
+            _ => None,
+        }
+    }
+
+    // Get the number of (ranks, files) of the variant's board
+    pub fn dimensions(self) -> (usize, usize) {
+        match self {
+            Variant::Capablanca | Variant::Gothic => (8, 10),
+            Variant::Gardner => (5, 5),
+            Variant::LosAlamos => (6, 6),
+            Variant::Custom(custom) => (custom.ranks, custom.files),
+            _ => (8, 8),
+        }
+    }
+
+    // Get the board the variant starts from
+    pub fn starting_board(self) -> Board {
+        match self {
+            Variant::Horde => horde_position(),
+            Variant::RacingKings => racing_kings_position(),
+            Variant::Capablanca => capablanca_position(),
+            Variant::Gothic => gothic_position(),
+            Variant::Gardner => gardner_position(),
+            Variant::LosAlamos => los_alamos_position(),
+            Variant::Custom(_) | Variant::Seirawan => Position::with_variant(self).board,
+            _ => starting_position(),
+        }
+    }
+
+    // Get the FEN of the starting position for the variants that need more than a board to start from
+    pub fn starting_fen(self) -> Option<&'static str> {
+        match self {
+            Variant::Seirawan => Some(SEIRAWAN_FEN),
+            Variant::Custom(custom) => Some(&custom.start),
+            _ => None,
+        }
+    }
+
+    // Get the fairy pieces the variant's FEN can use: the hawk and elephant of S-Chess, or those
+    // defined in a variant file
+    pub fn fairy_pieces(self) -> Vec<Arc<FairyPiece>> {
+        match self {
+            Variant::Seirawan => seirawan_pieces().to_vec(),
+            Variant::Custom(custom) => custom.pieces.clone(),
+            _ => Vec::new(),
+        }
+    }
+
+    // Get the pieces a pawn can promote to
+    pub fn promotion_pieces(self) -> Vec<PieceType> {
+        match self {
+            Variant::Antichess => vec![PieceType::Queen, PieceType::Rook, PieceType::Bishop, PieceType::Knight, PieceType::King],
+            Variant::Capablanca | Variant::Gothic => {
+                vec![PieceType::Queen, PieceType::Chancellor, PieceType::Archbishop, PieceType::Rook, PieceType::Bishop, PieceType::Knight]
+            },
+            Variant::LosAlamos => vec![PieceType::Queen, PieceType::Rook, PieceType::Knight],
+            Variant::Seirawan => {
+                let [hawk, elephant] = seirawan_pieces();
+                vec![PieceType::Queen, PieceType::Fairy(elephant), PieceType::Fairy(hawk), PieceType::Rook, PieceType::Bishop, PieceType::Knight]
+            },
+            Variant::Custom(custom) => custom.promotion_pieces.clone(),
+            _ => vec![PieceType::Queen, PieceType::Rook, PieceType::Bishop, PieceType::Knight],
+        }
+    }
+
+    // Check if a piece type is used in the variant: the pawn and the king, whatever pawns promote to,
+    // and the duck in Duck chess. A variant file can place any of the pieces in its starting position.
+    pub fn has_piece(self, piece_type: &PieceType) -> bool {
+        if *piece_type == PieceType::Duck {
+            return self == Variant::Duck;
+        }
+        matches!(self, Variant::Custom(_))
+            || matches!(piece_type, PieceType::Pawn | PieceType::King)
+            || self.promotion_pieces().contains(piece_type)
+    }
+
+    // Check if the engine can play the variant
+    pub fn is_engine_variant(self) -> bool {
+        ENGINE_VARIANTS.contains(&self)
+    }
+}
+
+// Get the two pieces of S-Chess: the hawk moves like a bishop or a knight, and the elephant like a
+// rook or a knight
+fn seirawan_pieces() -> [Arc<FairyPiece>; 2] {
+    let piece = |name, symbol, notation| Arc::new(FairyPiece::new(name, symbol, notation).unwrap());
+    [piece("Hawk", 'h', "BN"), piece("Elephant", 'e', "RN")]
+}
+
+// Check if the game has been won by the variant's own goal rather than by checkmate
+pub fn variant_outcome(position: &Position) -> Option<Outcome> {
+    match position.variant {
+        Variant::ThreeCheck => {
+            if position.white_checks >= 3 {
+                Some(Outcome::Win(Color::White, "White gave three checks".to_string()))
+            } else if position.black_checks >= 3 {
+                Some(Outcome::Win(Color::Black, "Black gave three checks".to_string()))
+            } else {
+                None
+            }
+        },
+        Variant::KingOfTheHill => HILL.iter().find_map(|&(x, y)| match position.board[x][y] {
+            Some(ref piece) if piece.piece_type == PieceType::King => {
+                Some(Outcome::Win(piece.color, format!("the {:?} king reached the centre", piece.color)))
+            },
+            _ => None,
+        }),
+        Variant::Atomic => [Color::White, Color::Black].into_iter().find(|&color| find_king(&position.board, color).is_none())
+            .map(|color| Outcome::Win(opponent(color), format!("the {:?} king was blown up", color))),
+        Variant::Horde if !position.board.iter().flatten().flatten().any(|piece| piece.color == Color::White) => {
+            Some(Outcome::Win(Color::Black, "Black captured the whole horde".to_string()))
+        },
+        Variant::RacingKings => racing_kings_outcome(position),
+        Variant::Duck => [Color::White, Color::Black].into_iter().find(|&color| find_king(&position.board, color).is_none())
+            .map(|color| Outcome::Win(opponent(color), format!("the {:?} king was captured", color))),
+        Variant::Custom(custom) => custom_outcome(position, custom),
+        _ => None,
+    }
+}
+
+// Decide a race in Racing Kings. White moves first, so when the White king reaches the eighth rank
+// Black gets one more move, and the game is drawn if the Black king can reach it too.
+fn racing_kings_outcome(position: &Position) -> Option<Outcome> {
+    let in_goal = |color| find_king(&position.board, color).is_some_and(|(x, _)| x == 0);
+    match (in_goal(Color::White), in_goal(Color::Black)) {
+        (false, false) => None,
+        (true, true) => Some(Outcome::Draw("both kings reached the eighth rank".to_string())),
+        (false, true) => Some(Outcome::Win(Color::Black, "the Black king reached the eighth rank".to_string())),
+        (true, false) => {
+            let catching_up = position.side_to_move == Color::Black
+                && position.legal_moves_ignoring_outcome().iter().any(|mv| {
+                    mv.end.0 == 0 && matches!(position.board[mv.start.0][mv.start.1], Some(ref piece) if piece.piece_type == PieceType::King)
+                });
+            if catching_up {
+                None
+            } else {
+                Some(Outcome::Win(Color::White, "the White king reached the eighth rank".to_string()))
+            }
+        },
+    }
+}
+
+// Check if the game is over, by the variant's goal, checkmate or stalemate
+pub fn outcome(position: &Position) -> Option<Outcome> {
+    if let Some(outcome) = variant_outcome(position) {
+        return Some(outcome);
+    }
+    if !position.legal_moves().is_empty() {
+        return None;
+    }
+    // In antichess and Duck chess the player who cannot move, having lost all their pieces or being stalemated, wins
+    if position.variant.stalemate_wins() {
+        let color = position.side_to_move;
+        if position.board.iter().flatten().flatten().any(|piece| piece.color == color) {
+            return Some(Outcome::Win(color, format!("{:?} is stalemated", color)));
+        }
+        return Some(Outcome::Win(color, format!("{:?} lost all their pieces", color)));
+    }
+    if position.is_checkmate() {
+        let winner = opponent(position.side_to_move);
+        Some(Outcome::Win(winner, format!("{:?} mates", winner)))
+    } else {
+        Some(Outcome::Draw("stalemate".to_string()))
+    }
+}
+
+// Show the board together with the pockets when the variant has them
+pub fn print_position(position: &Position) {
+    if position.variant.has_drops() {
+        println!("Black pocket: {}", pocket_to_string(position.pocket(Color::Black), Color::Black));
+    }
+    print_board(&position.board);
+    if position.variant.has_drops() {
+        println!("White pocket: {}", pocket_to_string(position.pocket(Color::White), Color::White));
+    }
+    if position.variant.has_gating() {
+        println!("Reserves: White {}, Black {}", reserve_to_string(position.reserve(Color::White), Color::White), reserve_to_string(position.reserve(Color::Black), Color::Black));
+    }
+    if position.variant.check_limit().is_some() {
+        println!("Checks given: White {}, Black {}", position.white_checks, position.black_checks);
+    }
+}
+
+// Parse a move typed by a player: SAN such as "Nf3" or "N@f3", or the squares as in "g1 f3" or "g1f3".
+// In Duck chess the duck's square comes last, as in "Nf3 d5" or "g1f3,d5".
+pub fn parse_player_move(position: &Position, text: &str) -> Option<Move> {
+    let (text, duck) = match text.trim().rsplit_once([' ', ',']) {
+        Some((text, duck)) if position.variant == Variant::Duck => (text, format!(",{}", duck)),
+        _ => (text, String::new()),
+    };
+    let compact: String = text.split_whitespace().collect::<String>().trim_end_matches(',').to_string();
+    position.parse_san(&(compact.clone() + &duck))
+        .or_else(|| position.parse_move(&(compact.clone() + &duck)))
+        // Pawns reaching the last rank become queens unless another piece is asked for
+        .or_else(|| position.parse_move(&(compact + "q" + &duck)))
+}
+
+// Play a hot-seat game from the position with both players at one terminal
+pub fn play(mut position: Position) {
+    loop {
+        print_position(&position);
+        match outcome(&position) {
+            Some(Outcome::Win(color, reason)) => {
+                println!("{:?} wins: {}.", color, reason);
+                return;
+            },
+            Some(Outcome::Draw(reason)) => {
+                println!("The game is a draw: {}.", reason);
+                return;
+            },
+            None => {},
+        }
+
+        if position.variant == Variant::Duck {
+            print!("{:?} to move, enter move and duck square (e.g. e4 d5): ", position.side_to_move);
+        } else {
+            print!("{:?} to move, enter move: ", position.side_to_move);
+        }
+        io::stdout().flush().unwrap();
+        let mut input = String::new();
+        if io::stdin().read_line(&mut input).unwrap() == 0 {
+            return;
+        }
+        match parse_player_move(&position, input.trim()) {
+            Some(mv) => position.make_move(&mv),
+            None => println!("Invalid move"),
+        }
+    }
+}
+
+// Let the players pick one of the variants, including those described in the variant files, and play it
+pub fn variant_menu() {
+    let (custom_variants, errors) = custom_variants();
+    for error in errors {
+        println!("Skipping {}", error);
+    }
+
+    println!("Choose a variant:");
+    for (i, variant) in MENU_VARIANTS.iter().enumerate() {
+        println!("{}. {}", i + 1, variant.title());
+    }
+    println!("{}. Chess960", MENU_VARIANTS.len() + 1);
+    println!("{}. Fairy pieces of your own", MENU_VARIANTS.len() + 2);
+    for (i, variant) in custom_variants.iter().enumerate() {
+        println!("{}. {}", MENU_VARIANTS.len() + 3 + i, variant.title());
+    }
+    print!("Enter your choice: ");
+    io::stdout().flush().unwrap();
+    let mut input = String::new();
+    io::stdin().read_line(&mut input).unwrap();
+    let choice = input.trim().parse::<usize>().unwrap_or(0);
+    match MENU_VARIANTS.get(choice.wrapping_sub(1)) {
+        Some(&variant) => play(Position::with_variant(variant)),
+        None if choice == MENU_VARIANTS.len() + 1 => play(chess960_menu()),
+        None if choice == MENU_VARIANTS.len() + 2 => {
+            if let Some(position) = fairy_menu() {
+                play(position);
+            }
+        },
+        None => match custom_variants.get(choice.wrapping_sub(MENU_VARIANTS.len() + 3)) {
+            Some(&variant) => play(Position::with_variant(variant)),
+            None => println!("Invalid choice"),
+        },
+    }
+}
+
+// Ask for the number of a Chess960 starting position, picking one at random if none is given
+fn chess960_menu() -> Position {
+    print!("Starting position number from 0 to 959 (Enter for a random one): ");
+    io::stdout().flush().unwrap();
+    let mut input = String::new();
+    io::stdin().read_line(&mut input).unwrap();
+    let index = match input.trim().parse::<usize>() {
+        Ok(index) if index < 960 => index,
+        _ => (random_u64() % 960) as usize,
+    };
+    println!("Chess960 position number {}", index);
+    Position::chess960(index)
+}
+
+// Ask for pieces defined by their letter and Betza notation, and a starting position that uses them
+fn fairy_menu() -> Option<Position> {
+    println!("Define a piece on each line by its letter, its Betza notation and optionally its name,");
+    println!("e.g. \"m C Camel\" or \"h NB Hawk\". Finish with an empty line.");
+    let mut pieces = Vec::new();
+    loop {
+        print!("Piece: ");
+        io::stdout().flush().unwrap();
+        let mut input = String::new();
+        if io::stdin().read_line(&mut input).unwrap() == 0 {
+            return None;
+        }
+        let words: Vec<&str> = input.split_whitespace().collect();
+        let (symbol, notation) = match words[..] {
+            [] => break,
+            [symbol, notation, ..] if symbol.chars().count() == 1 => (symbol.chars().next().unwrap(), notation),
+            _ => {
+                println!("Give a single letter and the Betza notation");
+                continue;
+            },
+        };
+        let name = if words.len() > 2 { words[2..].join(" ") } else { notation.to_string() };
+        match FairyPiece::new(&name, symbol, notation) {
+            Ok(piece) => pieces.push(Arc::new(piece)),
+            Err(error) => println!("{}", error),
+        }
+    }
+
+    print!("FEN of the starting position: ");
+    io::stdout().flush().unwrap();
+    let mut input = String::new();
+    io::stdin().read_line(&mut input).unwrap();
+    match Position::from_fen_with_pieces(input.trim(), None, &pieces) {
+        Ok(position) => Some(position),
+        Err(error) => {
+            println!("{}", error);
+            None
+        },
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Play moves written as at the terminal
+    fn play_moves(position: &mut Position, moves: &[&str]) {
+        for text in moves {
+            let mv = parse_player_move(position, text).unwrap_or_else(|| panic!("Illegal move '{}' in {}", text, position.to_fen()));
+            position.make_move(&mv);
+        }
+    }
+
+    #[test]
+    fn three_checks_win() {
+        let fen = "rnbqkbnr/ppp2ppp/8/3pp3/4P3/8/PPPP1PPP/RNBQKBNR w KQkq - 1+3 0 3";
+        let mut position = Position::from_fen(fen, None).unwrap();
+        assert_eq!(position.variant, Variant::ThreeCheck);
+        assert_eq!(position.to_fen(), fen);
+        // The checks given may also follow the move counters
+        let given = Position::from_fen("rnbqkbnr/ppp2ppp/8/3pp3/4P3/8/PPPP1PPP/RNBQKBNR w KQkq - 0 3 +2+0", None).unwrap();
+        assert_eq!(given.to_fen(), fen);
+
+        assert_eq!(variant_outcome(&position), None);
+        play_moves(&mut position, &["Bb5"]);
+        assert_eq!(position.to_fen(), "rnbqkbnr/ppp2ppp/8/1B1pp3/4P3/8/PPPP1PPP/RNBQK1NR b KQkq - 0+3 1 3");
+        assert_eq!(outcome(&position), Some(Outcome::Win(Color::White, "White gave three checks".to_string())));
+        assert!(position.legal_moves().is_empty());
+    }
+
+    #[test]
+    fn king_of_the_hill_wins_in_the_centre() {
+        let mut position = Position::from_fen("4k3/8/8/8/8/3K4/8/8 w - - 0 1", Some(Variant::KingOfTheHill)).unwrap();
+        assert_eq!(variant_outcome(&position), None);
+        play_moves(&mut position, &["Ke4"]);
+        assert_eq!(outcome(&position), Some(Outcome::Win(Color::White, "the White king reached the centre".to_string())));
+    }
+
+    #[test]
+    fn antichess_is_won_by_losing_everything() {
+        let mut position = Position::from_fen("8/8/8/8/8/8/3k4/4K3 w - - 0 1", Some(Variant::Antichess)).unwrap();
+        assert_eq!(outcome(&position), None);
+        play_moves(&mut position, &["Kxd2"]);
+        assert_eq!(outcome(&position), Some(Outcome::Win(Color::Black, "Black lost all their pieces".to_string())));
+
+        // A player who cannot move wins too
+        let position = Position::from_fen("8/8/8/8/8/p7/P7/8 b - - 0 1", Some(Variant::Antichess)).unwrap();
+        assert_eq!(outcome(&position), Some(Outcome::Win(Color::Black, "Black is stalemated".to_string())));
+    }
+
+    #[test]
+    fn atomic_captures_blow_up_the_king() {
+        let mut position = Position::from_fen("4k3/3p4/8/8/8/8/8/3QK3 w - - 0 1", Some(Variant::Atomic)).unwrap();
+        play_moves(&mut position, &["Qxd7"]);
+        assert_eq!(position.to_fen(), "8/8/8/8/8/8/8/4K3 b - - 0 1");
+        assert_eq!(outcome(&position), Some(Outcome::Win(Color::White, "the Black king was blown up".to_string())));
+    }
+
+    #[test]
+    fn duck_chess_is_won_by_taking_the_king() {
+        let mut position = Position::from_fen("4k3/8/8/8/8/8/8/4Q1K1 w - - 0 1", Some(Variant::Duck)).unwrap();
+        assert_eq!(outcome(&position), None);
+        play_moves(&mut position, &["Qxe8 d4"]);
+        assert_eq!(position.to_fen(), "4Q3/8/8/8/3*4/8/8/6K1 b - - 0 1");
+        assert_eq!(outcome(&position), Some(Outcome::Win(Color::White, "the Black king was captured".to_string())));
+    }
+}
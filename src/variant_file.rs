@@ -0,0 +1,374 @@
+use std::fmt;
+use std::fs;
+use std::path::Path;
+use std::sync::{Arc, OnceLock};
+
+use crate::betza::FairyPiece;
+use crate::position::{opponent, parse_square, piece_from_char, Position};
+use crate::variant::{Outcome, Variant};
+use crate::{Color, PieceType};
+
+// Whole variants can be described in files like Fairy-Stockfish's variants.ini, so they can be
+// played without changing the code. A file holds one or more sections, each starting with the
+// variant's name in brackets and followed by "key = value" lines. Lines starting with # or ; are
+// comments. For example:
+//
+//     [hawkchess]
+//     title = Hawk chess
+//     piece = h NB Hawk
+//     start = rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBHKBNR w KQkq - 0 1
+//     promotion = qhrbn
+//
+// The keys are:
+//
+// - title: the name shown to players, the section's name if left out
+// - ranks and files: the size of the board, 8x8 if left out, from 4 to 9 ranks and 4 to 10 files
+// - piece: a fairy piece as its letter, its Betza notation and optionally its name, one per line
+// - start: the starting position in FEN, which is required
+// - promotion: the letters of the pieces a pawn can promote to, qrbn if left out
+// - castling, double_step and drops: true or false, whether the players can castle, whether pawns
+//   can make a double step, and whether captured pieces go to the capturer's pocket to be dropped
+//   again (only pawns, knights, bishops, rooks and queens are kept). Castling and double steps are
+//   allowed and drops are not unless the file says otherwise.
+// - checkmate: true or false, whether the king is royal. Without it the king can be captured like
+//   any other piece, and the game has to be won another way.
+// - extinction: the letters of pieces a player loses by having none left of, e.g. "k" to win by
+//   capturing the king
+// - flag_piece, flag_white and flag_black: a player wins by bringing the flag piece, the king if
+//   left out, to one of their flag squares. The squares are listed as in "d4 e4 d5 e5", or as whole
+//   ranks as in "*8".
+// - checks: the number of checks that wins the game, as in three-check
+
+// The directory the variant menu looks for variant files in
+pub const VARIANT_DIRECTORY: &str = "variants";
+
+// A variant read from a file
+#[derive(Clone, PartialEq)]
+pub struct CustomVariant {
+    pub name: String,
+    pub title: String,
+    pub ranks: usize,
+    pub files: usize,
+    pub pieces: Vec<Arc<FairyPiece>>,
+    pub start: String,
+    pub promotion_pieces: Vec<PieceType>,
+    pub castling: bool,
+    pub double_step: bool,
+    pub drops: bool,
+    pub checkmate: bool,
+    pub extinction: Vec<PieceType>,
+    pub flag_piece: PieceType,
+    pub white_flag: Vec<(usize, usize)>,
+    pub black_flag: Vec<(usize, usize)>,
+    pub checks: Option<u32>,
+}
+
+// Variants are printed by name, like fairy pieces
+impl fmt::Debug for CustomVariant {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.name)
+    }
+}
+
+// Find the piece type a letter stands for, among the built-in pieces and the variant's fairy pieces
+fn piece_type_from_letter(c: char, pieces: &[Arc<FairyPiece>]) -> Option<PieceType> {
+    piece_from_char(c).map(|piece| piece.piece_type).or_else(|| {
+        pieces.iter().find(|fairy| fairy.symbol == c.to_ascii_lowercase()).map(|fairy| PieceType::Fairy(fairy.clone()))
+    })
+}
+
+// Parse a list of piece letters such as "qrbn"
+fn parse_letters(value: &str, pieces: &[Arc<FairyPiece>]) -> Result<Vec<PieceType>, String> {
+    value.chars().filter(|c| !c.is_whitespace())
+        .map(|c| piece_type_from_letter(c, pieces).ok_or(format!("Unknown piece '{}'", c)))
+        .collect()
+}
+
+// Parse a list of squares such as "d4 e4", where "*8" stands for every square of the eighth rank
+fn parse_squares(value: &str, ranks: usize, files: usize) -> Result<Vec<(usize, usize)>, String> {
+    let mut squares = Vec::new();
+    for word in value.split_whitespace() {
+        match word.strip_prefix('*') {
+            Some(rank) => {
+                let x = match rank.parse::<usize>() {
+                    Ok(rank @ 1..) if rank <= ranks => ranks - rank,
+                    _ => return Err(format!("Invalid rank '{}'", word)),
+                };
+                squares.extend((0..files).map(|y| (x, y)));
+            },
+            None => squares.push(parse_square(word, ranks).filter(|&(_, y)| y < files).ok_or(format!("Invalid square '{}'", word))?),
+        }
+    }
+    Ok(squares)
+}
+
+// Parse a value that is either true or false
+fn parse_bool(value: &str) -> Result<bool, String> {
+    match value {
+        "true" => Ok(true),
+        "false" => Ok(false),
+        _ => Err(format!("Expected true or false, not '{}'", value)),
+    }
+}
+
+// Build a variant from the keys and values of its section
+fn parse_section(name: &str, entries: &[(&str, &str)]) -> Result<CustomVariant, String> {
+    let get = |key: &str| entries.iter().rev().find(|(k, _)| *k == key).map(|&(_, value)| value);
+    let get_bool = |key: &str, default: bool| get(key).map_or(Ok(default), parse_bool);
+
+    // A smaller board leaves no room for the pawns' first steps or for castling
+    let size = |key: &str, max: usize| match get(key).map(|value| value.parse::<usize>()) {
+        None => Ok(8),
+        Some(Ok(size @ 4..)) if size <= max => Ok(size),
+        Some(_) => Err(format!("The {} must be a number from 4 to {}", key, max)),
+    };
+    let ranks = size("ranks", 9)?;
+    let files = size("files", 10)?;
+
+    let mut pieces: Vec<Arc<FairyPiece>> = Vec::new();
+    for &(_, value) in entries.iter().filter(|(key, _)| *key == "piece") {
+        let words: Vec<&str> = value.split_whitespace().collect();
+        let (symbol, notation) = match words[..] {
+            [symbol, notation, ..] if symbol.chars().count() == 1 => (symbol.chars().next().unwrap(), notation),
+            _ => return Err(format!("A piece needs a letter and its Betza notation: '{}'", value)),
+        };
+        let name = if words.len() > 2 { words[2..].join(" ") } else { notation.to_string() };
+        let piece = FairyPiece::new(&name, symbol, notation)?;
+        if pieces.iter().any(|other| other.symbol == piece.symbol) {
+            return Err(format!("Two pieces use the letter '{}'", symbol));
+        }
+        pieces.push(Arc::new(piece));
+    }
+
+    let flag_white = get("flag_white").map(|value| parse_squares(value, ranks, files)).transpose()?;
+    let flag_black = get("flag_black").map(|value| parse_squares(value, ranks, files)).transpose()?;
+    let flag_piece = match get("flag_piece") {
+        Some(value) => match parse_letters(value, &pieces)?[..] {
+            [ref piece_type] => piece_type.clone(),
+            _ => return Err(format!("The flag piece must be a single letter, not '{}'", value)),
+        },
+        None => PieceType::King,
+    };
+
+    Ok(CustomVariant {
+        name: name.to_string(),
+        title: get("title").unwrap_or(name).to_string(),
+        ranks,
+        files,
+        start: get("start").ok_or("The starting position is missing")?.to_string(),
+        promotion_pieces: parse_letters(get("promotion").unwrap_or("qrbn"), &pieces)?,
+        castling: get_bool("castling", true)?,
+        double_step: get_bool("double_step", true)?,
+        drops: get_bool("drops", false)?,
+        checkmate: get_bool("checkmate", true)?,
+        extinction: parse_letters(get("extinction").unwrap_or(""), &pieces)?,
+        flag_piece,
+        white_flag: flag_white.unwrap_or_default(),
+        black_flag: flag_black.unwrap_or_default(),
+        checks: get("checks").map(|value| value.parse::<u32>().map_err(|_| format!("Invalid number of checks '{}'", value))).transpose()?,
+        pieces,
+    })
+}
+
+// Parse the variants described in the text of a variant file
+pub fn parse_variants(text: &str) -> Result<Vec<CustomVariant>, String> {
+    const KEYS: [&str; 15] = [
+        "title", "ranks", "files", "piece", "start", "promotion", "castling", "double_step", "drops",
+        "checkmate", "extinction", "flag_piece", "flag_white", "flag_black", "checks",
+    ];
+
+    // Collect the entries of each section first, since some keys depend on others
+    let mut sections: Vec<(&str, Vec<(&str, &str)>)> = Vec::new();
+    for (i, line) in text.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') || line.starts_with(';') {
+            continue;
+        }
+        if let Some(name) = line.strip_prefix('[').and_then(|line| line.strip_suffix(']')) {
+            let name = name.trim();
+            if sections.iter().any(|(other, _)| *other == name) {
+                return Err(format!("Line {}: the variant [{}] is described twice", i + 1, name));
+            }
+            sections.push((name, Vec::new()));
+            continue;
+        }
+        let (key, value) = line.split_once('=').ok_or(format!("Line {}: expected \"key = value\"", i + 1))?;
+        let key = key.trim();
+        if !KEYS.contains(&key) {
+            return Err(format!("Line {}: unknown key '{}'", i + 1, key));
+        }
+        match sections.last_mut() {
+            Some((_, entries)) => entries.push((key, value.trim())),
+            None => return Err(format!("Line {}: the variant's [name] has to come first", i + 1)),
+        }
+    }
+
+    sections.iter()
+        .map(|(name, entries)| parse_section(name, entries).map_err(|error| format!("[{}] {}", name, error)))
+        .collect()
+}
+
+// Get the variants described in the variant directory, with the errors of the files that could not
+// be used. The files are only read the first time, since the variants are kept for good.
+pub fn custom_variants() -> &'static (Vec<Variant>, Vec<String>) {
+    static LOADED: OnceLock<(Vec<Variant>, Vec<String>)> = OnceLock::new();
+    LOADED.get_or_init(|| load_variants(Path::new(VARIANT_DIRECTORY)))
+}
+
+// Load the variants described in the .ini files of a directory, in the order of their file names.
+// Each variant is kept for the rest of the program, so that Variant can refer to it and stay Copy.
+// A file that cannot be used is reported among the errors, without hiding the variants of the others.
+fn load_variants(directory: &Path) -> (Vec<Variant>, Vec<String>) {
+    let mut paths: Vec<_> = match fs::read_dir(directory) {
+        Ok(entries) => entries.flatten().map(|entry| entry.path()).filter(|path| path.extension().is_some_and(|ext| ext == "ini")).collect(),
+        Err(_) => return (Vec::new(), Vec::new()),
+    };
+    paths.sort();
+
+    let mut variants = Vec::new();
+    let mut errors = Vec::new();
+    for path in paths {
+        let parsed = fs::read_to_string(&path).map_err(|error| error.to_string()).and_then(|text| parse_variants(&text));
+        let customs = match parsed {
+            Ok(customs) => customs,
+            Err(error) => {
+                errors.push(format!("{}: {}", path.display(), error));
+                continue;
+            },
+        };
+        for custom in customs {
+            // Variants are found by name, so two of them cannot share one
+            if Variant::from_name(&custom.name).is_some() || variants.iter().any(|variant: &Variant| variant.name() == custom.name) {
+                errors.push(format!("{}: [{}] another variant already has this name", path.display(), custom.name));
+                continue;
+            }
+            let custom: &'static CustomVariant = Box::leak(Box::new(custom));
+            let variant = Variant::Custom(custom);
+            // The starting position can only be checked against the variant it belongs to
+            match Position::from_fen(&custom.start, Some(variant)) {
+                Ok(_) => variants.push(variant),
+                Err(error) => errors.push(format!("{}: [{}] {}", path.display(), variant.name(), error)),
+            }
+        }
+    }
+    (variants, errors)
+}
+
+// Check if the game has been won by one of the goals of a variant file
+pub fn custom_outcome(position: &Position, custom: &CustomVariant) -> Option<Outcome> {
+    // A player who has lost every piece of a type that must not die out loses, the side to move first
+    for color in [position.side_to_move, opponent(position.side_to_move)] {
+        let extinct = custom.extinction.iter().find(|&piece_type| {
+            !position.board.iter().flatten().flatten().any(|piece| piece.color == color && piece.piece_type == *piece_type)
+        });
+        if let Some(piece_type) = extinct {
+            return Some(Outcome::Win(opponent(color), format!("{:?} has no {:?} left", color, piece_type)));
+        }
+    }
+
+    if let Some(limit) = custom.checks {
+        for (color, given) in [(Color::White, position.white_checks), (Color::Black, position.black_checks)] {
+            if given >= limit {
+                return Some(Outcome::Win(color, format!("{:?} gave {} checks", color, limit)));
+            }
+        }
+    }
+
+    for (color, flag) in [(Color::White, &custom.white_flag), (Color::Black, &custom.black_flag)] {
+        let reached = flag.iter().any(|&(x, y)| {
+            matches!(position.board[x][y], Some(ref piece) if piece.color == color && piece.piece_type == custom.flag_piece)
+        });
+        if reached {
+            return Some(Outcome::Win(color, format!("the {:?} {:?} reached its goal", color, custom.flag_piece)));
+        }
+    }
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const HAWK_CHESS: &str = "
+        # A queen traded for a hawk
+        [hawkchess]
+        title = Hawk chess
+        piece = h NB Hawk
+        start = rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBHKBNR w KQkq - 0 1
+        promotion = qhrbn
+    ";
+
+    // Parse a single variant, or get the error of the file
+    fn parse_one(text: &str) -> Result<CustomVariant, String> {
+        parse_variants(text).map(|mut variants| variants.remove(0))
+    }
+
+    #[test]
+    fn variants_are_parsed() {
+        let custom = parse_one(HAWK_CHESS).unwrap();
+        assert_eq!((custom.name.as_str(), custom.title.as_str()), ("hawkchess", "Hawk chess"));
+        assert_eq!((custom.ranks, custom.files), (8, 8));
+        assert_eq!(custom.pieces[0].name, "Hawk");
+        assert_eq!(custom.promotion_pieces.len(), 5);
+        assert!(custom.castling && custom.double_step && custom.checkmate && !custom.drops);
+
+        // The hawk on d1 can already leap to c3 and e3, besides the usual 20 moves
+        let variant = Variant::Custom(Box::leak(Box::new(custom)));
+        let position = Position::with_variant(variant);
+        assert_eq!(position.legal_moves().len(), 22);
+        assert_eq!(position.to_fen(), "rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBHKBNR w KQkq - 0 1");
+    }
+
+    #[test]
+    fn flags_win_the_game() {
+        let text = "[race]\nstart = 8/8/8/8/8/8/krbnNBRK/qrbnNBRQ w - - 0 1\ncastling = false\nflag_white = *8\nflag_black = *8";
+        let custom = parse_one(text).unwrap();
+        assert_eq!(custom.white_flag.len(), 8);
+        assert_eq!(custom.white_flag[0], (0, 0));
+        let custom: &'static CustomVariant = Box::leak(Box::new(custom));
+        let position = Position::from_fen("7K/8/8/8/8/8/krbnNBR1/qrbnNBRQ b - - 0 1", Some(Variant::Custom(custom))).unwrap();
+        assert_eq!(custom_outcome(&position, custom), Some(Outcome::Win(Color::White, "the White King reached its goal".to_string())));
+    }
+
+    #[test]
+    fn invalid_files_are_reported() {
+        let start = "start = rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KQkq - 0 1";
+        let error = |text: &str| parse_variants(text).unwrap_err();
+        assert_eq!(error(start), "Line 1: the variant's [name] has to come first");
+        assert_eq!(error("[a]\ncolour = white"), "Line 2: unknown key 'colour'");
+        assert_eq!(error("[a]\nranks 8"), "Line 2: expected \"key = value\"");
+        assert_eq!(error(&format!("[a]\n{}\n[b]\n{}\n[a]\n{}", start, start, start)), "Line 5: the variant [a] is described twice");
+        assert_eq!(error("[a]\nranks = 8"), "[a] The starting position is missing");
+        assert_eq!(error(&format!("[a]\n{}\nranks = 3", start)), "[a] The ranks must be a number from 4 to 9");
+        assert_eq!(error(&format!("[a]\n{}\nfiles = 11", start)), "[a] The files must be a number from 4 to 10");
+        assert_eq!(error(&format!("[a]\n{}\nfiles = ten", start)), "[a] The files must be a number from 4 to 10");
+        assert_eq!(error(&format!("[a]\n{}\ncastling = yes", start)), "[a] Expected true or false, not 'yes'");
+        assert_eq!(error(&format!("[a]\n{}\npiece = h", start)), "[a] A piece needs a letter and its Betza notation: 'h'");
+        assert_eq!(error(&format!("[a]\n{}\npiece = h NB\npiece = h RN", start)), "[a] Two pieces use the letter 'h'");
+        assert_eq!(error(&format!("[a]\n{}\npiece = h XB", start)), "[a] Unknown atom 'X' in Betza notation 'XB'");
+        assert_eq!(error(&format!("[a]\n{}\npromotion = qx", start)), "[a] Unknown piece 'x'");
+        assert_eq!(error(&format!("[a]\n{}\nflag_white = i9", start)), "[a] Invalid square 'i9'");
+        assert_eq!(error(&format!("[a]\n{}\nflag_white = *9", start)), "[a] Invalid rank '*9'");
+        assert_eq!(error(&format!("[a]\n{}\nflag_piece = qr", start)), "[a] The flag piece must be a single letter, not 'qr'");
+    }
+
+    #[test]
+    fn loading_skips_unusable_variants() {
+        let directory = std::env::temp_dir().join(format!("variants-{}", std::process::id()));
+        fs::create_dir_all(&directory).unwrap();
+        fs::write(directory.join("a.ini"), HAWK_CHESS).unwrap();
+        fs::write(directory.join("b.ini"), "[atomic]\nstart = 4k3/8/8/8/8/8/8/4K3 w - - 0 1\n[hawkchess]\nstart = 4k3/8/8/8/8/8/8/4K3 w - - 0 1").unwrap();
+        fs::write(directory.join("c.ini"), "[broken]\nstart = 4k3/8/8 w - - 0 1").unwrap();
+        fs::write(directory.join("d.ini"), "[unclosed").unwrap();
+        fs::write(directory.join("notes.txt"), "not a variant").unwrap();
+        let (variants, errors) = load_variants(&directory);
+        fs::remove_dir_all(&directory).unwrap();
+
+        assert_eq!(variants.iter().map(|variant| variant.name()).collect::<Vec<_>>(), ["hawkchess"]);
+        assert_eq!(errors.len(), 4, "{:#?}", errors);
+        assert!(errors[0].ends_with("b.ini: [atomic] another variant already has this name"));
+        assert!(errors[1].ends_with("b.ini: [hawkchess] another variant already has this name"));
+        assert!(errors[2].contains("c.ini: [broken]"));
+        assert!(errors[3].ends_with("d.ini: Line 1: expected \"key = value\""));
+    }
+}
@@ -0,0 +1,544 @@
+use std::io::{self, BufRead};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::thread::{self, JoinHandle};
+use std::time::Duration;
+
+use crate::book::{BookSelection, BookSettings};
+use crate::endgame::{load_endgames, EndgameTables};
+use crate::position::{Move, Position};
+use crate::search::{mate_in, SearchInfo, SearchLimits, Searcher, MATE};
+use crate::syzygy::{load_syzygy, SyzygyTables};
+use crate::variant::{outcome, Outcome, Variant, ENGINE_VARIANTS};
+use crate::Color;
+
+// The time control set with "level", "st" and "sd"
+#[derive(Debug, Clone, Default)]
+struct TimeControl {
+    moves_per_session: u32,
+    increment: Duration,
+    time_per_move: Option<Duration>,
+    max_depth: Option<u32>,
+}
+
+// A search running on its own thread so "?" and other commands can still be read
+struct RunningSearch {
+    handle: JoinHandle<Option<Move>>,
+    stop: Arc<AtomicBool>,
+    // Set by whichever side gets there first: the search when it sends its move, or the
+    // interface when the move is no longer wanted
+    claimed: Arc<AtomicBool>,
+}
+
+// Format a search score the way CECP expects, with mates as 100000 + N
+pub fn format_score(score: i32) -> i32 {
+    match mate_in(score) {
+        Some(moves) if moves > 0 => MATE + moves,
+        Some(moves) => -MATE + moves,
+        None => score,
+    }
+}
+
+// Format one completed search iteration from the position as CECP thinking output: ply, score,
+// time in centiseconds, nodes and pv
+pub fn format_thinking(position: &Position, info: &SearchInfo) -> String {
+    let pv: Vec<String> = info.pv.iter().map(|mv| position.move_to_string(mv)).collect();
+    format!(
+        "{} {} {} {} {}",
+        info.depth,
+        format_score(info.score),
+        info.elapsed.as_millis() / 10,
+        info.nodes,
+        pv.join(" "),
+    )
+}
+
+// Format a move for the GUI, which wants Chess960 castling written as O-O or O-O-O
+fn format_move(position: &Position, mv: &Move) -> String {
+    if position.chess960 && position.is_castling(mv) {
+        return if mv.end.1 > mv.start.1 { "O-O".to_string() } else { "O-O-O".to_string() };
+    }
+    position.move_to_string(mv)
+}
+
+// Parse a "level" base time, given either as minutes or as minutes:seconds
+fn parse_base_time(text: &str) -> Option<Duration> {
+    match text.split_once(':') {
+        Some((minutes, seconds)) => Some(Duration::from_secs(minutes.parse::<u64>().ok()? * 60 + seconds.parse::<u64>().ok()?)),
+        None => Some(Duration::from_secs(text.parse::<u64>().ok()? * 60)),
+    }
+}
+
+// Describe the result of the game if the side to move has no legal moves left
+fn game_result(position: &Position) -> Option<String> {
+    match outcome(position) {
+        Some(Outcome::Win(Color::White, reason)) => Some(format!("1-0 {{{}}}", capitalize(&reason))),
+        Some(Outcome::Win(Color::Black, reason)) => Some(format!("0-1 {{{}}}", capitalize(&reason))),
+        Some(Outcome::Draw(reason)) => Some(format!("1/2-1/2 {{{}}}", capitalize(&reason))),
+        None if position.halfmove_clock >= 100 => Some("1/2-1/2 {50 move rule}".to_string()),
+        None => None,
+    }
+}
+
+// Start a result comment with a capital letter, as in "{Stalemate}"
+fn capitalize(text: &str) -> String {
+    let mut chars = text.chars();
+    match chars.next() {
+        Some(first) => first.to_uppercase().chain(chars).collect(),
+        None => String::new(),
+    }
+}
+
+// The state of an xboard session
+struct XBoard {
+    position: Position,
+    variant: Variant,
+    chess960: bool,
+    history: Vec<Position>,
+    force: bool,
+    post: bool,
+    time_control: TimeControl,
+    engine_time: Option<Duration>,
+    opponent_time: Option<Duration>,
+    search: Option<RunningSearch>,
+    book: BookSettings,
+    endgames: Arc<EndgameTables>,
+    syzygy: Arc<SyzygyTables>,
+}
+
+impl XBoard {
+    fn new() -> XBoard {
+        XBoard {
+            position: Position::new(),
+            variant: Variant::Standard,
+            chess960: false,
+            history: Vec::new(),
+            force: false,
+            post: false,
+            time_control: TimeControl::default(),
+            engine_time: None,
+            opponent_time: None,
+            search: None,
+            book: BookSettings::new(),
+            endgames: Arc::new(EndgameTables::new()),
+            syzygy: Arc::new(SyzygyTables::new()),
+        }
+    }
+
+    // Play a move and remember the previous position for "undo"
+    fn play(&mut self, mv: &Move) {
+        self.history.push(self.position.clone());
+        self.position.make_move(mv);
+    }
+
+    // Build the search limits for the engine's move from the clock and time control
+    fn limits(&self) -> SearchLimits {
+        let mut limits = SearchLimits {
+            depth: self.time_control.max_depth,
+            movetime: self.time_control.time_per_move,
+            move_overhead: Duration::from_millis(50),
+            ..SearchLimits::default()
+        };
+        if limits.movetime.is_none() {
+            let moves_per_session = self.time_control.moves_per_session;
+            if moves_per_session > 0 {
+                // Some FENs number the first move 0, which counts the same as 1
+                let moves_made = self.position.fullmove_number.saturating_sub(1);
+                limits.moves_to_go = Some(moves_per_session - moves_made % moves_per_session);
+            }
+            let (engine, opponent) = (self.engine_time, self.opponent_time);
+            let increment = Some(self.time_control.increment);
+            match self.position.side_to_move {
+                Color::White => {
+                    (limits.white_time, limits.black_time) = (engine, opponent);
+                    limits.white_increment = increment;
+                },
+                Color::Black => {
+                    (limits.black_time, limits.white_time) = (engine, opponent);
+                    limits.black_increment = increment;
+                },
+            }
+            // Without any clock information, think for a fixed time rather than forever
+            if engine.is_none() && limits.depth.is_none() {
+                limits.movetime = Some(Duration::from_secs(5));
+            }
+        }
+        limits
+    }
+
+    // Start thinking about the move for the side to move
+    fn start_thinking(&mut self) {
+        if let Some(result) = game_result(&self.position) {
+            println!("{}", result);
+            return;
+        }
+
+        // Known opening theory is played straight from the book without thinking
+        if let Some(mv) = self.book.probe(&self.position) {
+            println!("move {}", format_move(&self.position, &mv));
+            self.play(&mv);
+            return;
+        }
+
+        let position = self.position.clone();
+        let limits = self.limits();
+        let post = self.post;
+        let endgames = self.endgames.clone();
+        let syzygy = self.syzygy.clone();
+        let stop = Arc::new(AtomicBool::new(false));
+        let claimed = Arc::new(AtomicBool::new(false));
+        let (thread_stop, thread_claimed) = (stop.clone(), claimed.clone());
+
+        let handle = thread::spawn(move || {
+            let pondering = AtomicBool::new(false);
+            let mut searcher = Searcher::new(&position, &limits, &thread_stop, &pondering);
+            searcher.set_endgames(&endgames);
+            searcher.set_syzygy(&syzygy);
+            let result = searcher.search(&position, limits.depth, |info| {
+                if post {
+                    println!("{}", format_thinking(&position, info));
+                }
+            });
+            let mv = result?.pv[0].clone();
+
+            // Only send the move if it is still wanted
+            if thread_claimed.swap(true, Ordering::SeqCst) {
+                return None;
+            }
+            println!("move {}", format_move(&position, &mv));
+            let mut next = position.clone();
+            next.make_move(&mv);
+            if let Some(result) = game_result(&next) {
+                println!("{}", result);
+            }
+            Some(mv)
+        });
+
+        self.search = Some(RunningSearch { handle, stop, claimed });
+    }
+
+    // Wait for a running search and play the move it sent, if any
+    fn finish_thinking(&mut self) {
+        if let Some(search) = self.search.take() {
+            if let Some(mv) = search.handle.join().unwrap() {
+                self.play(&mv);
+            }
+        }
+    }
+
+    // Stop a running search and throw its move away if it was not sent yet
+    fn cancel_thinking(&mut self) {
+        if let Some(ref search) = self.search {
+            search.claimed.store(true, Ordering::SeqCst);
+            search.stop.store(true, Ordering::Relaxed);
+        }
+        self.finish_thinking();
+    }
+
+    // Play the opponent's move and reply to it unless in force mode
+    fn user_move(&mut self, text: &str) {
+        // Chess960 castling arrives as O-O or O-O-O
+        match self.position.parse_move(text).or_else(|| self.position.parse_san(text)) {
+            Some(mv) => {
+                self.play(&mv);
+                if !self.force {
+                    self.start_thinking();
+                }
+            },
+            None => println!("Illegal move: {}", text),
+        }
+    }
+
+    // Handle one command from the GUI, returning false on "quit"
+    fn handle(&mut self, command: &str, args: &[&str]) -> bool {
+        // These can be answered while the engine is still thinking
+        match command {
+            "?" => {
+                if let Some(ref search) = self.search {
+                    search.stop.store(true, Ordering::Relaxed);
+                }
+                return true;
+            },
+            "ping" => {
+                println!("pong {}", args.first().unwrap_or(&""));
+                return true;
+            },
+            "post" => {
+                self.post = true;
+                return true;
+            },
+            "nopost" => {
+                self.post = false;
+                return true;
+            },
+            "time" | "otim" => {
+                let centiseconds = args.first().and_then(|arg| arg.parse::<u64>().ok()).unwrap_or(0);
+                let time = Some(Duration::from_millis(centiseconds * 10));
+                if command == "time" {
+                    self.engine_time = time;
+                } else {
+                    self.opponent_time = time;
+                }
+                return true;
+            },
+            // Commands that only matter to other engines
+            // The engine keeps track of the pockets itself, so "holding" is not needed either
+            "xboard" | "accepted" | "rejected" | "hard" | "easy" | "random" | "computer" | "name" | "rating" | "draw" | "holding" => return true,
+            _ => {},
+        }
+
+        // Anything else that changes the game stops the search first
+        match command {
+            "new" | "force" | "result" | "setboard" | "undo" | "remove" | "variant" | "quit" => self.cancel_thinking(),
+            _ => self.finish_thinking(),
+        }
+
+        match command {
+            "protover" => {
+                println!("feature myname=\"ConcurrentChess\" usermove=1 setboard=1 ping=1 san=0 colors=0 sigint=0 sigterm=0");
+                let variants: Vec<&str> = ENGINE_VARIANTS.iter().map(|variant| if *variant == Variant::Standard { "normal" } else { variant.name() }).collect();
+                println!("feature variants=\"{},fischerandom\"", variants.join(","));
+                println!("feature option=\"OwnBook -check 0\" option=\"Book File -file \" option=\"Best Book Move -check 0\" option=\"Endgame Path -path \"");
+                println!("feature egt=\"syzygy\"");
+                println!("feature done=1");
+            },
+            "new" => {
+                self.variant = Variant::Standard;
+                self.chess960 = false;
+                self.position = Position::new();
+                self.history.clear();
+                self.force = false;
+                self.time_control.time_per_move = None;
+                self.time_control.max_depth = None;
+            },
+            "force" => self.force = true,
+            "go" => {
+                self.force = false;
+                self.start_thinking();
+            },
+            "usermove" => self.user_move(args.first().unwrap_or(&"")),
+            "level" => {
+                if args.len() < 3 {
+                    println!("Error (not enough arguments): level");
+                    return true;
+                }
+                self.time_control.moves_per_session = args[0].parse().unwrap_or(0);
+                if let Some(base) = parse_base_time(args[1]) {
+                    self.engine_time = Some(base);
+                    self.opponent_time = Some(base);
+                }
+                let increment: f64 = args[2].parse().unwrap_or(0.0);
+                self.time_control.increment = Duration::from_secs_f64(increment.max(0.0));
+                self.time_control.time_per_move = None;
+            },
+            "st" => {
+                let seconds: f64 = args.first().and_then(|arg| arg.parse().ok()).unwrap_or(0.0);
+                self.time_control.time_per_move = Some(Duration::from_secs_f64(seconds.max(0.0)));
+            },
+            "option" => {
+                let (name, value) = args.join(" ").split_once('=').map(|(name, value)| (name.to_string(), value.to_string())).unwrap_or_default();
+                match name.as_str() {
+                    "OwnBook" => self.book.enabled = value == "1",
+                    "Book File" => {
+                        if let Err(error) = self.book.load(&value) {
+                            println!("tellusererror {}", error);
+                        }
+                    },
+                    "Best Book Move" => {
+                        self.book.selection = if value == "1" { BookSelection::Best } else { BookSelection::WeightedRandom };
+                    },
+                    "Endgame Path" => match load_endgames(&value) {
+                        Ok(tables) => self.endgames = Arc::new(tables),
+                        Err(error) => println!("tellusererror {}", error),
+                    },
+                    _ => println!("Error (unknown option): {}", name),
+                }
+            },
+            // The GUI tells where the tablebases of each kind it knows of are
+            "egtpath" if args.first() == Some(&"syzygy") => match load_syzygy(&args[1..].join(" ")) {
+                Ok(tables) => self.syzygy = Arc::new(tables),
+                Err(error) => println!("tellusererror {}", error),
+            },
+            "sd" => self.time_control.max_depth = args.first().and_then(|arg| arg.parse().ok()),
+            "variant" if args.first() == Some(&"fischerandom") => {
+                // The GUI sends the starting position with "setboard" next
+                self.chess960 = true;
+                self.position.chess960 = true;
+            },
+            "variant" => match args.first().and_then(|name| Variant::from_name(name)) {
+                Some(variant) if variant.is_engine_variant() => {
+                    self.variant = variant;
+                    self.position = Position::with_variant(variant);
+                    self.history.clear();
+                },
+                _ => println!("Error (unsupported variant): {}", args.join(" ")),
+            },
+            "setboard" => match Position::from_fen(&args.join(" "), Some(self.variant).filter(|&variant| variant != Variant::Standard)) {
+                Ok(mut position) => {
+                    position.chess960 |= self.chess960;
+                    self.position = position;
+                    self.history.clear();
+                },
+                Err(error) => println!("tellusererror Illegal position: {}", error),
+            },
+            "undo" => {
+                if let Some(position) = self.history.pop() {
+                    self.position = position;
+                }
+            },
+            "remove" => {
+                for _ in 0..2 {
+                    if let Some(position) = self.history.pop() {
+                        self.position = position;
+                    }
+                }
+            },
+            // The game is over, so wait for "new" before thinking again
+            "result" => self.force = true,
+            "quit" => return false,
+            // Older interfaces send moves without the "usermove" prefix
+            _ if self.position.parse_move(command).is_some() => self.user_move(command),
+            _ => println!("Error (unknown command): {}", command),
+        }
+
+        true
+    }
+}
+
+// Run the engine under the Chess Engine Communication Protocol (xboard/winboard) on stdin and stdout
+pub fn run() {
+    let mut xboard = XBoard::new();
+
+    for line in io::stdin().lock().lines() {
+        let line = match line {
+            Ok(line) => line,
+            Err(_) => break,
+        };
+        let tokens: Vec<&str> = line.split_whitespace().collect();
+        let (command, args) = match tokens.split_first() {
+            Some((command, args)) => (*command, args),
+            None => continue,
+        };
+
+        if !xboard.handle(command, args) {
+            break;
+        }
+    }
+
+    xboard.cancel_thinking();
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn scores_use_cecp_mate_values() {
+        assert_eq!(format_score(35), 35);
+        assert_eq!(format_score(-120), -120);
+        assert_eq!(format_score(MATE - 1), 100001);
+        assert_eq!(format_score(MATE - 5), 100003);
+        assert_eq!(format_score(-MATE + 2), -100001);
+    }
+
+    #[test]
+    fn level_base_times() {
+        assert_eq!(parse_base_time("5"), Some(Duration::from_secs(300)));
+        assert_eq!(parse_base_time("0:30"), Some(Duration::from_secs(30)));
+        assert_eq!(parse_base_time("2:05"), Some(Duration::from_secs(125)));
+        assert_eq!(parse_base_time("x"), None);
+        assert_eq!(parse_base_time("1:"), None);
+    }
+
+    #[test]
+    fn undo_and_remove_restore_positions() {
+        let mut xboard = XBoard::new();
+        xboard.handle("force", &[]);
+        for mv in ["e2e4", "e7e5", "g1f3", "b8c6"] {
+            xboard.handle("usermove", &[mv]);
+        }
+        assert_eq!(xboard.history.len(), 4);
+
+        xboard.handle("undo", &[]);
+        assert_eq!(xboard.position.to_fen(), "rnbqkbnr/pppp1ppp/8/4p3/4P3/5N2/PPPP1PPP/RNBQKB1R b KQkq - 1 2");
+        xboard.handle("remove", &[]);
+        assert_eq!(xboard.position.to_fen(), "rnbqkbnr/pppppppp/8/8/4P3/8/PPPP1PPP/RNBQKBNR b KQkq e3 0 1");
+        assert_eq!(xboard.history.len(), 1);
+
+        // Taking back more moves than were played stops at the start
+        xboard.handle("remove", &[]);
+        xboard.handle("undo", &[]);
+        assert_eq!(xboard.position.to_fen(), Position::new().to_fen());
+        assert!(xboard.history.is_empty());
+
+        xboard.handle("usermove", &["d2d4"]);
+        xboard.handle("setboard", &["8/8/8/4k3/8/8/8/4K3", "w", "-", "-", "0", "1"]);
+        assert!(xboard.history.is_empty());
+    }
+
+    #[test]
+    fn limits_follow_the_time_control() {
+        let mut xboard = XBoard::new();
+
+        // Without a clock the engine thinks for a fixed time
+        assert_eq!(xboard.limits().movetime, Some(Duration::from_secs(5)));
+
+        // 40 moves in 5 minutes with no increment, after 12 moves have been played
+        xboard.handle("level", &["40", "5", "0"]);
+        xboard.handle("setboard", &["4k3/8/8/8/8/8/8/4K2R", "b", "K", "-", "0", "13"]);
+        xboard.handle("time", &["12000"]);
+        xboard.handle("otim", &["9000"]);
+        let limits = xboard.limits();
+        assert_eq!(limits.moves_to_go, Some(28));
+        assert_eq!(limits.black_time, Some(Duration::from_secs(120)));
+        assert_eq!(limits.white_time, Some(Duration::from_secs(90)));
+        assert_eq!(limits.black_increment, Some(Duration::ZERO));
+        assert_eq!(limits.movetime, None);
+
+        // A FEN with a move number of 0 is treated as the first move
+        xboard.handle("setboard", &["4k3/8/8/8/8/8/8/4K2R", "w", "K", "-", "0", "0"]);
+        assert_eq!(xboard.limits().moves_to_go, Some(40));
+
+        // An incremental clock has no session length
+        xboard.handle("level", &["0", "2:30", "1.5"]);
+        xboard.handle("setboard", &["4k3/8/8/8/8/8/8/4K2R", "w", "K", "-", "0", "13"]);
+        let limits = xboard.limits();
+        assert_eq!(limits.moves_to_go, None);
+        assert_eq!(limits.white_time, Some(Duration::from_secs(150)));
+        assert_eq!(limits.white_increment, Some(Duration::from_millis(1500)));
+
+        // A fixed time per move overrides the clock
+        xboard.handle("st", &["3"]);
+        let limits = xboard.limits();
+        assert_eq!(limits.movetime, Some(Duration::from_secs(3)));
+        assert_eq!(limits.white_time, None);
+
+        xboard.handle("sd", &["4"]);
+        assert_eq!(xboard.limits().depth, Some(4));
+        xboard.handle("new", &[]);
+        assert_eq!(xboard.limits().depth, None);
+        assert_eq!(xboard.limits().movetime, None);
+    }
+
+    #[test]
+    fn finished_search_plays_its_move() {
+        let mut xboard = XBoard::new();
+        xboard.handle("sd", &["1"]);
+        xboard.handle("go", &[]);
+        xboard.finish_thinking();
+        assert_eq!(xboard.history.len(), 1);
+        assert_eq!(xboard.position.side_to_move, Color::Black);
+    }
+
+    #[test]
+    fn cancelled_search_move_is_thrown_away() {
+        let mut xboard = XBoard::new();
+        xboard.handle("st", &["60"]);
+        xboard.handle("go", &[]);
+        // Claims the move before the search can, so the search stops without sending it
+        xboard.handle("force", &[]);
+        assert!(xboard.search.is_none());
+        assert!(xboard.history.is_empty());
+        assert_eq!(xboard.position.to_fen(), Position::new().to_fen());
+    }
+}